@@ -0,0 +1,335 @@
+use reqwest::{header, Method, RequestBuilder, Response};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::sync::RwLock;
+use crate::error::{Error, Result};
+use crate::types::*;
+
+/// Path every endpoint of the version this crate speaks is mounted under
+pub const API_PREFIX: &str = "/api/v1";
+
+/// Name of the cookie the server keeps the session token in
+const TOKEN_COOKIE: &str = "user";
+
+/// How the session token is sent back to the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Auth {
+    /// `Cookie: user=<token>`, like a browser
+    Cookie,
+    /// `Authorization: Bearer <token>`
+    Bearer,
+}
+
+/// `data` of a paginated list with its position
+#[derive(Debug)]
+pub struct Paginated<T> {
+    pub list: Vec<T>,
+    pub count: i64,
+    pub pagination: Pagination,
+}
+
+/// `data` of a response carrying the `ETag` of the user it describes, send it
+/// back as `if_match` to only update that version
+#[derive(Debug)]
+pub struct Versioned<T> {
+    pub etag: Option<String>,
+    pub data: T,
+}
+
+/// Async client of every endpoint, `login` keeps the session token for the calls after it
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    auth: Auth,
+    token: RwLock<Option<String>>,
+}
+
+impl Client {
+  /// `base_url` is where the server listens, e.g. `http://localhost:3000`
+  pub fn new(base_url: impl Into<String>) -> Self {
+    Client::with_http_client(reqwest::Client::new(), base_url)
+  }
+
+  pub fn with_http_client(http: reqwest::Client, base_url: impl Into<String>) -> Self {
+    Client {
+      http,
+      base_url: base_url.into().trim_end_matches('/').to_string(),
+      auth: Auth::Cookie,
+      token: RwLock::new(None),
+    }
+  }
+
+  pub fn auth(mut self, auth: Auth) -> Self {
+    self.auth = auth;
+    self
+  }
+
+  /// Token of the current session, `None` before `login`
+  pub fn token(&self) -> Option<String> {
+    self.token.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+  }
+
+  /// Reuse a session, e.g. one kept from an earlier run
+  pub fn set_token(&self, token: Option<String>) {
+    *self.token.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = token;
+  }
+
+  fn request(&self, method: Method, path: &str) -> RequestBuilder {
+    let builder = self.http.request(method, format!("{}{}{}", self.base_url, API_PREFIX, path));
+    match (self.token(), self.auth) {
+      (Some(token), Auth::Cookie) => builder.header(header::COOKIE, format!("{}={}", TOKEN_COOKIE, token)),
+      (Some(token), Auth::Bearer) => builder.bearer_auth(token),
+      (None, _) => builder,
+    }
+  }
+
+  /// Send a request, error envelopes become `Error::Api`
+  async fn send(&self, builder: RequestBuilder) -> Result<Response> {
+    let response = builder.send().await?;
+    if response.status().is_success() {
+      return Ok(response)
+    }
+    Err(api_error(response).await)
+  }
+
+  async fn envelope<T: DeserializeOwned>(&self, builder: RequestBuilder) -> Result<ApiResponse<T>> {
+    let response = self.send(builder).await?;
+    let status = response.status().as_u16();
+    let body = response.bytes().await?;
+    serde_json::from_slice(&body).map_err(|e| Error::UnexpectedResponse { status, reason: e.to_string() })
+  }
+
+  async fn data<T: DeserializeOwned>(&self, builder: RequestBuilder) -> Result<T> {
+    Ok(self.envelope(builder).await?.data)
+  }
+
+  async fn page<T: DeserializeOwned>(&self, builder: RequestBuilder) -> Result<Paginated<T>> {
+    let envelope = self.envelope::<Page<T>>(builder).await?;
+    let pagination = envelope.pagination.ok_or_else(|| Error::UnexpectedResponse {
+      status: 200,
+      reason: "pagination is missing".to_string(),
+    })?;
+    Ok(Paginated { list: envelope.data.list, count: envelope.data.count, pagination })
+  }
+
+  async fn versioned<T: DeserializeOwned>(&self, builder: RequestBuilder) -> Result<Versioned<T>> {
+    let response = self.send(builder).await?;
+    let status = response.status().as_u16();
+    let etag = response
+        .headers()
+        .get(header::ETAG)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+    let body = response.bytes().await?;
+    let envelope: ApiResponse<T> = serde_json::from_slice(&body)
+        .map_err(|e| Error::UnexpectedResponse { status, reason: e.to_string() })?;
+    Ok(Versioned { etag, data: envelope.data })
+  }
+
+  async fn bytes(&self, builder: RequestBuilder) -> Result<Vec<u8>> {
+    Ok(self.send(builder).await?.bytes().await?.to_vec())
+  }
+
+  /// Log in and keep the session token for the following calls
+  pub async fn login(&self, body: &LoginRequestBody) -> Result<UserSelf> {
+    let response = self.send(self.request(Method::POST, "/login").json(body)).await?;
+    let status = response.status().as_u16();
+    let token = response
+        .headers()
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .find_map(session_token)
+        .ok_or_else(|| Error::UnexpectedResponse { status, reason: "no session cookie".to_string() })?;
+    let body = response.bytes().await?;
+    let envelope: ApiResponse<UserSelf> = serde_json::from_slice(&body)
+        .map_err(|e| Error::UnexpectedResponse { status, reason: e.to_string() })?;
+
+    self.set_token(Some(token));
+    Ok(envelope.data)
+  }
+
+  /// Forget the session token, the server keeps no session to end
+  pub fn logout(&self) {
+    self.set_token(None);
+  }
+
+  /// Register a user, returns its id
+  pub async fn register(&self, body: &RegisterRequestBody) -> Result<String> {
+    self.data(self.request(Method::POST, "/register").json(body)).await
+  }
+
+  pub async fn register_availability(&self, name: &str) -> Result<NameAvailability> {
+    let query = RegisterAvailabilityQuery { name: name.to_string() };
+    self.data(self.request(Method::GET, "/register/availability").query(&query)).await
+  }
+
+  pub async fn get_users(&self, query: &GetUsersAPIQuery) -> Result<Paginated<UserView>> {
+    self.page(self.request(Method::GET, "/users").query(query)).await
+  }
+
+  pub async fn get_user(&self, user_id: &str, query: &GetUserQuery) -> Result<Versioned<UserView>> {
+    self.versioned(self.request(Method::GET, &format!("/users/{}", user_id)).query(query)).await
+  }
+
+  pub async fn patch_user(&self, user_id: &str, body: &PatchUserBody, if_match: Option<&str>) -> Result<Versioned<UserView>> {
+    let builder = self
+        .request(Method::PATCH, &format!("/users/{}", user_id))
+        .header(header::CONTENT_TYPE, "application/merge-patch+json")
+        .body(serde_json::to_vec(body).expect("PatchUserBody serializes"));
+    self.versioned(with_if_match(builder, if_match)).await
+  }
+
+  /// Delete a user, returns its id
+  pub async fn delete_user(&self, user_id: &str, if_match: Option<&str>) -> Result<String> {
+    let builder = self.request(Method::DELETE, &format!("/users/{}", user_id));
+    self.data(with_if_match(builder, if_match)).await
+  }
+
+  #[deprecated(note = "use `patch_user`")]
+  pub async fn update_user_password(&self, user_id: &str, body: &UpdateUserPasswordBody, if_match: Option<&str>) -> Result<Versioned<String>> {
+    let builder = self.request(Method::POST, &format!("/users/{}/update_password", user_id)).json(body);
+    self.versioned(with_if_match(builder, if_match)).await
+  }
+
+  #[deprecated(note = "use `patch_user`")]
+  pub async fn update_user_status(&self, user_id: &str, body: &UpdateUserStatusBody, if_match: Option<&str>) -> Result<Versioned<String>> {
+    let builder = self.request(Method::POST, &format!("/users/{}/update_status", user_id)).json(body);
+    self.versioned(with_if_match(builder, if_match)).await
+  }
+
+  pub async fn bulk_users(&self, body: &BulkUsersBody) -> Result<BulkUsersData> {
+    self.data(self.request(Method::POST, "/users/bulk").json(body)).await
+  }
+
+  /// Import users from a CSV or NDJSON file
+  pub async fn import_users(&self, query: &ImportUsersQuery, file: impl Into<reqwest::Body>) -> Result<ImportUsersData> {
+    let builder = self
+        .request(Method::POST, "/users/import")
+        .query(query)
+        .header(header::CONTENT_TYPE, query.format.content_type())
+        .body(file);
+    self.data(builder).await
+  }
+
+  /// Export users, returns the file in the asked format
+  pub async fn export_users(&self, query: &ExportUsersQuery) -> Result<Vec<u8>> {
+    self.bytes(self.request(Method::GET, "/users/export").query(query)).await
+  }
+
+  pub async fn get_audit_events(&self, query: &GetAuditEventsQuery) -> Result<Paginated<AuditEventView>> {
+    self.page(self.request(Method::GET, "/audit").query(query)).await
+  }
+
+  pub async fn get_groups(&self, query: &GetGroupsQuery) -> Result<Paginated<GroupView>> {
+    self.page(self.request(Method::GET, "/groups").query(query)).await
+  }
+
+  pub async fn create_group(&self, body: &GroupBody) -> Result<GroupView> {
+    self.data(self.request(Method::POST, "/groups").json(body)).await
+  }
+
+  pub async fn get_group(&self, group_id: &str) -> Result<GroupDetail> {
+    self.data(self.request(Method::GET, &format!("/groups/{}", group_id))).await
+  }
+
+  pub async fn update_group(&self, group_id: &str, body: &GroupBody) -> Result<GroupView> {
+    self.data(self.request(Method::PUT, &format!("/groups/{}", group_id)).json(body)).await
+  }
+
+  /// Delete a group, returns its id
+  pub async fn delete_group(&self, group_id: &str) -> Result<String> {
+    self.data(self.request(Method::DELETE, &format!("/groups/{}", group_id))).await
+  }
+
+  pub async fn add_group_member(&self, group_id: &str, user_id: &str) -> Result<String> {
+    self.data(self.request(Method::PUT, &format!("/groups/{}/members/{}", group_id, user_id))).await
+  }
+
+  pub async fn remove_group_member(&self, group_id: &str, user_id: &str) -> Result<String> {
+    self.data(self.request(Method::DELETE, &format!("/groups/{}/members/{}", group_id, user_id))).await
+  }
+
+  pub async fn add_group_grant(&self, group_id: &str, grant: Grant) -> Result<String> {
+    self.data(self.request(Method::PUT, &format!("/groups/{}/grants/{}", group_id, grant))).await
+  }
+
+  pub async fn revoke_group_grant(&self, group_id: &str, grant: Grant) -> Result<String> {
+    self.data(self.request(Method::DELETE, &format!("/groups/{}/grants/{}", group_id, grant))).await
+  }
+
+  pub async fn add_user_grant(&self, user_id: &str, grant: Grant) -> Result<String> {
+    self.data(self.request(Method::PUT, &format!("/users/{}/grants/{}", user_id, grant))).await
+  }
+
+  pub async fn revoke_user_grant(&self, user_id: &str, grant: Grant) -> Result<String> {
+    self.data(self.request(Method::DELETE, &format!("/users/{}/grants/{}", user_id, grant))).await
+  }
+
+  /// Effective permissions of a user, from its role, direct grants and groups
+  pub async fn get_user_permissions(&self, user_id: &str) -> Result<Vec<Permission>> {
+    self.data(self.request(Method::GET, &format!("/users/{}/permissions", user_id))).await
+  }
+
+  /// Everything stored about a user, as a zip archive
+  pub async fn export_user_data(&self, user_id: &str) -> Result<Vec<u8>> {
+    self.bytes(self.request(Method::GET, &format!("/users/{}/data-export", user_id))).await
+  }
+
+  pub async fn erase_user(&self, user_id: &str) -> Result<ErasureReceipt> {
+    self.data(self.request(Method::POST, &format!("/users/{}/erase", user_id))).await
+  }
+
+  pub async fn get_user_logins(&self, user_id: &str, query: &GetUserLoginsQuery) -> Result<Paginated<LoginAttemptView>> {
+    self.page(self.request(Method::GET, &format!("/users/{}/logins", user_id)).query(query)).await
+  }
+
+  pub async fn get_admin_stats(&self, query: &GetAdminStatsQuery) -> Result<AdminStats> {
+    self.data(self.request(Method::GET, "/admin/stats").query(query)).await
+  }
+
+  /// Schedule the deletion of the logged in account, the server ends the session
+  pub async fn request_account_deletion(&self, body: &DeletionRequestBody) -> Result<DeletionRequestData> {
+    let data = self.data(self.request(Method::POST, "/users/me/delete-request").json(body)).await?;
+    self.set_token(None);
+    Ok(data)
+  }
+}
+
+fn with_if_match(builder: RequestBuilder, if_match: Option<&str>) -> RequestBuilder {
+  match if_match {
+    Some(etag) => builder.header(header::IF_MATCH, etag),
+    None => builder,
+  }
+}
+
+/// Value of the session cookie in a `Set-Cookie` header
+fn session_token(set_cookie: &str) -> Option<String> {
+  let (name, value) = set_cookie.split(';').next()?.split_once('=')?;
+  if name.trim() != TOKEN_COOKIE || value.is_empty() {
+    return None
+  }
+  Some(value.trim().to_string())
+}
+
+/// Map an error envelope back to its code, `data` holds the broken rules of validation errors
+async fn api_error(response: Response) -> Error {
+  let status = response.status().as_u16();
+  let body = match response.bytes().await {
+    Ok(body) => body,
+    Err(e) => return Error::Http(e),
+  };
+
+  match serde_json::from_slice::<ApiResponse<Value>>(&body) {
+    Ok(envelope) => Error::Api {
+      status,
+      code: ErrorCode::parse(&envelope.code),
+      message: envelope.message,
+      errors: serde_json::from_value(envelope.data).unwrap_or_default(),
+    },
+    Err(_) => Error::UnexpectedResponse {
+      status,
+      reason: String::from_utf8_lossy(&body).into_owned(),
+    },
+  }
+}
@@ -0,0 +1,43 @@
+use thiserror::Error;
+use crate::types::{ErrorCode, FieldError};
+
+/// What a call can fail with, API errors keep the server's envelope
+#[derive(Error, Debug)]
+pub enum Error {
+    /// The server answered with an error envelope
+    #[error("{status} {code}: {message}")]
+    Api {
+        status: u16,
+        code: ErrorCode,
+        /// Already translated by the server
+        message: String,
+        /// Broken rules, only on `request.validation_failed`
+        errors: Vec<FieldError>,
+    },
+    #[cfg(feature = "http")]
+    #[error("HTTP Error: {0}")]
+    Http(#[from] reqwest::Error),
+    /// The body is not what the endpoint documents
+    #[error("Unexpected Response ({status}): {reason}")]
+    UnexpectedResponse { status: u16, reason: String },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+  /// Code of an API error, `None` when the server could not be understood
+  pub fn code(&self) -> Option<&ErrorCode> {
+    match self {
+      Error::Api { code, .. } => Some(code),
+      _ => None,
+    }
+  }
+
+  pub fn status(&self) -> Option<u16> {
+    match self {
+      Error::Api { status, .. } | Error::UnexpectedResponse { status, .. } => Some(*status),
+      #[cfg(feature = "http")]
+      Error::Http(error) => error.status().map(|status| status.as_u16()),
+    }
+  }
+}
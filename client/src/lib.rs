@@ -0,0 +1,21 @@
+//! Typed client of the rust_learn API
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), rust_learn_client::Error> {
+//! use rust_learn_client::{Client, types::{GetUsersAPIQuery, LoginRequestBody}};
+//!
+//! let client = Client::new("http://localhost:3000");
+//! client.login(&LoginRequestBody { name: "alice".into(), password: "secret".into() }).await?;
+//! let users = client.get_users(&GetUsersAPIQuery::default()).await?;
+//! # Ok(())
+//! # }
+//! ```
+pub mod types;
+
+mod error;
+pub use error::{Error, Result};
+
+#[cfg(feature = "http")]
+mod client;
+#[cfg(feature = "http")]
+pub use client::{Auth, Client, Paginated, Versioned, API_PREFIX};
@@ -0,0 +1,147 @@
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use crate::types::UserStatus;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "server", derive(utoipa::IntoParams))]
+pub struct GetAuditEventsQuery {
+    pub page: Option<i32>,
+    pub page_size: Option<i32>,
+    pub actor_id: Option<String>,
+    pub target_id: Option<String>,
+    pub action: Option<String>,
+    /// RFC 3339 timestamp, inclusive
+    pub from: Option<DateTime<FixedOffset>>,
+    /// RFC 3339 timestamp, exclusive
+    pub to: Option<DateTime<FixedOffset>>,
+}
+
+/// An audit entry as shown to clients, same keys as the stored row
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEventView {
+    pub id: String,
+    pub actor_id: Option<String>,
+    pub target_id: Option<String>,
+    /// e.g. `user.update` or `permission.grant`
+    pub action: String,
+    /// Before and after values, depends on the action
+    #[cfg_attr(feature = "server", schema(value_type = Option<Object>))]
+    pub diff: Option<Value>,
+    pub ip: Option<String>,
+    pub request_id: Option<String>,
+    pub created_at: DateTime<FixedOffset>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "server", derive(utoipa::IntoParams))]
+pub struct GetUserLoginsQuery {
+    pub page: Option<i32>,
+    pub page_size: Option<i32>,
+    pub success: Option<bool>,
+    /// Failure reason, e.g. `wrong_password` or `account_suspended`
+    pub reason: Option<String>,
+    pub ip: Option<String>,
+    /// RFC 3339 timestamp, inclusive
+    pub from: Option<DateTime<FixedOffset>>,
+    /// RFC 3339 timestamp, exclusive
+    pub to: Option<DateTime<FixedOffset>>,
+}
+
+/// A login attempt as shown to clients, same keys as the stored row
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct LoginAttemptView {
+    pub id: String,
+    pub user_id: Option<String>,
+    /// Name as typed at login
+    pub name: String,
+    pub success: bool,
+    /// Why the attempt failed, e.g. `wrong_password`
+    pub reason: Option<String>,
+    pub method: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<FixedOffset>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum StatsInterval {
+    Day,
+    /// ISO weeks, e.g. `2023-W07`
+    Week,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "server", derive(utoipa::IntoParams))]
+pub struct GetAdminStatsQuery {
+    /// First day of the registrations series, defaults to 30 days ago
+    pub from: Option<NaiveDate>,
+    /// Last day of the registrations series, inclusive, defaults to today
+    pub to: Option<NaiveDate>,
+    pub interval: Option<StatsInterval>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct StatusCount {
+    pub status: UserStatus,
+    pub count: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct RegistrationBucket {
+    pub bucket: String,
+    pub count: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct Registrations {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub interval: StatsInterval,
+    /// Buckets without registrations are left out
+    pub buckets: Vec<RegistrationBucket>,
+}
+
+/// Distinct users with a successful login in the last day, 7 days and 30 days
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct ActiveUsers {
+    pub day: i64,
+    pub week: i64,
+    pub month: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct AdminStats {
+    pub generated_at: DateTime<Utc>,
+    pub total: i64,
+    pub by_status: Vec<StatusCount>,
+    /// Suspended and banned accounts
+    pub locked: i64,
+    pub registrations: Registrations,
+    pub active_users: ActiveUsers,
+}
+
+/// Proof of erasure, also stored as the diff of the `user.erase` audit entry
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct ErasureReceipt {
+    pub user_id: String,
+    pub erased_at: DateTime<FixedOffset>,
+    /// Audit entry recording the erasure
+    pub audit_event_id: String,
+    pub grants_removed: i64,
+    pub memberships_removed: i64,
+    pub audit_events_scrubbed: i64,
+    pub logins_scrubbed: i64,
+}
@@ -0,0 +1,56 @@
+use chrono::{DateTime, FixedOffset};
+use serde::{Serialize, Deserialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct LoginRequestBody {
+    /// Bounded by the `User.name` column
+    #[cfg_attr(feature = "server", schema(min_length = 1, max_length = 50))]
+    pub name: String,
+    /// Looser than at registration, so passwords set before the rules existed still work
+    #[cfg_attr(feature = "server", schema(min_length = 1, max_length = 128))]
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct RegisterRequestBody {
+    /// See the naming rules, checked after these bounds
+    #[cfg_attr(feature = "server", schema(min_length = 3, max_length = 32))]
+    pub name: String,
+    /// At least 8 characters and at most 72 bytes, bcrypt ignores the rest
+    #[cfg_attr(feature = "server", schema(min_length = 8, max_length = 72))]
+    pub password: String,
+    #[cfg_attr(feature = "server", schema(min_length = 8, max_length = 72))]
+    pub password_confirm: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(utoipa::IntoParams))]
+pub struct RegisterAvailabilityQuery {
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct NameAvailability {
+    pub name: String,
+    pub available: bool,
+    /// Why the name can't be used, when it can't
+    pub reason: Option<String>,
+    pub suggestions: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct DeletionRequestBody {
+    /// Current password, deleting an account needs a fresh proof of identity
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct DeletionRequestData {
+    /// When the account will be erased unless the user logs in before
+    pub scheduled_for: DateTime<FixedOffset>,
+}
@@ -0,0 +1,50 @@
+use serde::{Serialize, Deserialize};
+#[cfg(feature = "server")]
+use crate::types::{AuditEventView, GroupView, LoginAttemptView, UserView};
+
+/// Envelope of every JSON response, errors use the same shape
+///
+/// The server keeps its own copy, it is what answers its requests.
+#[derive(Deserialize, Debug)]
+pub struct ApiResponse<T> {
+    /// `200` on success, a stable error code such as `auth.invalid_credentials` otherwise
+    pub code: String,
+    pub message: String,
+    pub data: T,
+    /// Only set on paginated lists
+    pub pagination: Option<Pagination>,
+}
+
+/// Position of a page in a list
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct Pagination {
+    pub page: i32,
+    pub page_size: i32,
+    pub total_pages: i64,
+}
+
+/// One page of a list, `count` is the number of items on all pages
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "server", aliases(
+  UserViewPage = Page<UserView>,
+  AuditEventPage = Page<AuditEventView>,
+  GroupPage = Page<GroupView>,
+  LoginAttemptPage = Page<LoginAttemptView>,
+))]
+pub struct Page<T> {
+    pub list: Vec<T>,
+    pub count: i64,
+}
+
+/// One rule a request field broke, the `data` of `request.validation_failed` errors
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct FieldError {
+    /// Path of the field, nested fields are joined with `.`
+    pub field: String,
+    /// Name of the rule, e.g. `length` or `must_match`
+    pub code: String,
+    pub message: String,
+}
@@ -0,0 +1,93 @@
+use serde::{Serialize, Deserialize, Deserializer, Serializer};
+use std::fmt;
+
+macro_rules! error_codes {
+  ($($variant:ident => $code:literal,)*) => {
+    /// Stable code of an API error, sent as `code` in the envelope and in problem details
+    ///
+    /// Codes added by a newer server than this crate come back as `Other`.
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    pub enum ErrorCode {
+        $($variant,)*
+        Other(String),
+    }
+
+    impl ErrorCode {
+      /// Every code the server sends
+      pub const ALL: &'static [ErrorCode] = &[$(ErrorCode::$variant,)*];
+
+      pub fn as_str(&self) -> &str {
+        match self {
+          $(ErrorCode::$variant => $code,)*
+          ErrorCode::Other(code) => code,
+        }
+      }
+
+      pub fn parse(code: &str) -> ErrorCode {
+        match code {
+          $($code => ErrorCode::$variant,)*
+          other => ErrorCode::Other(other.to_string()),
+        }
+      }
+    }
+  };
+}
+
+error_codes! {
+  ResourceNotFound => "resource.not_found",
+  ResourceExists => "resource.exists",
+  ResourceReferenceConflict => "resource.reference_conflict",
+  AuthInvalidCredentials => "auth.invalid_credentials",
+  AuthInvalidToken => "auth.invalid_token",
+  AuthAccountNotActive => "auth.account_not_active",
+  AuthPermissionDenied => "auth.permission_denied",
+  AuthFieldNotPermitted => "auth.field_not_permitted",
+  UserPasswordMismatch => "user.password_mismatch",
+  UserOperationOnSelf => "user.operation_on_self",
+  UserInvalidStatusTransition => "user.invalid_status_transition",
+  UserAlreadyErased => "user.already_erased",
+  UserInvalidName => "user.invalid_name",
+  UserNameConfusable => "user.name_confusable",
+  BulkTargetMissing => "bulk.target_missing",
+  BulkLimitExceeded => "bulk.limit_exceeded",
+  ImportInvalidFile => "import.invalid_file",
+  ExportFailed => "export.failed",
+  PermissionUnknownGrant => "permission.unknown_grant",
+  StatsInvalidRange => "stats.invalid_range",
+  RequestInvalidFields => "request.invalid_fields",
+  RequestPreconditionFailed => "request.precondition_failed",
+  RequestInvalidPatch => "request.invalid_patch",
+  RequestValidationFailed => "request.validation_failed",
+  RequestMalformedJson => "request.malformed_json",
+  RequestInvalidJson => "request.invalid_json",
+  RequestUnsupportedMediaType => "request.unsupported_media_type",
+  RequestInvalidPath => "request.invalid_path",
+  RequestInvalidQuery => "request.invalid_query",
+  RequestValueTooLong => "request.value_too_long",
+  RequestRejected => "request.rejected",
+  RequestUnsupportedVersion => "request.unsupported_version",
+  RequestRateLimited => "request.rate_limited",
+  ServiceDatabaseUnavailable => "service.database_unavailable",
+  InternalDatabase => "internal.database",
+  InternalUnknownUserStatus => "internal.unknown_user_status",
+  InternalError => "internal.error",
+}
+
+impl fmt::Display for ErrorCode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
+impl Serialize for ErrorCode {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(self.as_str())
+  }
+}
+
+impl<'de> Deserialize<'de> for ErrorCode {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    let code = String::deserialize(deserializer)?;
+    Ok(ErrorCode::parse(&code))
+  }
+}
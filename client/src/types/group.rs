@@ -0,0 +1,60 @@
+use chrono::{DateTime, FixedOffset};
+use serde::{Serialize, Deserialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "server", derive(utoipa::IntoParams))]
+pub struct GetGroupsQuery {
+    pub page: Option<i32>,
+    pub page_size: Option<i32>,
+}
+
+/// A group as shown to clients, same keys as the stored row
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct GroupView {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub created_at: DateTime<FixedOffset>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct GroupMemberView {
+    pub group_id: String,
+    pub user_id: String,
+    /// When the user joined
+    pub created_at: DateTime<FixedOffset>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct GroupGrantView {
+    pub group_id: String,
+    /// `role:<role>` or a permission name
+    pub permission: String,
+    pub created_at: DateTime<FixedOffset>,
+}
+
+/// A group with its members and grants
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct GroupDetail {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub created_at: DateTime<FixedOffset>,
+    pub members: Vec<GroupMemberView>,
+    pub grants: Vec<GroupGrantView>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct GroupBody {
+    pub name: String,
+    pub description: Option<String>,
+}
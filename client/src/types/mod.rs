@@ -0,0 +1,21 @@
+/*
+  Request and response bodies of the API. The server is built from the same
+  definitions, with the `server` feature adding their OpenAPI schemas, so both
+  sides can't drift apart. What the server allows, validation rules, status
+  transitions and what a role grants, stays on the server.
+*/
+mod admin;
+mod auth;
+mod envelope;
+mod error_code;
+mod group;
+mod models;
+mod user;
+
+pub use admin::*;
+pub use auth::*;
+pub use envelope::*;
+pub use error_code::ErrorCode;
+pub use group::*;
+pub use models::*;
+pub use user::*;
@@ -0,0 +1,183 @@
+use serde::{Serialize, Deserialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Lifecycle of a user account. Which changes are allowed and how it is stored
+/// is up to the server
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum UserStatus {
+    Active,
+    Pending,
+    Suspended,
+    Banned,
+    Deactivated,
+    /// The user asked for deletion, logging in during the grace period cancels it
+    #[serde(rename = "pending_deletion")]
+    PendingDeletion,
+}
+
+impl UserStatus {
+  pub const ALL: [UserStatus; 6] = [
+    UserStatus::Active,
+    UserStatus::Pending,
+    UserStatus::Suspended,
+    UserStatus::Banned,
+    UserStatus::Deactivated,
+    UserStatus::PendingDeletion,
+  ];
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      UserStatus::Active => "active",
+      UserStatus::Pending => "pending",
+      UserStatus::Suspended => "suspended",
+      UserStatus::Banned => "banned",
+      UserStatus::Deactivated => "deactivated",
+      UserStatus::PendingDeletion => "pending_deletion",
+    }
+  }
+}
+
+impl fmt::Display for UserStatus {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
+/// Role of a user account, what it allows is up to the server
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Admin,
+}
+
+impl Role {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Role::User => "user",
+      Role::Admin => "admin",
+    }
+  }
+}
+
+impl fmt::Display for Role {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
+impl FromStr for Role {
+  type Err = ();
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "user" => Ok(Role::User),
+      "admin" => Ok(Role::Admin),
+      _ => Err(()),
+    }
+  }
+}
+
+/// Fine grained permission, granted through a role or directly
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub enum Permission {
+    /// See the admin view of every user
+    #[serde(rename = "users.read_private")]
+    UsersReadPrivate,
+    /// Change the status of other users
+    #[serde(rename = "users.manage")]
+    UsersManage,
+    #[serde(rename = "users.bulk")]
+    UsersBulk,
+    #[serde(rename = "users.import")]
+    UsersImport,
+    #[serde(rename = "users.export")]
+    UsersExport,
+    #[serde(rename = "audit.read")]
+    AuditRead,
+    /// Create groups, manage their members and any grants
+    #[serde(rename = "groups.manage")]
+    GroupsManage,
+    /// Export and erase the personal data of any user
+    #[serde(rename = "users.privacy")]
+    UsersPrivacy,
+    #[serde(rename = "stats.read")]
+    StatsRead,
+}
+
+impl Permission {
+  pub const ALL: [Permission; 9] = [
+    Permission::UsersReadPrivate,
+    Permission::UsersManage,
+    Permission::UsersBulk,
+    Permission::UsersImport,
+    Permission::UsersExport,
+    Permission::AuditRead,
+    Permission::GroupsManage,
+    Permission::UsersPrivacy,
+    Permission::StatsRead,
+  ];
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Permission::UsersReadPrivate => "users.read_private",
+      Permission::UsersManage => "users.manage",
+      Permission::UsersBulk => "users.bulk",
+      Permission::UsersImport => "users.import",
+      Permission::UsersExport => "users.export",
+      Permission::AuditRead => "audit.read",
+      Permission::GroupsManage => "groups.manage",
+      Permission::UsersPrivacy => "users.privacy",
+      Permission::StatsRead => "stats.read",
+    }
+  }
+}
+
+impl fmt::Display for Permission {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
+impl FromStr for Permission {
+  type Err = ();
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    Permission::ALL
+      .into_iter()
+      .find(|permission| permission.as_str() == value)
+      .ok_or(())
+  }
+}
+
+/// What can be granted to a user or a group, stored as `role:<role>` or `<permission>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Grant {
+    Role(Role),
+    Permission(Permission),
+}
+
+impl fmt::Display for Grant {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Grant::Role(role) => write!(f, "role:{}", role),
+      Grant::Permission(permission) => write!(f, "{}", permission),
+    }
+  }
+}
+
+impl FromStr for Grant {
+  type Err = ();
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value.strip_prefix("role:") {
+      Some(role) => Role::from_str(role).map(Grant::Role),
+      None => Permission::from_str(value).map(Grant::Permission),
+    }
+  }
+}
@@ -0,0 +1,249 @@
+use serde::{Serialize, Deserialize};
+use crate::types::{Role, UserStatus};
+
+/// What anybody logged in may see about a user
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct UserPublic {
+    pub id: String,
+    pub name: String,
+}
+
+/// What users see about themselves
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct UserSelf {
+    pub id: String,
+    pub name: String,
+    pub status: UserStatus,
+}
+
+/// What admins see about any user
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct UserAdmin {
+    pub id: String,
+    pub name: String,
+    pub status: UserStatus,
+    pub role: Role,
+}
+
+/// Subset of a view picked with `fields=`, fields that were not asked for are left out
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct UserPartial {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<UserStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<Role>,
+}
+
+/// Views are told apart by their fields, the widest one that matches wins
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(untagged)]
+pub enum UserView {
+    Admin(UserAdmin),
+    Owner(UserSelf),
+    Public(UserPublic),
+    Partial(UserPartial),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "server", derive(utoipa::IntoParams))]
+pub struct GetUsersAPIQuery {
+    pub page: Option<i32>,
+    pub page_size: Option<i32>,
+    pub status: Option<UserStatus>,
+    /// Comma separated fields to return, e.g. `id,name`
+    pub fields: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "server", derive(utoipa::IntoParams))]
+pub struct GetUserQuery {
+    /// Comma separated fields to return, e.g. `id,name`
+    pub fields: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct UpdateUserPasswordBody {
+    /// At least 8 characters and at most 72 bytes, bcrypt ignores the rest
+    #[cfg_attr(feature = "server", schema(min_length = 8, max_length = 72))]
+    pub password: String,
+    #[cfg_attr(feature = "server", schema(min_length = 8, max_length = 72))]
+    pub password_confirm: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct UpdateUserStatusBody {
+    pub status: UserStatus,
+}
+
+/// RFC 7396 merge patch of a user, fields left out are not changed
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct PatchUserBody {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<UserStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<Role>,
+}
+
+/// Operation applied to every targeted user
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BulkOperation {
+    UpdateStatus { status: UserStatus },
+    Delete,
+    ResetPassword { password: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct BulkUsersFilter {
+    pub status: Option<UserStatus>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct BulkUsersBody {
+    pub operation: BulkOperation,
+    pub ids: Option<Vec<String>>,
+    pub filter: Option<BulkUsersFilter>,
+    /// Apply items one by one instead of in a single transaction
+    #[serde(default)]
+    pub best_effort: bool,
+    /// Only report what would change
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum BulkItemState {
+    WouldApply,
+    Applied,
+    Failed,
+    RolledBack,
+    NotAttempted,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct BulkItemResult {
+    pub user_id: String,
+    pub state: BulkItemState,
+    pub before_status: Option<UserStatus>,
+    pub after_status: Option<UserStatus>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct BulkUsersData {
+    pub dry_run: bool,
+    pub best_effort: bool,
+    pub committed: bool,
+    pub results: Vec<BulkItemResult>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    Csv,
+    Ndjson,
+}
+
+impl ImportFormat {
+  pub fn content_type(&self) -> &'static str {
+    match self {
+      ImportFormat::Csv => "text/csv; charset=utf-8",
+      ImportFormat::Ndjson => "application/x-ndjson",
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    /// Rows whose name already exists are reported as errors
+    Create,
+    /// Rows whose name already exists update that user
+    Upsert,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(utoipa::IntoParams))]
+pub struct ImportUsersQuery {
+    pub format: ImportFormat,
+    pub mode: Option<ImportMode>,
+    pub dry_run: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct ImportRowError {
+    pub line: u64,
+    pub name: Option<String>,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct ImportUsersData {
+    pub dry_run: bool,
+    pub total: u64,
+    pub created: u64,
+    pub updated: u64,
+    pub failed: u64,
+    pub errors: Vec<ImportRowError>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Xlsx,
+    Ndjson,
+}
+
+impl ExportFormat {
+  pub fn content_type(&self) -> &'static str {
+    match self {
+      ExportFormat::Csv => "text/csv; charset=utf-8",
+      ExportFormat::Xlsx => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+      ExportFormat::Ndjson => "application/x-ndjson",
+    }
+  }
+
+  pub fn extension(&self) -> &'static str {
+    match self {
+      ExportFormat::Csv => "csv",
+      ExportFormat::Xlsx => "xlsx",
+      ExportFormat::Ndjson => "ndjson",
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "server", derive(utoipa::IntoParams))]
+pub struct ExportUsersQuery {
+    pub format: ExportFormat,
+    pub status: Option<UserStatus>,
+}
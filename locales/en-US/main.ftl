@@ -0,0 +1,64 @@
+# Success messages, the keys are passed to `ApiResponse::new`
+ok = OK
+login-success = Login Success
+deletion-scheduled = Deletion Scheduled
+user-data-erased = User Data Erased
+user-deleted = User Deleted
+group-created = Group Created
+group-updated = Group Updated
+group-deleted = Group Deleted
+member-added = Member Added
+member-removed = Member Removed
+grant-added = Grant Added
+grant-revoked = Grant Revoked
+bulk-dry-run = Dry Run
+bulk-not-committed = Not Committed
+
+# Error messages, one per `AppError::code` with `.` written as `-`
+error-resource-not_found = Record not found
+error-resource-exists = Record existed
+error-resource-reference_conflict = Record is referenced by or references a missing record
+error-auth-invalid_credentials = Username/password incorrect
+error-auth-invalid_token = Login Error
+error-auth-account_not_active = { $status ->
+    [pending] Account is pending activation
+    [suspended] Account is suspended
+    [banned] Account is banned
+    [deactivated] Account is deactivated
+    [pending_deletion] Account is scheduled for deletion
+   *[other] Account is not active
+}
+error-auth-permission_denied = Permission denied
+error-auth-field_not_permitted = Not allowed to change { $field }
+error-user-password_mismatch = Passwords don't match
+error-user-operation_on_self = Operation Conflict
+error-user-invalid_status_transition = Cannot change status from { $from } to { $to }, allowed: { $allowed }
+error-user-already_erased = User data was already erased
+error-user-invalid_name = Name { $reason }
+error-user-name_confusable = Name looks too much like an existing one
+error-bulk-target_missing = Either ids or filter is required
+error-bulk-limit_exceeded = Too many users in one bulk request
+error-import-invalid_file = Import file could not be read
+error-export-failed = Export failed
+error-permission-unknown_grant = Unknown grant { $grant }, expected role:<role> or a permission
+error-stats-invalid_range = from must not be after to
+error-request-invalid_fields = { $count ->
+    [0] No fields requested
+   *[other] Unknown or not allowed fields: { $fields }
+}
+error-request-precondition_failed = User was changed by someone else, reload it and retry
+error-request-invalid_patch = Invalid patch: { $errors }
+error-request-validation_failed = Validation failed
+error-request-malformed_json = { $reason }
+error-request-invalid_json = { $reason }
+error-request-unsupported_media_type = Expected `Content-Type: application/json`
+error-request-invalid_path = { $reason }
+error-request-invalid_query = { $reason }
+error-request-unsupported_version = { $media_type } names no API version this server has
+error-request-rate_limited = Too many requests, try again in { $retry_after } seconds
+error-request-value_too_long = Value is too long
+error-request-rejected = { $reason }
+error-internal-unknown_user_status = Unknown user status
+error-internal-error = Internal error
+error-internal-database = Internal error
+error-service-database_unavailable = Service temporarily unavailable
@@ -0,0 +1,64 @@
+# 成功消息，键由 `ApiResponse::new` 传入
+ok = 成功
+login-success = 登录成功
+deletion-scheduled = 已安排删除
+user-data-erased = 用户数据已清除
+user-deleted = 用户已删除
+group-created = 用户组已创建
+group-updated = 用户组已更新
+group-deleted = 用户组已删除
+member-added = 成员已添加
+member-removed = 成员已移除
+grant-added = 授权已添加
+grant-revoked = 授权已撤销
+bulk-dry-run = 试运行
+bulk-not-committed = 未提交
+
+# 错误消息，每个 `AppError::code` 一条，`.` 写作 `-`
+error-resource-not_found = 记录不存在
+error-resource-exists = 记录已存在
+error-resource-reference_conflict = 记录被引用，或引用了不存在的记录
+error-auth-invalid_credentials = 用户名或密码错误
+error-auth-invalid_token = 登录失败
+error-auth-account_not_active = { $status ->
+    [pending] 账号尚未激活
+    [suspended] 账号已被暂停
+    [banned] 账号已被封禁
+    [deactivated] 账号已停用
+    [pending_deletion] 账号已安排删除
+   *[other] 账号不可用
+}
+error-auth-permission_denied = 没有权限
+error-auth-field_not_permitted = 不允许修改 { $field }
+error-user-password_mismatch = 两次输入的密码不一致
+error-user-operation_on_self = 不能对自己执行此操作
+error-user-invalid_status_transition = 不能将状态从 { $from } 改为 { $to }，允许：{ $allowed }
+error-user-already_erased = 用户数据已被清除
+error-user-invalid_name = 用户名{ $reason }
+error-user-name_confusable = 用户名与已有用户名过于相似
+error-bulk-target_missing = 必须提供 ids 或 filter
+error-bulk-limit_exceeded = 单次批量操作的用户过多
+error-import-invalid_file = 无法读取导入文件
+error-export-failed = 导出失败
+error-permission-unknown_grant = 未知的授权 { $grant }，应为 role:<role> 或权限名
+error-stats-invalid_range = from 不能晚于 to
+error-request-invalid_fields = { $count ->
+    [0] 未指定字段
+   *[other] 未知或不允许的字段：{ $fields }
+}
+error-request-precondition_failed = 用户已被他人修改，请重新加载后重试
+error-request-invalid_patch = 无效的补丁：{ $errors }
+error-request-validation_failed = 校验失败
+error-request-malformed_json = JSON 格式错误：{ $reason }
+error-request-invalid_json = JSON 内容无效：{ $reason }
+error-request-unsupported_media_type = 需要 `Content-Type: application/json`
+error-request-invalid_path = 路径参数无效：{ $reason }
+error-request-invalid_query = 查询参数无效：{ $reason }
+error-request-unsupported_version = { $media_type } 不是本服务支持的 API 版本
+error-request-rate_limited = 请求过于频繁，请在 { $retry_after } 秒后重试
+error-request-value_too_long = 值过长
+error-request-rejected = 请求被拒绝：{ $reason }
+error-internal-unknown_user_status = 未知的用户状态
+error-internal-error = 内部错误
+error-internal-database = 内部错误
+error-service-database_unavailable = 服务暂时不可用
@@ -1,12 +1,17 @@
 use axum::{
+  body::Body,
   extract::Extension,
+  http::Request,
+  middleware,
   Router
 };
 use http::header;
 use tower_http::{
+  catch_panic::CatchPanicLayer,
   compression::CompressionLayer,
   cors::CorsLayer,
   propagate_header::PropagateHeaderLayer,
+  request_id::{MakeRequestUuid, SetRequestIdLayer},
   sensitive_headers::SetSensitiveHeadersLayer,
   trace,
 };
@@ -17,7 +22,7 @@ use tracing_subscriber::{
 };
 
 use utoipa::{
-  openapi::security::{ApiKey, ApiKeyValue, SecurityScheme},
+  openapi::{self, security::{ApiKey, ApiKeyValue, SecurityScheme}},
   Modify, OpenApi,
 };
 
@@ -27,11 +32,186 @@ use std::sync::Arc;
 
 use dotenv::dotenv;
 // use crate::logger;
-// use crate::models;
 
-use crate::routes;
+use crate::middlewares::locale::locale_middleware;
+use crate::middlewares::panic::{panic_response, request_span_middleware};
+use crate::middlewares::version::{deprecation_middleware, serve_bare_path};
+use crate::middlewares::problem::problem_json_middleware;
+use crate::middlewares::rate_limit::rate_limit_middleware;
+use crate::routes::{self, registry::{self, ApiVersion}};
+use crate::models;
+use crate::response;
+use crate::utils;
 use crate::db;
 
+/// Every documented operation, with paths below the version prefix
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+      routes::auth::login_api,
+      routes::auth::register_api,
+      routes::auth::register_availability_api,
+      routes::user::get_users_api,
+      routes::user::get_user_api,
+      routes::user::update_user_password_api,
+      routes::user::update_user_status_api,
+      routes::user::delete_user_api,
+      routes::user::patch_user_api,
+      routes::user::bulk_users_api,
+      routes::user_import::import_users_api,
+      routes::user_export::export_users_api,
+      routes::audit::get_audit_events_api,
+      routes::group::get_groups_api,
+      routes::group::create_group_api,
+      routes::group::get_group_api,
+      routes::group::update_group_api,
+      routes::group::delete_group_api,
+      routes::group::add_group_member_api,
+      routes::group::remove_group_member_api,
+      routes::group::add_group_grant_api,
+      routes::group::revoke_group_grant_api,
+      routes::grant::add_user_grant_api,
+      routes::grant::revoke_user_grant_api,
+      routes::grant::get_user_permissions_api,
+      routes::user_privacy::export_user_data_api,
+      routes::user_privacy::erase_user_api,
+      routes::login_history::get_user_logins_api,
+      routes::admin_stats::get_admin_stats_api,
+      routes::account_deletion::request_account_deletion_api,
+    ),
+    components(
+      schemas(
+        routes::user::UpdateUserPasswordBody,
+        routes::user::BulkUsersBody,
+        routes::user::BulkUsersFilter,
+        routes::user::BulkOperation,
+        routes::user::BulkItemResult,
+        routes::user::BulkItemState,
+        routes::user_import::ImportFormat,
+        routes::user_import::ImportMode,
+        routes::user_import::ImportRowError,
+        routes::user_export::ExportFormat,
+        routes::user::UpdateUserStatusBody,
+        models::user_status::UserStatus,
+        models::role::Role,
+        models::user_view::UserPublic,
+        models::user_view::UserSelf,
+        models::user_view::UserAdmin,
+        models::user_view::UserPartial,
+        models::user_view::UserView,
+        routes::auth::LoginRequestBody,
+        routes::auth::RegisterRequestBody,
+        routes::auth::NameAvailability,
+        routes::user::PatchUserBody,
+        routes::user::BulkUsersData,
+        routes::user_import::ImportUsersData,
+        models::permission::Permission,
+        routes::audit::AuditEventView,
+        routes::group::GroupBody,
+        routes::group::GroupView,
+        routes::group::GroupMemberView,
+        routes::group::GroupGrantView,
+        routes::group::GroupDetail,
+        routes::login_history::LoginAttemptView,
+        routes::user_privacy::ErasureReceipt,
+        routes::admin_stats::StatsInterval,
+        routes::admin_stats::StatusCount,
+        routes::admin_stats::RegistrationBucket,
+        routes::admin_stats::Registrations,
+        routes::admin_stats::ActiveUsers,
+        routes::admin_stats::AdminStats,
+        routes::account_deletion::DeletionRequestBody,
+        routes::account_deletion::DeletionRequestData,
+        response::Pagination,
+        response::UserViewPage,
+        response::AuditEventPage,
+        response::GroupPage,
+        response::LoginAttemptPage,
+        response::ErrorResponse,
+        response::ProblemDetails,
+        response::ValidationErrorResponse,
+        utils::validation::FieldError,
+        response::IdResponse,
+        response::LoginResponse,
+        response::RegisterAvailabilityResponse,
+        response::GetUsersAPIResponse,
+        response::GetUserAPIResponse,
+        response::PatchUserResponse,
+        response::BulkUsersResponse,
+        response::ImportUsersResponse,
+        response::GetAuditEventsResponse,
+        response::GetGroupsResponse,
+        response::GroupResponse,
+        response::GroupDetailResponse,
+        response::UserPermissionsResponse,
+        response::EraseUserResponse,
+        response::GetUserLoginsResponse,
+        response::GetAdminStatsResponse,
+        response::DeletionRequestResponse,
+      )
+    ),
+    // modifiers(&SecurityAddon),
+    tags(
+        (name = "user", description = "Users' API")
+    )
+)]
+pub struct ApiDoc;
+
+/// OpenAPI document of `version`: its operations at their full path, with
+/// the routes the registry marks as deprecated flagged so, and the 429 every
+/// route can answer once its rate limit is used up
+pub fn versioned_openapi(version: ApiVersion) -> openapi::OpenApi {
+  let mut doc = ApiDoc::openapi();
+  doc.info.version = version.to_string();
+
+  let rate_limited = openapi::ResponseBuilder::new()
+    .description("Rate limit used up, see the `Retry-After` and `RateLimit-*` headers")
+    .content(
+      "application/json",
+      openapi::ContentBuilder::new().schema(openapi::Ref::from_schema_name("ErrorResponse")).build(),
+    )
+    .build();
+
+  doc.paths.paths = std::mem::take(&mut doc.paths.paths)
+    .into_iter()
+    .filter_map(|(path, mut item)| {
+      item.operations.retain(|method, operation| {
+        match registry::find(version, path_item_method(method), &path) {
+          Some(route) => {
+            if route.deprecation.is_some() {
+              operation.deprecated = Some(openapi::Deprecated::True);
+            }
+            operation.responses.responses
+              .entry("429".to_string())
+              .or_insert_with(|| openapi::RefOr::T(rate_limited.clone()));
+            true
+          }
+          None => false,
+        }
+      });
+      if item.operations.is_empty() {
+        return None
+      }
+      Some((format!("{}{}", version.prefix(), registry::openapi_path(&path)), item))
+    })
+    .collect();
+  doc
+}
+
+fn path_item_method(method: &openapi::PathItemType) -> &'static str {
+  match method {
+    openapi::PathItemType::Get => "GET",
+    openapi::PathItemType::Post => "POST",
+    openapi::PathItemType::Put => "PUT",
+    openapi::PathItemType::Delete => "DELETE",
+    openapi::PathItemType::Options => "OPTIONS",
+    openapi::PathItemType::Head => "HEAD",
+    openapi::PathItemType::Patch => "PATCH",
+    openapi::PathItemType::Trace => "TRACE",
+    openapi::PathItemType::Connect => "CONNECT",
+  }
+}
+
 pub async fn create_app() -> Router {
   // logger::setup();
 
@@ -47,36 +227,57 @@ pub async fn create_app() -> Router {
     .with_max_level(tracing::Level::INFO)
     .init();
 
-  #[derive(OpenApi)]
-  #[openapi(
-      paths(
-        routes::auth::login_api,
-        routes::auth::register_api,
-        routes::user::get_users_api,
-        routes::user::get_user_api,
-        routes::user::update_user_password_api,
-      ),
-      components(
-        schemas(
-          routes::user::UpdateUserPasswordBody,
-        )
-      ),
-      // modifiers(&SecurityAddon),
-      tags(
-          (name = "user", description = "Users' API")
-      )
-  )]
-  struct ApiDoc;
+  utils::i18n::load();
+
+  // Users created before names were normalized, those left over still log in
+  // by their exact name, so a failure here is no reason to refuse to start
+  if let Err(e) = utils::username::backfill_canonical_names(&prisma_client).await {
+    tracing::error!("Could not normalize legacy user names: {}", e);
+  }
+
+  // Erase accounts whose deletion grace period is over
+  tokio::spawn(routes::account_deletion::run_scheduled_deletions(prisma_client.clone()));
+
+  create_router(utils::jwt::JwtSecret::from_env())
+    .layer(Extension(prisma_client))
+    .layer(trace::TraceLayer::new_for_http())
+}
+
+/// Routes and middlewares, without the database. `create_app` adds it, tests
+/// can add their own, and pick the secret tokens are signed with
+pub fn create_router(jwt_secret: utils::jwt::JwtSecret) -> Router {
+  // Buckets are shared by every version, a client can't double its quota by switching
+  let rate_limiter = Arc::new(utils::rate_limit::RateLimiter::from_env());
+
+  // Each version under its own prefix, rate limited per route, deprecated routes announce it
+  let api = ApiVersion::ALL.into_iter().fold(Router::new(), |api, version| {
+    api.nest(
+      version.prefix(),
+      routes::create_routes(version)
+        .route_layer(middleware::from_fn_with_state((version, rate_limiter.clone()), rate_limit_middleware))
+        .route_layer(middleware::from_fn_with_state(version, deprecation_middleware)),
+    )
+  });
+
+  let swagger_ui = ApiVersion::ALL.into_iter().fold(SwaggerUi::new("/swagger-ui"), |swagger_ui, version| {
+    swagger_ui.url(format!("/api-doc/{}/openapi.json", version), versioned_openapi(version))
+  });
 
   Router::new()
-    .merge(SwaggerUi::new("/swagger-ui").url("/api-doc/openapi.json", ApiDoc::openapi()))
-    .merge(routes::user::create_route())
-    .merge(routes::auth::create_route())
-    // .merge(Router::new().nest(
-      // "/v1",
-      // All public v1 routes will be nested here.
-      // Router::new().merge(routes::cat::create_route()),
-    // ))
+    .merge(swagger_ui)
+    .merge(api.clone())
+    // Paths without a prefix, the version comes from `Accept`
+    .fallback(move |req: Request<Body>| serve_bare_path(api.clone(), req))
+    // Read by the login handler and wherever a token is verified
+    .layer(Extension(jwt_secret))
+    // A panicking handler gets a 500 envelope instead of a dropped connection
+    .layer(CatchPanicLayer::custom(panic_response))
+    // Errors as `application/problem+json` for clients asking for it
+    .layer(middleware::from_fn(problem_json_middleware))
+    // Messages in the language of the `lang` cookie or `Accept-Language`
+    .layer(middleware::from_fn(locale_middleware))
+    // Log lines, including panics, carry the request's `X-Request-Id`
+    .layer(middleware::from_fn(request_span_middleware))
     // High level logging of requests and responses
     .layer(
       trace::TraceLayer::new_for_http()
@@ -95,9 +296,13 @@ pub async fn create_app() -> Router {
     .layer(PropagateHeaderLayer::new(header::HeaderName::from_static(
       "x-request-id",
     )))
+    // Give requests without an `X-Request-Id` a fresh one, audit entries
+    // refer to it
+    .layer(SetRequestIdLayer::new(
+      header::HeaderName::from_static("x-request-id"),
+      MakeRequestUuid,
+    ))
     // CORS configuration. This should probably be more restrictive in
     // production.
     .layer(CorsLayer::very_permissive())
-    .layer(Extension(prisma_client))
-    .layer(trace::TraceLayer::new_for_http())
-}
\ No newline at end of file
+}
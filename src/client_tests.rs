@@ -0,0 +1,135 @@
+//! The typed client against the app served on a local port: requests go
+//! through the same types the handlers use, and error envelopes come back as
+//! `rust_learn_client::Error`.
+//!
+//! `client_round_trip` needs a migrated, throwaway database in
+//! `TEST_DATABASE_URL` and is ignored by default, run it with `--ignored`.
+
+use axum::{extract::Extension, Router};
+use rust_learn_client::{
+  types::{
+    ErrorCode, FieldError, GetUserQuery, GetUsersAPIQuery, LoginRequestBody, PatchUserBody,
+    RegisterRequestBody, UserStatus, UserView,
+  },
+  Auth, Client, Error,
+};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::app::create_router;
+use crate::db;
+use crate::utils::jwt::JwtSecret;
+
+fn router() -> Router {
+  create_router(JwtSecret::new("client-tests"))
+}
+
+/// Serve `router` on a free local port, returns its base URL
+fn serve(router: Router) -> String {
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let addr = listener.local_addr().unwrap();
+  let server = axum::Server::from_tcp(listener)
+    .unwrap()
+    .serve(router.into_make_service_with_connect_info::<SocketAddr>());
+  tokio::spawn(server);
+  format!("http://{}", addr)
+}
+
+/// Fail unless `result` is an API error with `status` and `code`, returns its broken rules
+fn assert_api_error<T: std::fmt::Debug>(result: Result<T, Error>, status: u16, code: ErrorCode) -> Vec<FieldError> {
+  match result {
+    Err(Error::Api { status: actual_status, code: actual_code, errors, .. }) => {
+      assert_eq!(actual_status, status);
+      assert_eq!(actual_code, code);
+      errors
+    }
+    other => panic!("expected a {} {} error, got {:?}", status, code, other),
+  }
+}
+
+#[tokio::test]
+async fn errors_come_back_typed() {
+  let base_url = serve(router());
+
+  let client = Client::new(&base_url);
+  assert_api_error(client.get_users(&GetUsersAPIQuery::default()).await, 401, ErrorCode::AuthInvalidToken);
+
+  for auth in [Auth::Cookie, Auth::Bearer] {
+    let client = Client::new(&base_url).auth(auth);
+    client.set_token(Some("not-a-token".to_string()));
+    assert_api_error(client.get_user_permissions(&Uuid::new_v4().to_string()).await, 401, ErrorCode::AuthInvalidToken);
+  }
+}
+
+#[tokio::test]
+#[ignore = "needs a migrated, throwaway database in TEST_DATABASE_URL"]
+async fn client_round_trip() {
+  let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is not set");
+  let prisma_client = Arc::new(db::new_client_with_url(&url).await.unwrap());
+  let base_url = serve(router().layer(Extension(prisma_client)));
+
+  let name = format!("cl{}", &Uuid::new_v4().simple().to_string()[..12]);
+  let password = "client-password".to_string();
+  let client = Client::new(&base_url);
+
+  let errors = assert_api_error(
+    client.register(&RegisterRequestBody {
+      name: name.clone(),
+      password: "short".to_string(),
+      password_confirm: "short".to_string(),
+    }).await,
+    422,
+    ErrorCode::RequestValidationFailed,
+  );
+  assert!(errors.iter().any(|error| error.field == "password"));
+
+  let user_id = client
+    .register(&RegisterRequestBody { name: name.clone(), password: password.clone(), password_confirm: password.clone() })
+    .await
+    .unwrap();
+  assert!(!client.register_availability(&name).await.unwrap().available);
+
+  assert_api_error(
+    client.login(&LoginRequestBody { name: name.clone(), password: "not-the-password".to_string() }).await,
+    401,
+    ErrorCode::AuthInvalidCredentials,
+  );
+  assert_eq!(client.token(), None);
+
+  let user = client.login(&LoginRequestBody { name: name.clone(), password: password.clone() }).await.unwrap();
+  assert_eq!(user.id, user_id);
+  assert_eq!(user.status, UserStatus::Active);
+
+  // The same session sent as a bearer token
+  let bearer = Client::new(&base_url).auth(Auth::Bearer);
+  bearer.set_token(client.token());
+
+  for client in [&client, &bearer] {
+    let users = client.get_users(&GetUsersAPIQuery { page_size: Some(5), ..Default::default() }).await.unwrap();
+    assert_eq!(users.pagination.page_size, 5);
+    assert!(users.list.len() <= 5);
+
+    let own = client.get_user(&user_id, &GetUserQuery::default()).await.unwrap();
+    assert!(matches!(own.data, UserView::Owner(ref view) if view.name == name));
+    assert!(own.etag.is_some());
+  }
+
+  let own = client.get_user(&user_id, &GetUserQuery::default()).await.unwrap();
+  let renamed = format!("{}x", name);
+  let patched = client
+    .patch_user(&user_id, &PatchUserBody { name: Some(renamed.clone()), ..Default::default() }, own.etag.as_deref())
+    .await
+    .unwrap();
+  assert!(matches!(patched.data, UserView::Owner(ref view) if view.name == renamed));
+  assert_ne!(patched.etag, own.etag);
+
+  // The version the patch replaced is gone
+  assert_api_error(
+    client.patch_user(&user_id, &PatchUserBody { name: Some(name.clone()), ..Default::default() }, own.etag.as_deref()).await,
+    412,
+    ErrorCode::RequestPreconditionFailed,
+  );
+
+  assert_api_error(client.get_user(&Uuid::new_v4().to_string(), &GetUserQuery::default()).await, 404, ErrorCode::ResourceNotFound);
+}
@@ -0,0 +1,356 @@
+//! The router, the route registry and the OpenAPI documents must agree: every
+//! registered route is served and documented, every documented operation is
+//! registered, and what the app answers matches the documented schema.
+//!
+//! `live_responses_match_the_spec` needs a migrated, throwaway database in
+//! `TEST_DATABASE_URL` and is ignored by default, run it with `--ignored`.
+
+use axum::{
+  body::Body,
+  extract::Extension,
+  http::{header, Method, Request, StatusCode},
+  Router,
+};
+use jsonschema::{Draft, JSONSchema};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+use crate::app::{create_router, versioned_openapi, ApiDoc};
+use crate::db;
+use crate::routes::registry::{self, ApiRoute, ApiVersion};
+use crate::utils::jwt::JwtSecret;
+use utoipa::OpenApi;
+
+/// Stands in for every path parameter, ids have to be UUIDs
+const SAMPLE_PARAM: &str = "6f1c3c1e-8a43-4b8e-9d2b-3f2f3c0e8f11";
+
+fn router() -> Router {
+  create_router(JwtSecret::new("contract-tests"))
+}
+
+fn spec(version: ApiVersion) -> Value {
+  serde_json::to_value(versioned_openapi(version)).unwrap()
+}
+
+fn sample_path(path: &str) -> String {
+  path
+    .split('/')
+    .map(|segment| if segment.starts_with(':') { SAMPLE_PARAM } else { segment })
+    .collect::<Vec<&str>>()
+    .join("/")
+}
+
+fn operation<'a>(spec: &'a Value, version: ApiVersion, method: &str, path: &str) -> Option<&'a Value> {
+  spec["paths"]
+    .get(format!("{}{}", version.prefix(), registry::openapi_path(path)))?
+    .get(method.to_lowercase())
+}
+
+/// OpenAPI 3.0 schema as JSON Schema: `nullable` becomes a `null` type, and
+/// `oneOf` becomes `anyOf` since the views of untagged enums overlap
+fn to_json_schema(value: &mut Value) {
+  let mut wrap_nullable = false;
+  if let Value::Object(map) = value {
+    if map.get("nullable") == Some(&Value::Bool(true)) {
+      map.remove("nullable");
+      match map.get("type").cloned() {
+        Some(Value::String(schema_type)) => {
+          map.insert("type".to_string(), json!([schema_type, "null"]));
+        }
+        _ => wrap_nullable = true,
+      }
+    }
+    if let Some(variants) = map.remove("oneOf") {
+      map.insert("anyOf".to_string(), variants);
+    }
+  }
+  if wrap_nullable {
+    let schema = value.take();
+    *value = json!({ "anyOf": [schema, { "type": "null" }] });
+  }
+
+  match value {
+    Value::Object(map) => map.values_mut().for_each(to_json_schema),
+    Value::Array(items) => items.iter_mut().for_each(to_json_schema),
+    _ => (),
+  }
+}
+
+/// Fail unless `status` is documented for the operation and `body` matches its schema
+fn assert_matches_spec(spec: &Value, version: ApiVersion, method: &str, path: &str, status: StatusCode, body: &[u8]) {
+  let operation = operation(spec, version, method, path)
+    .unwrap_or_else(|| panic!("{} {} is not documented", method, path));
+  let response = operation["responses"]
+    .get(status.as_str())
+    .unwrap_or_else(|| panic!("{} {} answered {}, which is not documented", method, path, status));
+  let schema = match response.pointer("/content/application~1json/schema") {
+    Some(schema) => schema.clone(),
+    None => return,
+  };
+
+  // References point into the components of the whole document
+  let mut root = json!({ "allOf": [schema], "components": spec["components"] });
+  to_json_schema(&mut root);
+  let compiled = JSONSchema::options()
+    .with_draft(Draft::Draft7)
+    .compile(&root)
+    .unwrap_or_else(|error| panic!("Schema of {} {} {} does not compile: {}", method, path, status, error));
+
+  let instance: Value = serde_json::from_slice(body)
+    .unwrap_or_else(|error| panic!("{} {} answered {} without JSON: {}", method, path, status, error));
+  if let Err(errors) = compiled.validate(&instance) {
+    let errors: Vec<String> = errors.map(|error| format!("{} at {}", error, error.instance_path)).collect();
+    panic!("{} {} answered {} not matching its schema: {}\n{}", method, path, status, errors.join("; "), instance);
+  }
+}
+
+async fn send(router: &Router, request: Request<Body>) -> (StatusCode, axum::http::HeaderMap, Vec<u8>) {
+  let response = router.clone().oneshot(request).await.unwrap();
+  let status = response.status();
+  let headers = response.headers().clone();
+  let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+  (status, headers, body.to_vec())
+}
+
+fn request(route: &ApiRoute, version: ApiVersion) -> Request<Body> {
+  Request::builder()
+    .method(Method::from_bytes(route.method.as_bytes()).unwrap())
+    .uri(format!("{}{}", version.prefix(), sample_path(route.path)))
+    .body(Body::empty())
+    .unwrap()
+}
+
+#[test]
+fn every_registered_route_is_documented() {
+  for version in ApiVersion::ALL {
+    let spec = spec(version);
+    for route in version.routes() {
+      assert!(
+        operation(&spec, version, route.method, route.path).is_some(),
+        "{} {} {} is registered but missing from the OpenAPI document",
+        version,
+        route.method,
+        route.path
+      );
+    }
+  }
+}
+
+#[test]
+fn every_documented_operation_is_registered() {
+  let doc = serde_json::to_value(ApiDoc::openapi()).unwrap();
+  for (path, item) in doc["paths"].as_object().unwrap() {
+    for method in item.as_object().unwrap().keys() {
+      assert!(
+        ApiVersion::ALL.into_iter().any(|version| registry::find(version, method, path).is_some()),
+        "{} {} is documented but not in the route registry",
+        method.to_uppercase(),
+        path
+      );
+    }
+  }
+}
+
+#[test]
+fn documented_paths_declare_their_parameters() {
+  let doc = serde_json::to_value(ApiDoc::openapi()).unwrap();
+  for (path, item) in doc["paths"].as_object().unwrap() {
+    assert!(!path.contains(':'), "{} uses axum syntax, write {{name}} instead of :name", path);
+
+    let names: Vec<&str> = path
+      .split('/')
+      .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+      .collect();
+    for (method, operation) in item.as_object().unwrap() {
+      let parameters = operation["parameters"].as_array().cloned().unwrap_or_default();
+      for name in names.iter() {
+        assert!(
+          parameters.iter().any(|parameter| parameter["name"] == *name && parameter["in"] == "path"),
+          "{} {} does not declare the path parameter {}",
+          method.to_uppercase(),
+          path,
+          name
+        );
+      }
+    }
+  }
+}
+
+#[test]
+fn deprecated_routes_are_marked_in_the_document() {
+  for version in ApiVersion::ALL {
+    let spec = spec(version);
+    for route in version.routes() {
+      let deprecated = operation(&spec, version, route.method, route.path)
+        .and_then(|operation| operation.get("deprecated"))
+        == Some(&Value::Bool(true));
+      assert_eq!(deprecated, route.deprecation.is_some(), "{} {} {}", version, route.method, route.path);
+    }
+  }
+}
+
+#[tokio::test]
+async fn every_registered_route_is_served() {
+  let router = router();
+  for version in ApiVersion::ALL {
+    for route in version.routes() {
+      let (status, _, _) = send(&router, request(route, version)).await;
+      assert!(
+        status != StatusCode::NOT_FOUND && status != StatusCode::METHOD_NOT_ALLOWED,
+        "{} {} {} is registered but answered {}",
+        version,
+        route.method,
+        route.path,
+        status
+      );
+    }
+  }
+}
+
+#[tokio::test]
+async fn unauthenticated_responses_match_the_spec() {
+  // Without a database only the answers given before a query can be checked,
+  // that is the 401 of every route behind the auth middleware
+  let router = router();
+  for version in ApiVersion::ALL {
+    let spec = spec(version);
+    for route in version.routes() {
+      let (status, _, body) = send(&router, request(route, version)).await;
+      if status == StatusCode::UNAUTHORIZED {
+        assert_matches_spec(&spec, version, route.method, route.path, status, &body);
+      }
+    }
+  }
+}
+
+#[tokio::test]
+async fn bare_paths_are_deprecated_aliases() {
+  let router = router();
+  let (status, headers, _) = send(&router, Request::get("/users").body(Body::empty()).unwrap()).await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+  assert!(headers.contains_key("deprecation"));
+  assert!(headers.contains_key("sunset"));
+  assert_eq!(headers[header::LINK], "</api/v1/users>; rel=\"successor-version\"");
+
+  let versioned = Request::get("/users")
+    .header(header::ACCEPT, "application/vnd.rust-learn.v1+json")
+    .body(Body::empty())
+    .unwrap();
+  let (status, headers, _) = send(&router, versioned).await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+  assert!(!headers.contains_key("deprecation"));
+
+  let unknown = Request::get("/users")
+    .header(header::ACCEPT, "application/vnd.rust-learn.v9+json")
+    .body(Body::empty())
+    .unwrap();
+  let (status, _, _) = send(&router, unknown).await;
+  assert_eq!(status, StatusCode::NOT_ACCEPTABLE);
+}
+
+fn json_request(method: Method, uri: &str, cookie: Option<&str>, body: Option<Value>) -> Request<Body> {
+  let mut builder = Request::builder().method(method).uri(uri);
+  if let Some(cookie) = cookie {
+    builder = builder.header(header::COOKIE, cookie);
+  }
+  match body {
+    Some(body) => builder
+      .header(header::CONTENT_TYPE, "application/json")
+      .body(Body::from(body.to_string()))
+      .unwrap(),
+    None => builder.body(Body::empty()).unwrap(),
+  }
+}
+
+#[tokio::test]
+#[ignore = "needs a migrated, throwaway database in TEST_DATABASE_URL"]
+async fn live_responses_match_the_spec() {
+  let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is not set");
+  let prisma_client = Arc::new(db::new_client_with_url(&url).await.unwrap());
+  let router = router().layer(Extension(prisma_client));
+
+  let version = ApiVersion::V1;
+  let spec = spec(version);
+  let prefix = version.prefix();
+  let name = format!("ct{}", &Uuid::new_v4().simple().to_string()[..12]);
+  let password = "contract-password";
+
+  let (status, _, body) = send(&router, json_request(
+    Method::POST,
+    &format!("{}/register", prefix),
+    None,
+    Some(json!({ "name": name, "password": password, "password_confirm": password })),
+  )).await;
+  assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+  assert_matches_spec(&spec, version, "POST", "/register", status, &body);
+  let user_id = serde_json::from_slice::<Value>(&body).unwrap()["data"].as_str().unwrap().to_string();
+
+  let (status, _, body) = send(&router, json_request(
+    Method::GET,
+    &format!("{}/register/availability?name={}", prefix, name),
+    None,
+    None,
+  )).await;
+  assert_matches_spec(&spec, version, "GET", "/register/availability", status, &body);
+
+  let (status, _, body) = send(&router, json_request(
+    Method::POST,
+    &format!("{}/register", prefix),
+    None,
+    Some(json!({ "name": name, "password": "short", "password_confirm": "short" })),
+  )).await;
+  assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+  assert_matches_spec(&spec, version, "POST", "/register", status, &body);
+
+  let (status, headers, body) = send(&router, json_request(
+    Method::POST,
+    &format!("{}/login", prefix),
+    None,
+    Some(json!({ "name": name, "password": password })),
+  )).await;
+  assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+  assert_matches_spec(&spec, version, "POST", "/login", status, &body);
+  let cookie = headers[header::SET_COOKIE].to_str().unwrap().split(';').next().unwrap().to_string();
+
+  let (status, _, body) = send(&router, json_request(
+    Method::POST,
+    &format!("{}/login", prefix),
+    None,
+    Some(json!({ "name": name, "password": "not-the-password" })),
+  )).await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+  assert_matches_spec(&spec, version, "POST", "/login", status, &body);
+
+  let authenticated: [(Method, &str, String); 4] = [
+    (Method::GET, "/users", format!("{}/users?page=1&page_size=5", prefix)),
+    (Method::GET, "/users/:user_id", format!("{}/users/{}", prefix, user_id)),
+    (Method::GET, "/users/:user_id", format!("{}/users/{}", prefix, Uuid::new_v4())),
+    (Method::GET, "/users/:user_id/permissions", format!("{}/users/{}/permissions", prefix, user_id)),
+  ];
+  for (method, path, uri) in authenticated {
+    let (status, _, body) = send(&router, json_request(method.clone(), &uri, Some(&cookie), None)).await;
+    assert_matches_spec(&spec, version, method.as_str(), path, status, &body);
+  }
+
+  // A plain user can't delete somebody else
+  let other_name = format!("ct{}", &Uuid::new_v4().simple().to_string()[..12]);
+  let (status, _, body) = send(&router, json_request(
+    Method::POST,
+    &format!("{}/register", prefix),
+    None,
+    Some(json!({ "name": other_name, "password": password, "password_confirm": password })),
+  )).await;
+  assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+  let other_id = serde_json::from_slice::<Value>(&body).unwrap()["data"].as_str().unwrap().to_string();
+
+  let (status, _, body) = send(&router, json_request(
+    Method::DELETE,
+    &format!("{}/users/{}", prefix, other_id),
+    Some(&cookie),
+    None,
+  )).await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+  assert_matches_spec(&spec, version, "DELETE", "/users/:user_id", status, &body);
+}
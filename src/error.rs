@@ -3,12 +3,21 @@ use axum::{
   Json,
   response::{IntoResponse, Response},
 };
-use serde::{Serialize};
-use serde_json::{json};
+use fluent_templates::fluent_bundle::FluentValue;
+use serde_json::{json, Value};
 use thiserror::Error;
 
+use crate::models::user_status::{UserStatus, UserStatusExt};
+use crate::utils::i18n;
+use crate::utils::validation::FieldError;
+use crate::response::{ApiResponse, ProblemDetails};
+pub use crate::response::ErrorResponse;
+
 use prisma_client_rust::{
-  prisma_errors::query_engine::{RecordNotFound, UniqueKeyViolation},
+  prisma_errors::{
+    common::{DatabaseNotReachable, DatabaseOperationTimeout},
+    query_engine::{ForeignKeyViolation, RecordNotFound, UniqueKeyViolation, ValueTooLong},
+  },
   QueryError,
 };
 
@@ -29,6 +38,60 @@ pub enum AppError {
     PasswordDontMatch,
     #[error("Operation Conflict")]
     OperationConflict,
+    #[error("Bulk Target Missing")]
+    BulkTargetMissing,
+    #[error("Bulk Limit Exceeded")]
+    BulkLimitExceeded,
+    #[error("Invalid Import File")]
+    InvalidImportFile,
+    #[error("Export Failed")]
+    ExportFailed,
+    #[error("Cannot change status from {from} to {to}")]
+    InvalidStatusTransition { from: UserStatus, to: UserStatus },
+    #[error("Unknown User Status {0}")]
+    UnknownUserStatus(i32),
+    #[error("Account Not Active")]
+    AccountNotActive(UserStatus),
+    #[error("Permission Denied")]
+    PermissionDenied,
+    #[error("Invalid Fields")]
+    InvalidFields(Vec<String>),
+    #[error("Precondition Failed")]
+    PreconditionFailed,
+    #[error("Invalid Patch")]
+    InvalidPatch(Vec<String>),
+    #[error("Field Not Permitted")]
+    FieldNotPermitted(String),
+    #[error("Unknown Grant")]
+    UnknownGrant(String),
+    #[error("Already Erased")]
+    AlreadyErased,
+    #[error("Invalid Username")]
+    InvalidUsername(String),
+    #[error("Username Confusable")]
+    UsernameConfusable,
+    #[error("Invalid Stats Range")]
+    InvalidStatsRange,
+    #[error("Validation Failed")]
+    ValidationFailed(Vec<FieldError>),
+    #[error("Malformed JSON")]
+    MalformedJson(String),
+    #[error("Invalid JSON Data")]
+    InvalidJsonData(String),
+    #[error("Missing JSON Content Type")]
+    MissingJsonContentType,
+    #[error("Invalid Path")]
+    InvalidPath(String),
+    #[error("Invalid Query")]
+    InvalidQuery(String),
+    #[error("Request Rejected")]
+    RequestRejected { status: StatusCode, message: String },
+    #[error("Unsupported API Version {0}")]
+    UnsupportedApiVersion(String),
+    #[error("Rate Limited, retry in {0}s")]
+    RateLimited(u64),
+    #[error("Internal Error: {0}")]
+    Internal(String),
 }
 
 pub type AppResult<T> = Result<T, AppError>;
@@ -43,55 +106,348 @@ impl From<QueryError> for AppError {
   }
 }
 
-/// Define Error Response Struct
-#[derive(Serialize)]
-struct ErrorResponse {
-    code: String,
-    message: String,
-    data: String,
+impl From<bcrypt::BcryptError> for AppError {
+  fn from(error: bcrypt::BcryptError) -> Self {
+      AppError::Internal(format!("Password hashing failed: {}", error))
+  }
+}
+
+/// Status and code of a database failure. What the database said is only
+/// logged, it can name tables and columns
+fn query_error_kind(error: &QueryError) -> (StatusCode, &'static str) {
+  // Same as `RecordExisted`, whether the handler or the database noticed first
+  if error.is_prisma_error::<UniqueKeyViolation>() {
+    (StatusCode::CONFLICT, "resource.exists")
+  } else if error.is_prisma_error::<ForeignKeyViolation>() {
+    (StatusCode::CONFLICT, "resource.reference_conflict")
+  } else if error.is_prisma_error::<ValueTooLong>() {
+    (StatusCode::BAD_REQUEST, "request.value_too_long")
+  } else if error.is_prisma_error::<DatabaseNotReachable>() || error.is_prisma_error::<DatabaseOperationTimeout>() {
+    (StatusCode::SERVICE_UNAVAILABLE, "service.database_unavailable")
+  } else {
+    (StatusCode::INTERNAL_SERVER_ERROR, "internal.database")
+  }
+}
+
+/// Codes `PrismaError` can get, depending on what the database reported
+pub const DATABASE_ERROR_CODES: [&str; 5] = [
+  "resource.exists",
+  "resource.reference_conflict",
+  "request.value_too_long",
+  "service.database_unavailable",
+  "internal.database",
+];
+
+/// Key of the message of an error code in the catalogs
+pub fn message_key(code: &str) -> String {
+  format!("error-{}", code.replace('.', "-"))
+}
+
+impl AppError {
+  /// Stable machine-readable code, clients switch on it rather than on messages
+  pub fn code(&self) -> &'static str {
+    match self {
+      AppError::PrismaError(error) => query_error_kind(error).1,
+      AppError::RecordNotFound => "resource.not_found",
+      AppError::RecordExisted => "resource.exists",
+      AppError::WrongCredentials => "auth.invalid_credentials",
+      AppError::JWTTokenInvalid => "auth.invalid_token",
+      AppError::PasswordDontMatch => "user.password_mismatch",
+      AppError::OperationConflict => "user.operation_on_self",
+      AppError::BulkTargetMissing => "bulk.target_missing",
+      AppError::BulkLimitExceeded => "bulk.limit_exceeded",
+      AppError::InvalidImportFile => "import.invalid_file",
+      AppError::ExportFailed => "export.failed",
+      AppError::InvalidStatusTransition { .. } => "user.invalid_status_transition",
+      AppError::UnknownUserStatus(_) => "internal.unknown_user_status",
+      AppError::AccountNotActive(_) => "auth.account_not_active",
+      AppError::PermissionDenied => "auth.permission_denied",
+      AppError::InvalidFields(_) => "request.invalid_fields",
+      AppError::PreconditionFailed => "request.precondition_failed",
+      AppError::InvalidPatch(_) => "request.invalid_patch",
+      AppError::FieldNotPermitted(_) => "auth.field_not_permitted",
+      AppError::UnknownGrant(_) => "permission.unknown_grant",
+      AppError::AlreadyErased => "user.already_erased",
+      AppError::InvalidUsername(_) => "user.invalid_name",
+      AppError::UsernameConfusable => "user.name_confusable",
+      AppError::InvalidStatsRange => "stats.invalid_range",
+      AppError::ValidationFailed(_) => "request.validation_failed",
+      AppError::MalformedJson(_) => "request.malformed_json",
+      AppError::InvalidJsonData(_) => "request.invalid_json",
+      AppError::MissingJsonContentType => "request.unsupported_media_type",
+      AppError::InvalidPath(_) => "request.invalid_path",
+      AppError::InvalidQuery(_) => "request.invalid_query",
+      AppError::RequestRejected { .. } => "request.rejected",
+      AppError::UnsupportedApiVersion(_) => "request.unsupported_version",
+      AppError::RateLimited(_) => "request.rate_limited",
+      AppError::Internal(_) => "internal.error",
+    }
+  }
 }
 
 impl IntoResponse for AppError {
   fn into_response(self) -> Response {
-      let (status, error_message) = match self {
-          AppError::PrismaError(error) if error.is_prisma_error::<UniqueKeyViolation>() => {
-            (StatusCode::CONFLICT, "Record existed")
+      let code = self.code();
+      // Only validation errors carry details, everything else keeps an empty `data`
+      let field_errors = match &self {
+          AppError::ValidationFailed(errors) => Some(errors.clone()),
+          _ => None,
+      };
+      let data = field_errors.as_ref().map_or(Value::String("".to_string()), |errors| json!(errors));
+
+      let (status, args): (StatusCode, Vec<(&str, FluentValue<'static>)>) = match self {
+          AppError::PrismaError(error) => {
+            let (status, _) = query_error_kind(&error);
+            if status.is_server_error() {
+              tracing::error!("Query failed: {:?}", error);
+            } else {
+              tracing::info!("Query rejected: {:?}", error);
+            }
+            (status, vec![])
+          }
+          AppError::RecordNotFound => (StatusCode::NOT_FOUND, vec![]),
+          AppError::RecordExisted => (StatusCode::CONFLICT, vec![]),
+          AppError::WrongCredentials => (StatusCode::UNAUTHORIZED, vec![]),
+          AppError::JWTTokenInvalid => (StatusCode::UNAUTHORIZED, vec![]),
+          AppError::PasswordDontMatch => (StatusCode::BAD_REQUEST, vec![]),
+          AppError::OperationConflict => (StatusCode::BAD_REQUEST, vec![]),
+          AppError::BulkTargetMissing => (StatusCode::BAD_REQUEST, vec![]),
+          AppError::BulkLimitExceeded => (StatusCode::BAD_REQUEST, vec![]),
+          AppError::InvalidImportFile => (StatusCode::BAD_REQUEST, vec![]),
+          AppError::ExportFailed => (StatusCode::INTERNAL_SERVER_ERROR, vec![]),
+          AppError::InvalidStatusTransition { from, to } => {
+            let allowed: Vec<&str> = from.allowed_transitions().iter().map(|status| status.as_str()).collect();
+            (
+              StatusCode::CONFLICT,
+              vec![
+                ("from", FluentValue::from(from.as_str())),
+                ("to", FluentValue::from(to.as_str())),
+                ("allowed", FluentValue::from(allowed.join(", "))),
+              ],
+            )
+          }
+          AppError::UnknownUserStatus(value) => {
+            tracing::error!("Unknown user status value {}", value);
+            (StatusCode::INTERNAL_SERVER_ERROR, vec![])
+          }
+          AppError::AccountNotActive(status) => {
+            (StatusCode::FORBIDDEN, vec![("status", FluentValue::from(status.as_str()))])
+          }
+          AppError::PermissionDenied => (StatusCode::FORBIDDEN, vec![]),
+          AppError::InvalidFields(fields) => {
+            (
+              StatusCode::BAD_REQUEST,
+              vec![
+                ("count", FluentValue::from(fields.len())),
+                ("fields", FluentValue::from(fields.join(", "))),
+              ],
+            )
+          }
+          AppError::PreconditionFailed => (StatusCode::PRECONDITION_FAILED, vec![]),
+          AppError::InvalidPatch(errors) => {
+            (StatusCode::UNPROCESSABLE_ENTITY, vec![("errors", FluentValue::from(errors.join("; ")))])
+          }
+          AppError::FieldNotPermitted(field) => {
+            (StatusCode::FORBIDDEN, vec![("field", FluentValue::from(field))])
+          }
+          AppError::UnknownGrant(grant) => {
+            (StatusCode::BAD_REQUEST, vec![("grant", FluentValue::from(grant))])
+          }
+          AppError::AlreadyErased => (StatusCode::CONFLICT, vec![]),
+          AppError::InvalidUsername(reason) => {
+            (StatusCode::BAD_REQUEST, vec![("reason", FluentValue::from(reason))])
+          }
+          AppError::UsernameConfusable => (StatusCode::CONFLICT, vec![]),
+          AppError::InvalidStatsRange => (StatusCode::BAD_REQUEST, vec![]),
+          AppError::ValidationFailed(_) => (StatusCode::UNPROCESSABLE_ENTITY, vec![]),
+          AppError::MalformedJson(reason) => {
+            (StatusCode::BAD_REQUEST, vec![("reason", FluentValue::from(reason))])
           }
-          AppError::PrismaError(_) => {
-            (StatusCode::BAD_REQUEST, "Query Error")
+          AppError::InvalidJsonData(reason) => {
+            (StatusCode::UNPROCESSABLE_ENTITY, vec![("reason", FluentValue::from(reason))])
           }
-          AppError::RecordNotFound => {
-            (StatusCode::NOT_FOUND, "Record not found")
+          AppError::MissingJsonContentType => (StatusCode::UNSUPPORTED_MEDIA_TYPE, vec![]),
+          AppError::InvalidPath(reason) => {
+            (StatusCode::BAD_REQUEST, vec![("reason", FluentValue::from(reason))])
           }
-          AppError::RecordExisted => {
-            (StatusCode::BAD_REQUEST, "Record existed")
+          AppError::InvalidQuery(reason) => {
+            (StatusCode::BAD_REQUEST, vec![("reason", FluentValue::from(reason))])
           }
-          AppError::WrongCredentials => {
-            (StatusCode::UNAUTHORIZED, "Username/password incorrect")
+          AppError::RequestRejected { status, message } => {
+            (status, vec![("reason", FluentValue::from(message))])
           }
-          AppError::JWTTokenInvalid => {
-            (StatusCode::UNAUTHORIZED, "Login Error")
+          AppError::UnsupportedApiVersion(media_type) => {
+            (StatusCode::NOT_ACCEPTABLE, vec![("media_type", FluentValue::from(media_type))])
           }
-          AppError::PasswordDontMatch => {
-            (StatusCode::BAD_REQUEST, "Passwords don't match")
+          AppError::RateLimited(retry_after) => {
+            (StatusCode::TOO_MANY_REQUESTS, vec![("retry_after", FluentValue::from(retry_after))])
           }
-          AppError::OperationConflict => {
-            (StatusCode::BAD_REQUEST, "Operation Conflict")
+          AppError::Internal(detail) => {
+            // The detail can name internals, it is only logged
+            tracing::error!("Internal error: {}", detail);
+            (StatusCode::INTERNAL_SERVER_ERROR, vec![])
           }
       };
-      
-      let res_json = ErrorResponse {
-        code: status.to_string()
-        .chars()
-        .filter(|c| c.is_digit(10))
-        .collect(),
-        message: error_message.to_string(),
-        data: "".to_string()
+      let error_message = i18n::translate(&message_key(code), &args);
+
+      let problem = ProblemDetails::new(status, code, &error_message, field_errors);
+      let res_json = ApiResponse {
+        code: code.to_string(),
+        message: error_message,
+        data,
+        pagination: None,
       };
 
       tracing::debug!("{}", json!(&res_json));
-      let body = Json(json!(res_json));
 
-      (status, body).into_response()
+      // Not through `ApiResponse::into_response`, the message is translated already
+      let mut response = (status, Json(res_json)).into_response();
+      // Swapped in by `problem_json_middleware` for clients asking for it
+      response.extensions_mut().insert(problem);
+      response
+  }
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rust_learn_client::types::ErrorCode;
+
+  /// One error of every variant, `PrismaError` is covered by `DATABASE_ERROR_CODES`
+  fn samples() -> Vec<AppError> {
+    vec![
+      AppError::RecordNotFound,
+      AppError::RecordExisted,
+      AppError::WrongCredentials,
+      AppError::JWTTokenInvalid,
+      AppError::PasswordDontMatch,
+      AppError::OperationConflict,
+      AppError::BulkTargetMissing,
+      AppError::BulkLimitExceeded,
+      AppError::InvalidImportFile,
+      AppError::ExportFailed,
+      AppError::InvalidStatusTransition { from: UserStatus::Banned, to: UserStatus::Pending },
+      AppError::UnknownUserStatus(42),
+      AppError::AccountNotActive(UserStatus::Suspended),
+      AppError::PermissionDenied,
+      AppError::InvalidFields(vec!["hash".to_string()]),
+      AppError::PreconditionFailed,
+      AppError::InvalidPatch(vec!["/name: expected a string".to_string()]),
+      AppError::FieldNotPermitted("role".to_string()),
+      AppError::UnknownGrant("users.fly".to_string()),
+      AppError::AlreadyErased,
+      AppError::InvalidUsername("is too short".to_string()),
+      AppError::UsernameConfusable,
+      AppError::InvalidStatsRange,
+      AppError::ValidationFailed(vec![]),
+      AppError::MalformedJson("expected value".to_string()),
+      AppError::InvalidJsonData("missing field `name`".to_string()),
+      AppError::MissingJsonContentType,
+      AppError::InvalidPath("invalid UUID".to_string()),
+      AppError::InvalidQuery("invalid digit".to_string()),
+      AppError::RequestRejected { status: StatusCode::PAYLOAD_TOO_LARGE, message: "too large".to_string() },
+      AppError::UnsupportedApiVersion("application/vnd.rust-learn.v9+json".to_string()),
+      AppError::RateLimited(30),
+      AppError::Internal("hashing failed".to_string()),
+    ]
+  }
+
+  /// Fails to compile when a variant is added, add a sample for it above
+  #[allow(dead_code)]
+  fn sampled(error: &AppError) {
+    match error {
+      AppError::PrismaError(_)
+      | AppError::RecordNotFound
+      | AppError::RecordExisted
+      | AppError::WrongCredentials
+      | AppError::JWTTokenInvalid
+      | AppError::PasswordDontMatch
+      | AppError::OperationConflict
+      | AppError::BulkTargetMissing
+      | AppError::BulkLimitExceeded
+      | AppError::InvalidImportFile
+      | AppError::ExportFailed
+      | AppError::InvalidStatusTransition { .. }
+      | AppError::UnknownUserStatus(_)
+      | AppError::AccountNotActive(_)
+      | AppError::PermissionDenied
+      | AppError::InvalidFields(_)
+      | AppError::PreconditionFailed
+      | AppError::InvalidPatch(_)
+      | AppError::FieldNotPermitted(_)
+      | AppError::UnknownGrant(_)
+      | AppError::AlreadyErased
+      | AppError::InvalidUsername(_)
+      | AppError::UsernameConfusable
+      | AppError::InvalidStatsRange
+      | AppError::ValidationFailed(_)
+      | AppError::MalformedJson(_)
+      | AppError::InvalidJsonData(_)
+      | AppError::MissingJsonContentType
+      | AppError::InvalidPath(_)
+      | AppError::InvalidQuery(_)
+      | AppError::RequestRejected { .. }
+      | AppError::UnsupportedApiVersion(_)
+      | AppError::RateLimited(_)
+      | AppError::Internal(_) => (),
+    }
   }
-}
\ No newline at end of file
+
+  /// Every code the server can send
+  fn codes() -> Vec<&'static str> {
+    let mut codes: Vec<&str> = samples().iter().map(|error| error.code()).collect();
+    codes.extend(DATABASE_ERROR_CODES);
+    codes.sort();
+    codes.dedup();
+    codes
+  }
+
+  #[test]
+  fn every_error_has_a_message_in_every_locale() {
+    let codes = codes();
+
+    for locale in i18n::SUPPORTED_LOCALES.iter() {
+      for code in codes.iter() {
+        let key = message_key(code);
+        assert!(
+          i18n::lookup(locale, &key, &[]).is_some(),
+          "{} has no message {}",
+          locale,
+          key
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn client_error_codes_match_the_server() {
+    let codes = codes();
+
+    for code in codes.iter() {
+      assert_ne!(ErrorCode::parse(code), ErrorCode::Other(code.to_string()), "client does not know {}", code);
+    }
+    assert_eq!(codes.len(), ErrorCode::ALL.len(), "client knows codes the server never sends");
+  }
+
+  #[test]
+  fn existing_records_are_a_conflict() {
+    // A unique violation is reported the same way, see `query_error_kind`
+    assert_eq!(AppError::RecordExisted.code(), "resource.exists");
+    assert!(DATABASE_ERROR_CODES.contains(&"resource.exists"));
+    assert_eq!(AppError::RecordExisted.into_response().status(), StatusCode::CONFLICT);
+  }
+
+  #[test]
+  fn messages_follow_the_request_locale() {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let message = |locale: &str| {
+      let locale = i18n::negotiate(None, Some(locale));
+      runtime.block_on(i18n::with_locale(locale, async {
+        i18n::translate(&message_key(AppError::WrongCredentials.code()), &[])
+      }))
+    };
+
+    assert_eq!(message("en-US"), "Username/password incorrect");
+    assert_ne!(message("zh-CN,zh;q=0.9"), message("en-US"));
+    assert_eq!(message("fr-FR"), message("en-US"));
+  }
+}
@@ -3,13 +3,21 @@ extern crate dotenv_codegen;
 extern crate dotenv;
 
 use dotenv::dotenv;
+use std::net::SocketAddr;
 
 mod app;
 mod db;
 mod error;
+mod response;
 mod routes;
 mod utils;
 mod middlewares;
+mod models;
+
+#[cfg(test)]
+mod contract_tests;
+#[cfg(test)]
+mod client_tests;
 
 #[tokio::main]
 async fn main() {
@@ -21,7 +29,8 @@ async fn main() {
 
     // run it with hyper on $HOST:$PORT (from .env file)
     axum::Server::bind(&format!("{}:{}", dotenv!("HOST"), dotenv!("PORT")).parse().unwrap())
-        .serve(app.into_make_service())
+        // Keep the peer address around, the audit log records it
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .unwrap();
 }
\ No newline at end of file
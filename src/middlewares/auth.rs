@@ -1,34 +1,65 @@
 use axum::{
-    http::Request,
+    http::{header, Request},
     response::Response,
     middleware::Next,
 };
 use axum_extra::extract::cookie::{CookieJar};
-use crate::utils::jwt::verify;
+use std::sync::Arc;
+use crate::db::{self, user};
+use crate::models::user_status::{UserStatus, UserStatusExt};
+use crate::utils::jwt::{verify, Claims, JwtSecret};
 use crate::error::{AppError};
 
 /* 
   Middleware Example:
     User can query API's with cookie (or you can use redis for session here as well)
-    and store the User Id in context which can be used in request handler
+    and store the User Id in context which can be used in request handler.
+    Clients that don't keep cookies send the same token as `Authorization: Bearer`.
 */
 
+/// Token of an `Authorization: Bearer <token>` header
+pub fn bearer_token<B>(req: &Request<B>) -> Option<String> {
+  req
+    .headers()
+    .get(header::AUTHORIZATION)?
+    .to_str()
+    .ok()?
+    .strip_prefix("Bearer ")
+    .map(|token| token.trim().to_string())
+}
+
 pub async fn auth_middleware<B>(
   cookie_jar: CookieJar,
   mut req: Request<B>,
   next: Next<B>,
 ) -> Result<Response, AppError> {
-  if let Some(user_token) = cookie_jar.get("user") {
-    // Note: Print user's cookie
-    tracing::info!("User's Cookie: {}", &user_token.value());
+  let user_token = cookie_jar
+      .get("user")
+      .map(|cookie| cookie.value().to_string())
+      .or_else(|| bearer_token(&req));
+
+  if let Some(user_token) = user_token {
+    // Note: Print user's token
+    tracing::info!("User's Token: {}", &user_token);
 
-    match verify(&user_token.value()) {
+    let jwt_secret = req
+        .extensions()
+        .get::<JwtSecret>()
+        .cloned()
+        .expect("JwtSecret extension is missing");
+    match verify(&jwt_secret, &user_token) {
       Ok(claims) => {
         tracing::info!("Logged User's Id: {}", claims.sub);
+        let db = req
+            .extensions()
+            .get::<Arc<db::PrismaClient>>()
+            .cloned()
+            .expect("PrismaClient extension is missing");
+        check_account(&db, &claims).await?;
         req.extensions_mut().insert(claims);
       }
       Err(_e) => {
-        tracing::info!("Cookie Validate failed");
+        tracing::info!("Token Validate failed");
         return Err(AppError::JWTTokenInvalid)
       }
     };
@@ -36,4 +67,42 @@ pub async fn auth_middleware<B>(
     return Err(AppError::JWTTokenInvalid)
   }
   Ok(next.run(req).await)
+}
+
+user::select!(user_account_select {
+  status
+  erased_at
+  tokens_revoked_at
+});
+
+/// Reject tokens of deleted or erased users and tokens issued before the user's
+/// sessions were revoked. A token outlives a suspension or ban, so the status is
+/// checked on every request, the same way login checks it
+async fn check_account(db: &db::PrismaClient, claims: &Claims) -> Result<(), AppError> {
+  let user_obj = db
+      .user()
+      .find_unique(user::id::equals(claims.sub.to_string()))
+      .select(user_account_select::select())
+      .exec()
+      .await?
+      .ok_or(AppError::JWTTokenInvalid)?;
+
+  if user_obj.erased_at.is_some() {
+    tracing::info!("Token belongs to an erased user");
+    return Err(AppError::JWTTokenInvalid)
+  }
+
+  if let Some(revoked_at) = user_obj.tokens_revoked_at {
+    if claims.issued_at_ms() <= revoked_at.timestamp_millis() {
+      tracing::info!("Token was revoked");
+      return Err(AppError::JWTTokenInvalid)
+    }
+  }
+
+  let status = UserStatus::from_db(user_obj.status)?;
+  if status != UserStatus::Active {
+    tracing::info!("Account is {:?}", status);
+    return Err(AppError::AccountNotActive(status))
+  }
+  Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,29 @@
+use axum::{
+    http::{header, HeaderValue, Request},
+    response::Response,
+    middleware::Next,
+};
+use axum_extra::extract::cookie::{CookieJar};
+use crate::utils::i18n;
+
+/// Cookie holding the user's language preference, e.g. `zh-CN`
+pub const LANG_COOKIE: &str = "lang";
+
+/// Choose the locale of the request, messages built while handling it use it
+pub async fn locale_middleware<B>(
+  cookie_jar: CookieJar,
+  req: Request<B>,
+  next: Next<B>,
+) -> Response {
+  let accept_language = req
+      .headers()
+      .get(header::ACCEPT_LANGUAGE)
+      .and_then(|value| value.to_str().ok());
+  let locale = i18n::negotiate(cookie_jar.get(LANG_COOKIE).map(|cookie| cookie.value()), accept_language);
+
+  let mut response = i18n::with_locale(locale.clone(), next.run(req)).await;
+  if let Ok(value) = HeaderValue::from_str(&locale.to_string()) {
+    response.headers_mut().insert(header::CONTENT_LANGUAGE, value);
+  }
+  response
+}
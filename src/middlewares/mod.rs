@@ -1 +1,6 @@
-pub mod auth;
\ No newline at end of file
+pub mod auth;
+pub mod problem;
+pub mod locale;
+pub mod panic;
+pub mod version;
+pub mod rate_limit;
\ No newline at end of file
@@ -0,0 +1,37 @@
+use std::any::Any;
+use axum::{
+    http::Request,
+    response::{IntoResponse, Response},
+    middleware::Next,
+};
+use tracing::Instrument;
+use crate::error::AppError;
+
+/// Run the rest of the request in a span carrying its `X-Request-Id`, so
+/// whatever is logged while handling it can be matched with the request
+pub async fn request_span_middleware<B>(
+  req: Request<B>,
+  next: Next<B>,
+) -> Response {
+  let request_id = req
+      .headers()
+      .get("x-request-id")
+      .and_then(|value| value.to_str().ok())
+      .unwrap_or_default()
+      .to_string();
+  let span = tracing::info_span!("request", request_id = %request_id);
+  next.run(req).instrument(span).await
+}
+
+/// Answer for a handler that panicked, for `CatchPanicLayer::custom`. The
+/// panic is logged, the client gets the usual 500 envelope
+pub fn panic_response(panic: Box<dyn Any + Send + 'static>) -> Response {
+  let detail = if let Some(message) = panic.downcast_ref::<String>() {
+    message.clone()
+  } else if let Some(message) = panic.downcast_ref::<&str>() {
+    message.to_string()
+  } else {
+    "unknown panic payload".to_string()
+  };
+  AppError::Internal(format!("Handler panicked: {}", detail)).into_response()
+}
@@ -0,0 +1,57 @@
+use axum::{
+    body::{boxed, Full},
+    http::{header, HeaderMap, HeaderValue, Request},
+    response::Response,
+    middleware::Next,
+};
+use crate::response::ProblemDetails;
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// True when `Accept` lists `application/problem+json` without `q=0`
+fn accepts_problem_json(headers: &HeaderMap) -> bool {
+  headers
+    .get_all(header::ACCEPT)
+    .iter()
+    .filter_map(|value| value.to_str().ok())
+    .flat_map(|value| value.split(','))
+    .any(|media_range| {
+      let mut params = media_range.split(';').map(|param| param.trim());
+      let media_type = params.next().unwrap_or_default();
+      let refused = params.any(|param| {
+        param
+          .strip_prefix("q=")
+          .and_then(|quality| quality.trim().parse::<f32>().ok())
+          .map_or(false, |quality| quality == 0.0)
+      });
+      media_type.eq_ignore_ascii_case(PROBLEM_JSON) && !refused
+    })
+}
+
+/// Send errors as RFC 7807 documents to clients that accept them, everybody
+/// else keeps the `{code, message, data}` envelope
+pub async fn problem_json_middleware<B>(
+  req: Request<B>,
+  next: Next<B>,
+) -> Response {
+  let wants_problem = accepts_problem_json(req.headers());
+  let response = next.run(req).await;
+  if !wants_problem {
+    return response
+  }
+
+  let problem = match response.extensions().get::<ProblemDetails>() {
+    Some(problem) => problem.clone(),
+    None => return response,
+  };
+  let body = match serde_json::to_vec(&problem) {
+    Ok(body) => body,
+    Err(_) => return response,
+  };
+
+  // Keep the status and the headers set so far, e.g. cookies
+  let (mut parts, _) = response.into_parts();
+  parts.headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+  parts.headers.remove(header::CONTENT_LENGTH);
+  Response::from_parts(parts, boxed(Full::from(body)))
+}
@@ -0,0 +1,194 @@
+use axum::{
+    extract::{MatchedPath, State},
+    http::{HeaderMap, HeaderName, HeaderValue, Request, header},
+    response::{IntoResponse, Response},
+    middleware::Next,
+};
+use axum_extra::extract::cookie::CookieJar;
+use chrono::Utc;
+use std::sync::Arc;
+use crate::error::AppError;
+use crate::middlewares::auth::bearer_token;
+use crate::routes::registry::ApiVersion;
+use crate::utils::client_ip::client_ip;
+use crate::utils::jwt::{verify, JwtSecret};
+use crate::utils::rate_limit::{Decision, KeyKind, RateLimiter};
+
+/// Whose bucket a request is counted against, see `KeyKind`
+fn bucket_key<B>(limiter: &RateLimiter, kind: KeyKind, req: &Request<B>) -> String {
+  let user = || {
+    let token = CookieJar::from_headers(req.headers())
+      .get("user")
+      .map(|cookie| cookie.value().to_string())
+      .or_else(|| bearer_token(req))?;
+    // Only a signed token counts, anything else could pick a fresh bucket per request
+    let jwt_secret = req.extensions().get::<JwtSecret>()?;
+    verify(jwt_secret, &token).ok().map(|claims| format!("user:{}", claims.sub))
+  };
+  let api_key = || {
+    let api_key = req.headers().get("x-api-key")?.to_str().ok()?;
+    // Only a configured key counts, anything else could pick a fresh bucket per request
+    limiter.api_key_name(api_key).map(|name| format!("api_key:{}", name))
+  };
+
+  let key = match kind {
+    KeyKind::Ip => None,
+    KeyKind::User => user(),
+    KeyKind::ApiKey => api_key(),
+  };
+  key.unwrap_or_else(|| {
+    let ip = client_ip(req.headers(), req.extensions(), limiter.trusted_proxies);
+    format!("ip:{}", ip.unwrap_or_else(|| "unknown".to_string()))
+  })
+}
+
+/// Whole seconds, rounded up so clients never retry too early
+fn seconds(ms: i64) -> i64 {
+  (ms + 999) / 1000
+}
+
+fn insert_rate_limit_headers(headers: &mut HeaderMap, decision: &Decision, period_secs: u64) {
+  let values = [
+    ("ratelimit-limit", decision.limit.to_string()),
+    ("ratelimit-remaining", decision.remaining.to_string()),
+    ("ratelimit-reset", seconds(decision.reset_ms).to_string()),
+    ("ratelimit-policy", format!("{};w={}", decision.limit, period_secs)),
+  ];
+  for (name, value) in values {
+    if let Ok(value) = HeaderValue::from_str(&value) {
+      headers.insert(HeaderName::from_static(name), value);
+    }
+  }
+  if !decision.allowed {
+    headers.insert(header::RETRY_AFTER, HeaderValue::from(seconds(decision.retry_after_ms)));
+  }
+}
+
+/// Count the request against the quota of its route, refusing it with 429 once
+/// the quota is used up. Responses carry the `RateLimit-*` headers of the bucket
+pub async fn rate_limit_middleware<B>(
+  State((version, limiter)): State<(ApiVersion, Arc<RateLimiter>)>,
+  req: Request<B>,
+  next: Next<B>,
+) -> Response {
+  let path = req
+    .extensions()
+    .get::<MatchedPath>()
+    .map(|matched_path| matched_path.as_str())
+    .map(|path| path.strip_prefix(version.prefix()).unwrap_or(path).to_string());
+  let route_quota = match path.and_then(|path| limiter.quota_for(req.method().as_str(), &path)) {
+    Some(route_quota) => route_quota,
+    None => return next.run(req).await,
+  };
+
+  let key = format!("{}|{}", route_quota.bucket_prefix(), bucket_key(&limiter, route_quota.key, &req));
+  let decision = match limiter.acquire(&key, route_quota.quota, Utc::now().timestamp_millis()).await {
+    Ok(decision) => decision,
+    Err(e) => {
+      // An unreachable store must not take the API down with it
+      tracing::error!("Rate limit skipped: {}", e);
+      return next.run(req).await
+    }
+  };
+
+  let mut response = if decision.allowed {
+    next.run(req).await
+  } else {
+    tracing::info!("Rate limit exceeded for {}", key);
+    AppError::RateLimited(seconds(decision.retry_after_ms) as u64).into_response()
+  };
+  insert_rate_limit_headers(response.headers_mut(), &decision, route_quota.quota.period_secs);
+  response
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use axum::{body::Body, extract::ConnectInfo, http::StatusCode, middleware, routing::post, Router};
+  use std::net::SocketAddr;
+  use tower::ServiceExt;
+  use crate::utils::rate_limit::{parse_api_keys, parse_quotas, MemoryStore};
+
+  fn router(quotas: &str) -> Router {
+    let limiter = RateLimiter {
+      api_keys: parse_api_keys("partner=partner-key").unwrap(),
+      ..RateLimiter::new(parse_quotas(quotas).unwrap(), Box::new(MemoryStore::default()))
+    };
+    let limiter = Arc::new(limiter);
+    let version = ApiVersion::V1;
+    Router::new().nest(
+      version.prefix(),
+      Router::new()
+        .route("/login", post(|| async { "ok" }))
+        .route("/register", post(|| async { "ok" }))
+        .route_layer(middleware::from_fn_with_state((version, limiter), rate_limit_middleware)),
+    )
+  }
+
+  async fn send(router: &Router, path: &str, ip: &str) -> Response {
+    let req = Request::post(format!("/api/v1{}", path))
+      .extension(ConnectInfo(format!("{}:4000", ip).parse::<SocketAddr>().unwrap()))
+      .body(Body::empty())
+      .unwrap();
+    router.clone().oneshot(req).await.unwrap()
+  }
+
+  #[tokio::test]
+  async fn quotas_are_enforced_per_route_and_client() {
+    let router = router("POST /login=2/60:ip; *=100/60:user");
+
+    let response = send(&router, "/login", "10.0.0.1").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["ratelimit-limit"], "2");
+    assert_eq!(response.headers()["ratelimit-remaining"], "1");
+    assert_eq!(response.headers()["ratelimit-policy"], "2;w=60");
+
+    assert_eq!(send(&router, "/login", "10.0.0.1").await.status(), StatusCode::OK);
+    let refused = send(&router, "/login", "10.0.0.1").await;
+    assert_eq!(refused.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(refused.headers()["ratelimit-remaining"], "0");
+    assert_eq!(refused.headers()[header::RETRY_AFTER], "30");
+    let body = hyper::body::to_bytes(refused.into_body()).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["code"], "request.rate_limited");
+
+    // Another client, and another route of the same client, are not affected
+    assert_eq!(send(&router, "/login", "10.0.0.2").await.status(), StatusCode::OK);
+    let response = send(&router, "/register", "10.0.0.1").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["ratelimit-limit"], "100");
+  }
+
+  #[tokio::test]
+  async fn forwarded_for_is_ignored_unless_trusted() {
+    let router = router("POST /login=1/60:ip");
+    let forwarded = |ip: &str| Request::post("/api/v1/login")
+      .header("x-forwarded-for", ip)
+      .body(Body::empty())
+      .unwrap();
+
+    assert_eq!(router.clone().oneshot(forwarded("10.0.0.1")).await.unwrap().status(), StatusCode::OK);
+    let response = router.clone().oneshot(forwarded("10.0.0.2")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+  }
+
+  #[tokio::test]
+  async fn only_configured_api_keys_get_their_own_bucket() {
+    let router = router("POST /login=1/60:api_key");
+    let with_key = |ip: &str, api_key: &str| Request::post("/api/v1/login")
+      .extension(ConnectInfo(format!("{}:4000", ip).parse::<SocketAddr>().unwrap()))
+      .header("x-api-key", api_key)
+      .body(Body::empty())
+      .unwrap();
+
+    // A known key is counted by its name, wherever it comes from
+    assert_eq!(router.clone().oneshot(with_key("10.0.0.1", "partner-key")).await.unwrap().status(), StatusCode::OK);
+    let response = router.clone().oneshot(with_key("10.0.0.2", "partner-key")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // Made up keys are counted by the address, a new key per request doesn't help
+    assert_eq!(router.clone().oneshot(with_key("10.0.0.3", "made-up-1")).await.unwrap().status(), StatusCode::OK);
+    let response = router.clone().oneshot(with_key("10.0.0.3", "made-up-2")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+  }
+}
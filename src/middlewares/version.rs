@@ -0,0 +1,105 @@
+use axum::{
+    body::Body,
+    extract::{MatchedPath, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Request, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    middleware::Next,
+    Router,
+};
+use tower::ServiceExt;
+use crate::error::AppError;
+use crate::routes::registry::{self, ApiVersion, Deprecation, BARE_PATH_DEPRECATION};
+
+/// Vendor media type selecting a version, e.g. `application/vnd.rust-learn.v1+json`
+pub const VERSION_MEDIA_TYPE_PREFIX: &str = "application/vnd.rust-learn.v";
+const VERSION_MEDIA_TYPE_SUFFIX: &str = "+json";
+
+/// Version named by `Accept`, `None` when it names none
+fn requested_version(headers: &HeaderMap) -> Result<Option<ApiVersion>, AppError> {
+  let media_type = headers
+    .get_all(header::ACCEPT)
+    .iter()
+    .filter_map(|value| value.to_str().ok())
+    .flat_map(|value| value.split(','))
+    .filter_map(|media_range| media_range.split(';').next())
+    .map(|media_type| media_type.trim())
+    .find(|media_type| {
+      media_type
+        .get(..VERSION_MEDIA_TYPE_PREFIX.len())
+        .map_or(false, |prefix| prefix.eq_ignore_ascii_case(VERSION_MEDIA_TYPE_PREFIX))
+    });
+  let media_type = match media_type {
+    Some(media_type) => media_type,
+    None => return Ok(None),
+  };
+
+  media_type[VERSION_MEDIA_TYPE_PREFIX.len()..]
+    .strip_suffix(VERSION_MEDIA_TYPE_SUFFIX)
+    .and_then(|number| number.parse().ok())
+    .and_then(ApiVersion::from_number)
+    .map(Some)
+    .ok_or_else(|| AppError::UnsupportedApiVersion(media_type.to_string()))
+}
+
+fn insert_deprecation(headers: &mut HeaderMap, deprecation: &Deprecation) {
+  if let Ok(value) = HeaderValue::from_str(&format!("@{}", deprecation.since)) {
+    headers.insert(HeaderName::from_static("deprecation"), value);
+  }
+  headers.insert(HeaderName::from_static("sunset"), HeaderValue::from_static(deprecation.sunset));
+}
+
+/// Announce the deprecation of routes marked so in the registry
+pub async fn deprecation_middleware<B>(
+  State(version): State<ApiVersion>,
+  req: Request<B>,
+  next: Next<B>,
+) -> Response {
+  let deprecation = req
+    .extensions()
+    .get::<MatchedPath>()
+    .map(|matched_path| matched_path.as_str())
+    .map(|path| path.strip_prefix(version.prefix()).unwrap_or(path))
+    .and_then(|path| registry::find(version, req.method().as_str(), path))
+    .and_then(|route| route.deprecation);
+
+  let mut response = next.run(req).await;
+  if let Some(deprecation) = deprecation {
+    insert_deprecation(response.headers_mut(), &deprecation);
+  }
+  response
+}
+
+/// Serve a path without version prefix from the version `Accept` names.
+/// Without one it is an alias of the latest version, kept until
+/// `BARE_PATH_DEPRECATION` and announced as deprecated
+pub async fn serve_bare_path(api: Router, mut req: Request<Body>) -> Response {
+  let requested = match requested_version(req.headers()) {
+    Ok(requested) => requested,
+    Err(error) => return error.into_response(),
+  };
+  let version = requested.unwrap_or(ApiVersion::LATEST);
+
+  let path_and_query = req.uri().path_and_query().map_or("/", |path_and_query| path_and_query.as_str());
+  let versioned_path = format!("{}{}", version.prefix(), path_and_query);
+  let uri = match versioned_path.parse::<Uri>() {
+    Ok(uri) => uri,
+    Err(error) => return AppError::Internal(format!("Rewriting {} failed: {}", versioned_path, error)).into_response(),
+  };
+  *req.uri_mut() = uri;
+
+  let mut response = match api.oneshot(req).await {
+    Ok(response) => response,
+    Err(infallible) => match infallible {},
+  };
+  if requested.is_none() && response.status() != StatusCode::NOT_FOUND {
+    let path = versioned_path.split('?').next().unwrap_or_default();
+    // A deprecated route keeps its own dates
+    if !response.headers().contains_key("deprecation") {
+      insert_deprecation(response.headers_mut(), &BARE_PATH_DEPRECATION);
+    }
+    if let Ok(link) = HeaderValue::from_str(&format!("<{}>; rel=\"successor-version\"", path)) {
+      response.headers_mut().insert(header::LINK, link);
+    }
+  }
+  response
+}
@@ -0,0 +1,4 @@
+pub mod user_status;
+pub mod role;
+pub mod permission;
+pub mod user_view;
\ No newline at end of file
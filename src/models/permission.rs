@@ -0,0 +1,17 @@
+use crate::models::role::RoleExt;
+// Shared with the client crate, which only knows the values
+pub use rust_learn_client::types::{Grant, Permission};
+
+/// What a `Grant` hands out
+pub trait GrantExt {
+  fn permissions(&self) -> Vec<Permission>;
+}
+
+impl GrantExt for Grant {
+  fn permissions(&self) -> Vec<Permission> {
+    match self {
+      Grant::Role(role) => role.permissions().to_vec(),
+      Grant::Permission(permission) => vec![*permission],
+    }
+  }
+}
@@ -0,0 +1,26 @@
+use std::str::FromStr;
+use crate::models::permission::Permission;
+// Shared with the client crate, which only knows the values
+pub use rust_learn_client::types::Role;
+
+/// What a `Role` allows and how it is read from `User.role`
+pub trait RoleExt {
+  /// Permissions that come with the role, on top of direct and group grants
+  fn permissions(&self) -> &'static [Permission];
+
+  /// Unknown values fall back to the least privileged role
+  fn from_db(value: &str) -> Role;
+}
+
+impl RoleExt for Role {
+  fn permissions(&self) -> &'static [Permission] {
+    match self {
+      Role::User => &[],
+      Role::Admin => &Permission::ALL,
+    }
+  }
+
+  fn from_db(value: &str) -> Role {
+    Role::from_str(value).unwrap_or(Role::User)
+  }
+}
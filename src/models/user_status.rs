@@ -0,0 +1,66 @@
+// Shared with the client crate, which only knows the values
+pub use rust_learn_client::types::UserStatus;
+use crate::error::AppError;
+
+/// What the server allows of a `UserStatus` and how it is stored as
+/// `User.status` (TinyInt)
+pub trait UserStatusExt: Sized {
+  /// Statuses an account may move to from `self`
+  fn allowed_transitions(&self) -> &'static [UserStatus];
+
+  fn can_transition_to(&self, next: UserStatus) -> bool;
+
+  /// Check a transition, returning a descriptive error when it is not allowed
+  fn transition_to(&self, next: UserStatus) -> Result<UserStatus, AppError>;
+
+  /// Column value. `Active` is 0 so that the column default and existing rows
+  /// keep their meaning
+  fn to_db(&self) -> i32;
+
+  fn from_db(value: i32) -> Result<Self, AppError>;
+}
+
+impl UserStatusExt for UserStatus {
+  /// `PendingDeletion` is only entered through a deletion request, which needs
+  /// the user's password
+  fn allowed_transitions(&self) -> &'static [UserStatus] {
+    match self {
+      UserStatus::Pending => &[UserStatus::Active, UserStatus::Deactivated],
+      UserStatus::Active => &[UserStatus::Suspended, UserStatus::Banned, UserStatus::Deactivated],
+      UserStatus::Suspended => &[UserStatus::Active, UserStatus::Banned, UserStatus::Deactivated],
+      UserStatus::Banned => &[UserStatus::Active, UserStatus::Deactivated],
+      UserStatus::Deactivated => &[UserStatus::Active],
+      UserStatus::PendingDeletion => &[UserStatus::Active],
+    }
+  }
+
+  fn can_transition_to(&self, next: UserStatus) -> bool {
+    self.allowed_transitions().contains(&next)
+  }
+
+  fn transition_to(&self, next: UserStatus) -> Result<UserStatus, AppError> {
+    if self.can_transition_to(next) {
+      Ok(next)
+    } else {
+      Err(AppError::InvalidStatusTransition { from: *self, to: next })
+    }
+  }
+
+  fn to_db(&self) -> i32 {
+    match self {
+      UserStatus::Active => 0,
+      UserStatus::Pending => 1,
+      UserStatus::Suspended => 2,
+      UserStatus::Banned => 3,
+      UserStatus::Deactivated => 4,
+      UserStatus::PendingDeletion => 5,
+    }
+  }
+
+  fn from_db(value: i32) -> Result<Self, AppError> {
+    UserStatus::ALL
+      .into_iter()
+      .find(|status| status.to_db() == value)
+      .ok_or(AppError::UnknownUserStatus(value))
+  }
+}
@@ -0,0 +1,299 @@
+use prisma_client_rust::{Selection, SelectType};
+use serde::Deserialize;
+use crate::db::{self, user};
+use crate::error::{AppError, AppResult};
+use crate::models::permission::Permission;
+use crate::models::role::{Role, RoleExt};
+use crate::models::user_status::{UserStatus, UserStatusExt};
+use crate::utils::jwt::Claims;
+use crate::utils::permission::Permissions;
+pub use rust_learn_client::types::{UserAdmin, UserPartial, UserPublic, UserSelf, UserView};
+
+/*
+  Views of a user record returned by the API. Each view selects only its own
+  columns, the password hash is never fetched for a response.
+*/
+user::select!(user_public_select {
+  id
+  name
+});
+
+user::select!(user_self_select {
+  id
+  name
+  status
+});
+
+user::select!(user_admin_select {
+  id
+  name
+  status
+  role
+});
+
+/// Fields a client can ask for with `fields=`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserField {
+    Id,
+    Name,
+    Status,
+    Role,
+}
+
+impl UserField {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      UserField::Id => "id",
+      UserField::Name => "name",
+      UserField::Status => "status",
+      UserField::Role => "role",
+    }
+  }
+
+  fn to_selection(self) -> Selection {
+    let select_param = match self {
+      UserField::Id => user::SelectParam::Id(user::id::Select),
+      UserField::Name => user::SelectParam::Name(user::name::Select),
+      UserField::Status => user::SelectParam::Status(user::status::Select),
+      UserField::Role => user::SelectParam::Role(user::role::Select),
+    };
+    select_param.to_selection()
+  }
+}
+
+/// Row returned by a `fields=` selection, unselected columns are missing
+#[derive(Deserialize)]
+pub struct UserPartialRow {
+    id: Option<String>,
+    name: Option<String>,
+    status: Option<i32>,
+    role: Option<String>,
+}
+
+impl TryFrom<UserPartialRow> for UserPartial {
+  type Error = AppError;
+
+  fn try_from(row: UserPartialRow) -> Result<Self, Self::Error> {
+    Ok(UserPartial {
+      id: row.id,
+      name: row.name,
+      status: row.status.map(UserStatus::try_from).transpose()?,
+      role: row.role.map(|role| Role::from_db(role.as_str())),
+    })
+  }
+}
+
+/// Selection built at runtime from `fields=`, works like the ones `select!` generates
+struct UserFieldsSelection(Vec<Selection>);
+
+impl SelectType for UserFieldsSelection {
+  type Data = UserPartialRow;
+  type ModelData = user::Data;
+
+  fn to_selections(self) -> Vec<Selection> {
+    self.0
+  }
+}
+
+impl From<&[UserField]> for UserFieldsSelection {
+  fn from(fields: &[UserField]) -> Self {
+    UserFieldsSelection(fields.iter().map(|field| field.to_selection()).collect())
+  }
+}
+
+/// Parse a comma separated `fields=` value against what the view allows
+pub fn parse_fields(fields: &str, kind: UserViewKind) -> AppResult<Vec<UserField>> {
+  let allowed = kind.allowed_fields();
+  let mut parsed: Vec<UserField> = vec![];
+  let mut invalid: Vec<String> = vec![];
+
+  for name in fields.split(',').map(|name| name.trim()).filter(|name| !name.is_empty()) {
+    match allowed.iter().find(|field| field.as_str() == name) {
+      Some(field) if !parsed.contains(field) => parsed.push(*field),
+      Some(_) => {}
+      None => invalid.push(name.to_string()),
+    }
+  }
+
+  if !invalid.is_empty() || parsed.is_empty() {
+    return Err(AppError::InvalidFields(invalid))
+  }
+
+  Ok(parsed)
+}
+
+impl From<user_public_select::Data> for UserPublic {
+  fn from(data: user_public_select::Data) -> Self {
+    UserPublic { id: data.id, name: data.name }
+  }
+}
+
+impl TryFrom<user_self_select::Data> for UserSelf {
+  type Error = AppError;
+
+  fn try_from(data: user_self_select::Data) -> Result<Self, Self::Error> {
+    Ok(UserSelf {
+      id: data.id,
+      name: data.name,
+      status: UserStatus::from_db(data.status)?,
+    })
+  }
+}
+
+impl TryFrom<user_admin_select::Data> for UserAdmin {
+  type Error = AppError;
+
+  fn try_from(data: user_admin_select::Data) -> Result<Self, Self::Error> {
+    Ok(UserAdmin {
+      id: data.id,
+      name: data.name,
+      status: UserStatus::from_db(data.status)?,
+      role: Role::from_db(data.role.as_str()),
+    })
+  }
+}
+
+/// Login already holds the full record to check the password
+impl TryFrom<&user::Data> for UserSelf {
+  type Error = AppError;
+
+  fn try_from(data: &user::Data) -> Result<Self, Self::Error> {
+    Ok(UserSelf {
+      id: data.id.clone(),
+      name: data.name.clone(),
+      status: UserStatus::from_db(data.status)?,
+    })
+  }
+}
+
+/// Which view a caller gets, decided by the caller's permissions and the target user
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UserViewKind {
+    Admin,
+    Owner,
+    Public,
+}
+
+impl UserViewKind {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      UserViewKind::Admin => "admin",
+      UserViewKind::Owner => "owner",
+      UserViewKind::Public => "public",
+    }
+  }
+
+  /// Allowlist of `fields=` values, the same columns the full view returns
+  pub fn allowed_fields(&self) -> &'static [UserField] {
+    match self {
+      UserViewKind::Admin => &[UserField::Id, UserField::Name, UserField::Status, UserField::Role],
+      UserViewKind::Owner => &[UserField::Id, UserField::Name, UserField::Status],
+      UserViewKind::Public => &[UserField::Id, UserField::Name],
+    }
+  }
+
+  /// `target_id` is `None` for lists, where nobody gets the self view
+  pub fn for_caller(
+    permissions: &Permissions,
+    claims: &Claims,
+    target_id: Option<&str>,
+  ) -> UserViewKind {
+    if permissions.contains(Permission::UsersReadPrivate) {
+      return UserViewKind::Admin
+    }
+
+    match target_id {
+      Some(target_id) if claims.sub.to_string().eq(target_id) => UserViewKind::Owner,
+      _ => UserViewKind::Public,
+    }
+  }
+}
+
+/// Fetch a single user through the given view, or only `fields` of it
+pub async fn find_user_view(
+  db: &db::PrismaClient,
+  user_id: String,
+  kind: UserViewKind,
+  fields: Option<&[UserField]>,
+) -> AppResult<Option<UserView>> {
+  let query = db.user().find_unique(user::id::equals(user_id));
+
+  if let Some(fields) = fields {
+    return query
+        .select(UserFieldsSelection::from(fields))
+        .exec()
+        .await?
+        .map(|row| UserPartial::try_from(row).map(UserView::Partial))
+        .transpose()
+  }
+
+  let user_view = match kind {
+    UserViewKind::Admin => query
+        .select(user_admin_select::select())
+        .exec()
+        .await?
+        .map(|data| UserAdmin::try_from(data).map(UserView::Admin))
+        .transpose()?,
+    UserViewKind::Owner => query
+        .select(user_self_select::select())
+        .exec()
+        .await?
+        .map(|data| UserSelf::try_from(data).map(UserView::Owner))
+        .transpose()?,
+    UserViewKind::Public => query
+        .select(user_public_select::select())
+        .exec()
+        .await?
+        .map(|data| UserView::Public(UserPublic::from(data))),
+  };
+
+  Ok(user_view)
+}
+
+/// Fetch a page of users through the given view, or only `fields` of them
+pub async fn find_users_view(
+  db: &db::PrismaClient,
+  filter: Vec<user::WhereParam>,
+  skip: i64,
+  take: i64,
+  kind: UserViewKind,
+  fields: Option<&[UserField]>,
+) -> AppResult<Vec<UserView>> {
+  let query = db.user().find_many(filter).take(take).skip(skip);
+
+  if let Some(fields) = fields {
+    return query
+        .select(UserFieldsSelection::from(fields))
+        .exec()
+        .await?
+        .into_iter()
+        .map(|row| UserPartial::try_from(row).map(UserView::Partial))
+        .collect()
+  }
+
+  let user_views = match kind {
+    UserViewKind::Admin => query
+        .select(user_admin_select::select())
+        .exec()
+        .await?
+        .into_iter()
+        .map(|data| UserAdmin::try_from(data).map(UserView::Admin))
+        .collect::<AppResult<Vec<UserView>>>()?,
+    UserViewKind::Owner => query
+        .select(user_self_select::select())
+        .exec()
+        .await?
+        .into_iter()
+        .map(|data| UserSelf::try_from(data).map(UserView::Owner))
+        .collect::<AppResult<Vec<UserView>>>()?,
+    UserViewKind::Public => query
+        .select(user_public_select::select())
+        .exec()
+        .await?
+        .into_iter()
+        .map(|data| UserView::Public(UserPublic::from(data)))
+        .collect(),
+  };
+
+  Ok(user_views)
+}
@@ -0,0 +1,187 @@
+use axum::{
+  Json,
+  http::StatusCode,
+  response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::error::{AppError, AppResult};
+use crate::models::permission::Permission;
+use crate::models::user_view::{UserSelf, UserView};
+use crate::routes::account_deletion::DeletionRequestData;
+use crate::routes::admin_stats::AdminStats;
+use crate::routes::auth::NameAvailability;
+use crate::routes::group::{GroupDetail, GroupView};
+use crate::routes::user::BulkUsersData;
+use crate::routes::user_import::ImportUsersData;
+use crate::routes::user_privacy::ErasureReceipt;
+use crate::utils::i18n;
+use crate::utils::validation::FieldError;
+pub use rust_learn_client::types::{
+  Page, Pagination, AuditEventPage, GroupPage, LoginAttemptPage, UserViewPage,
+};
+
+/// Envelope of every JSON response, errors use the same shape
+///
+/// The aliases are the schemas shown in the OpenAPI document, one per kind of `data`.
+#[derive(Serialize, ToSchema, Debug)]
+#[aliases(
+  ErrorResponse = ApiResponse<String>,
+  ValidationErrorResponse = ApiResponse<Vec<FieldError>>,
+  IdResponse = ApiResponse<String>,
+  LoginResponse = ApiResponse<UserSelf>,
+  RegisterAvailabilityResponse = ApiResponse<NameAvailability>,
+  GetUsersAPIResponse = ApiResponse<UserViewPage>,
+  GetUserAPIResponse = ApiResponse<UserView>,
+  PatchUserResponse = ApiResponse<UserView>,
+  BulkUsersResponse = ApiResponse<BulkUsersData>,
+  ImportUsersResponse = ApiResponse<ImportUsersData>,
+  GetAuditEventsResponse = ApiResponse<AuditEventPage>,
+  GetGroupsResponse = ApiResponse<GroupPage>,
+  GroupResponse = ApiResponse<GroupView>,
+  GroupDetailResponse = ApiResponse<GroupDetail>,
+  UserPermissionsResponse = ApiResponse<Vec<Permission>>,
+  EraseUserResponse = ApiResponse<ErasureReceipt>,
+  GetUserLoginsResponse = ApiResponse<LoginAttemptPage>,
+  GetAdminStatsResponse = ApiResponse<AdminStats>,
+  DeletionRequestResponse = ApiResponse<DeletionRequestData>,
+)]
+pub struct ApiResponse<T> {
+    /// `200` on success, a stable error code such as `auth.invalid_credentials` otherwise
+    pub code: String,
+    pub message: String,
+    pub data: T,
+    /// Only set on paginated lists
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pagination: Option<Pagination>,
+}
+
+/// RFC 7807 body of an error, sent as `application/problem+json` when the client accepts it
+#[derive(Serialize, ToSchema, Debug, Clone)]
+pub struct ProblemDetails {
+    /// Always `about:blank`, `code` tells errors apart
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    /// Reason phrase of the status
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    /// Same stable code as in the JSON envelope
+    pub code: String,
+    /// Broken rules, only on validation errors
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errors: Option<Vec<FieldError>>,
+}
+
+impl ProblemDetails {
+  pub fn new(status: StatusCode, code: &str, detail: &str, errors: Option<Vec<FieldError>>) -> Self {
+    ProblemDetails {
+      problem_type: "about:blank".to_string(),
+      title: status.canonical_reason().unwrap_or("Error").to_string(),
+      status: status.as_u16(),
+      detail: detail.to_string(),
+      code: code.to_string(),
+      errors,
+    }
+  }
+}
+
+/// Largest `page_size` a list accepts
+pub const PAGE_SIZE_MAX: i32 = 100;
+
+/// `page` and `page_size` query values with their defaults applied
+#[derive(Debug, Clone, Copy)]
+pub struct PageQuery {
+    pub page: i32,
+    pub page_size: i32,
+}
+
+impl PageQuery {
+  /// Out of range values are rejected rather than clamped, a negative `skip`
+  /// would fail in the database instead of in the request
+  pub fn new(page: Option<i32>, page_size: Option<i32>) -> AppResult<Self> {
+    let page = page.unwrap_or(1);
+    let page_size = page_size.unwrap_or(10);
+    if page < 1 {
+      return Err(AppError::InvalidQuery("page must be at least 1".to_string()))
+    }
+    if !(1..=PAGE_SIZE_MAX).contains(&page_size) {
+      return Err(AppError::InvalidQuery(format!("page_size must be 1 to {}", PAGE_SIZE_MAX)))
+    }
+    Ok(PageQuery { page, page_size })
+  }
+
+  /// In i64, a large `page` times `page_size` overflows i32
+  pub fn skip(&self) -> i64 {
+    i64::from(self.page_size) * (i64::from(self.page) - 1)
+  }
+
+  pub fn take(&self) -> i64 {
+    i64::from(self.page_size)
+  }
+}
+
+impl<T> ApiResponse<T> {
+  /// `message` is a catalog key, translated into the request's locale when sent
+  pub fn new(message: &str, data: T) -> Self {
+    ApiResponse {
+      code: "200".to_string(),
+      message: message.to_string(),
+      data,
+      pagination: None,
+    }
+  }
+
+  pub fn ok(data: T) -> Self {
+    ApiResponse::new("ok", data)
+  }
+}
+
+impl<T> ApiResponse<Page<T>> {
+  pub fn page(list: Vec<T>, count: i64, query: PageQuery) -> Self {
+    // `page_size` is at least 1, see `PageQuery::new`
+    let total_pages = (count + i64::from(query.page_size) - 1) / i64::from(query.page_size);
+    ApiResponse {
+      pagination: Some(Pagination {
+        page: query.page,
+        page_size: query.page_size,
+        total_pages,
+      }),
+      ..ApiResponse::ok(Page { list, count })
+    }
+  }
+}
+
+impl<T: Serialize> IntoResponse for ApiResponse<T> {
+  fn into_response(self) -> Response {
+    let message = i18n::translate(&self.message, &[]);
+    Json(ApiResponse { message, ..self }).into_response()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn page_query_defaults_and_bounds() {
+    let page_query = PageQuery::new(None, None).unwrap();
+    assert_eq!((page_query.page, page_query.page_size), (1, 10));
+    assert_eq!(page_query.skip(), 0);
+    assert_eq!(page_query.take(), 10);
+
+    assert!(matches!(PageQuery::new(Some(0), None), Err(AppError::InvalidQuery(_))));
+    assert!(matches!(PageQuery::new(Some(-3), None), Err(AppError::InvalidQuery(_))));
+    assert!(matches!(PageQuery::new(None, Some(0)), Err(AppError::InvalidQuery(_))));
+    assert!(matches!(PageQuery::new(None, Some(-10)), Err(AppError::InvalidQuery(_))));
+    assert!(matches!(PageQuery::new(None, Some(PAGE_SIZE_MAX + 1)), Err(AppError::InvalidQuery(_))));
+    assert!(PageQuery::new(None, Some(PAGE_SIZE_MAX)).is_ok());
+  }
+
+  #[test]
+  fn page_query_skip_does_not_overflow() {
+    let page_query = PageQuery::new(Some(i32::MAX), Some(PAGE_SIZE_MAX)).unwrap();
+    assert_eq!(page_query.skip(), i64::from(PAGE_SIZE_MAX) * (i64::from(i32::MAX) - 1));
+  }
+}
@@ -0,0 +1,219 @@
+use axum::{
+  routing::post,
+  middleware::{self},
+  Extension,
+  Router,
+};
+use axum_extra::extract::cookie::{CookieJar, Cookie};
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use serde_json::json;
+use std::sync::Arc;
+use crate::db::{self, user};
+use crate::error::{AppError, AppResult};
+use crate::middlewares::auth::auth_middleware;
+use crate::models::user_status::{UserStatus, UserStatusExt};
+use crate::response::ApiResponse;
+use crate::routes::user_privacy::erase_user;
+use crate::utils::extract::Json;
+use crate::utils::audit::{self, AuditAction, AuditContext};
+use crate::utils::jwt::{Claims};
+use crate::utils::password::check_password;
+pub use rust_learn_client::types::{DeletionRequestBody, DeletionRequestData};
+type Database = Extension<std::sync::Arc<db::PrismaClient>>;
+
+/*
+
+Plan for Account Deletion API
+
+/api/users/me/delete-request => POST (password required, cancelled by logging in)
+
+*/
+pub fn create_route() -> Router {
+  Router::new()
+      .route("/users/me/delete-request", post(request_account_deletion_api))
+      .layer(middleware::from_fn(auth_middleware))
+}
+
+/// Days before a requested deletion is carried out, `ACCOUNT_DELETION_GRACE_DAYS` overrides it
+const DELETION_GRACE_DAYS: i64 = 30;
+
+/// How often due deletions are looked for
+const DELETION_SWEEP_INTERVAL_SECS: u64 = 3600;
+
+fn deletion_grace_days() -> i64 {
+  std::env::var("ACCOUNT_DELETION_GRACE_DAYS")
+    .ok()
+    .and_then(|value| value.parse().ok())
+    .unwrap_or(DELETION_GRACE_DAYS)
+}
+
+#[utoipa::path(
+  post,
+  path = "/users/me/delete-request",
+  request_body = DeletionRequestBody,
+  responses(
+      (status = 200, description = "Deletion scheduled, every session is signed out", body = crate::response::DeletionRequestResponse),
+      (status = UNAUTHORIZED, description = "Not Logged In or wrong password", body = crate::error::ErrorResponse),
+      (status = CONFLICT, description = "Only active accounts can be deleted this way", body = crate::error::ErrorResponse)
+  )
+)]
+pub async fn request_account_deletion_api(
+  Extension(claims): Extension<Claims>,
+  db: Database,
+  audit_ctx: AuditContext,
+  cookie_jar: CookieJar,
+  Json(input): Json<DeletionRequestBody>,
+) -> AppResult<(CookieJar, ApiResponse<DeletionRequestData>)> {
+  let user_obj = db
+      .user()
+      .find_unique(user::id::equals(claims.sub.to_string()))
+      .exec()
+      .await?
+      .ok_or(AppError::JWTTokenInvalid)?;
+
+  check_password(&input.password, &user_obj.password)?;
+
+  let status = UserStatus::from_db(user_obj.status)?;
+  if status != UserStatus::Active {
+    return Err(AppError::InvalidStatusTransition { from: status, to: UserStatus::PendingDeletion })
+  }
+
+  let now: DateTime<FixedOffset> = Utc::now().into();
+  let scheduled_for = now + Duration::days(deletion_grace_days());
+
+  db._transaction()
+    .run(|tx| async move {
+      let updated_count = tx
+        .user()
+        .update_many(
+          vec![
+            user::id::equals(user_obj.id.clone()),
+            user::version::equals(user_obj.version),
+          ],
+          vec![
+            user::status::set(UserStatus::PendingDeletion.to_db()),
+            user::deletion_scheduled_at::set(Some(scheduled_for)),
+            // Signs out every session, this one included
+            user::tokens_revoked_at::set(Some(now)),
+            user::version::increment(1),
+          ],
+        )
+        .exec()
+        .await?;
+
+      if updated_count == 0 {
+        return Err(AppError::PreconditionFailed)
+      }
+
+      audit::record(
+        &tx,
+        &audit_ctx,
+        AuditAction::DeletionRequest,
+        Some(user_obj.id.clone()),
+        Some(user_obj.id.clone()),
+        Some(json!({
+          "before": { "status": status },
+          "after": { "status": UserStatus::PendingDeletion },
+          "scheduled_for": scheduled_for,
+        })),
+      ).await?;
+
+      Ok::<(), AppError>(())
+    })
+    .await?;
+
+  let mut removed_cookie = Cookie::named("user");
+  removed_cookie.set_path("/");
+  let new_cookie_jar = cookie_jar.remove(removed_cookie);
+
+  let res_json = ApiResponse::new("deletion-scheduled", DeletionRequestData { scheduled_for });
+
+  Ok((new_cookie_jar, res_json))
+}
+
+/// Move a user whose deletion is pending back to active, called on login. Only
+/// from the version the login read, the sweep may have erased the user since
+pub async fn cancel_account_deletion(
+  db: &db::PrismaClient,
+  audit_ctx: &AuditContext,
+  user_obj: &user::Data,
+) -> AppResult<()> {
+  db._transaction()
+    .run(|tx| async move {
+      let updated_count = tx
+        .user()
+        .update_many(
+          vec![
+            user::id::equals(user_obj.id.clone()),
+            user::version::equals(user_obj.version),
+          ],
+          vec![
+            user::status::set(UserStatus::Active.to_db()),
+            user::deletion_scheduled_at::set(None),
+            user::version::increment(1),
+          ],
+        )
+        .exec()
+        .await?;
+
+      if updated_count == 0 {
+        return Err(AppError::PreconditionFailed)
+      }
+
+      audit::record(
+        &tx,
+        audit_ctx,
+        AuditAction::DeletionCancel,
+        Some(user_obj.id.clone()),
+        Some(user_obj.id.clone()),
+        Some(json!({
+          "before": { "status": UserStatus::PendingDeletion, "scheduled_for": user_obj.deletion_scheduled_at },
+          "after": { "status": UserStatus::Active },
+        })),
+      ).await?;
+
+      Ok::<(), AppError>(())
+    })
+    .await
+}
+
+/// Erase accounts whose grace period is over, runs for the lifetime of the app
+pub async fn run_scheduled_deletions(db: Arc<db::PrismaClient>) {
+  let mut interval = tokio::time::interval(std::time::Duration::from_secs(DELETION_SWEEP_INTERVAL_SECS));
+  loop {
+    interval.tick().await;
+
+    let due_user_objs = db
+        .user()
+        .find_many(vec![
+          user::status::equals(UserStatus::PendingDeletion.to_db()),
+          user::deletion_scheduled_at::lte(Utc::now().into()),
+        ])
+        .exec()
+        .await;
+
+    let due_user_objs = match due_user_objs {
+      Ok(user_objs) => user_objs,
+      Err(e) => {
+        tracing::error!("Looking up scheduled deletions failed: {}", e);
+        continue
+      }
+    };
+
+    for user_obj in due_user_objs {
+      // Checked again when the user is written, a login since the lookup cancels the deletion
+      let still_due = vec![
+        user::status::equals(UserStatus::PendingDeletion.to_db()),
+        user::deletion_scheduled_at::lte(Utc::now().into()),
+      ];
+      // Nobody is behind a scheduled erasure, so there is no actor or IP
+      match erase_user(&db, AuditContext::default(), None, user_obj.id.clone(), still_due).await {
+        Ok(_) => tracing::info!("Deleted user {} after the grace period", user_obj.id),
+        Err(AppError::PreconditionFailed) => {
+          tracing::info!("Skipped deleting user {}, the deletion was cancelled", user_obj.id)
+        }
+        Err(e) => tracing::error!("Deleting user {} failed: {}", user_obj.id, e),
+      }
+    }
+  }
+}
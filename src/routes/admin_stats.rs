@@ -0,0 +1,240 @@
+use axum::{
+  routing::get,
+  middleware::{self},
+  Extension,
+  Router,
+};
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, Utc};
+use prisma_client_rust::{raw, PrismaValue};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration as StdDuration, Instant};
+use crate::db::{self};
+use crate::error::{AppError, AppResult};
+use crate::middlewares::auth::auth_middleware;
+use crate::models::permission::Permission;
+use crate::models::user_status::{UserStatus, UserStatusExt};
+use crate::response::ApiResponse;
+use crate::utils::extract::Query;
+use crate::utils::jwt::{Claims};
+use crate::utils::permission::Permissions;
+pub use rust_learn_client::types::{ActiveUsers, AdminStats, GetAdminStatsQuery, RegistrationBucket, Registrations, StatsInterval, StatusCount};
+type Database = Extension<std::sync::Arc<db::PrismaClient>>;
+
+/*
+
+Plan for Admin Stats API
+
+/api/admin/stats => GET (needs stats.read, ?from=&to=&interval=day|week)
+
+*/
+pub fn create_route() -> Router {
+  Router::new()
+      .route("/admin/stats", get(get_admin_stats_api))
+      .layer(Extension(Arc::new(StatsCache::from_env())))
+      .layer(middleware::from_fn(auth_middleware))
+}
+
+/// Seconds a computed result is served from memory, `STATS_CACHE_TTL_SECS` overrides it
+const STATS_CACHE_TTL_SECS: u64 = 60;
+
+/// Default range of the registrations series, in days
+const STATS_DEFAULT_DAYS: i64 = 30;
+
+/// Statuses counted as locked accounts
+const LOCKED_STATUSES: [UserStatus; 2] = [UserStatus::Suspended, UserStatus::Banned];
+
+/// SQL expression of the bucket a row falls in, never built from input
+fn bucket_sql(interval: StatsInterval) -> &'static str {
+  match interval {
+    StatsInterval::Day => "DATE_FORMAT(`createdAt`, '%Y-%m-%d')",
+    StatsInterval::Week => "DATE_FORMAT(`createdAt`, '%x-W%v')",
+  }
+}
+
+type StatsKey = (NaiveDate, NaiveDate, StatsInterval);
+
+/// Computed stats per requested range, kept for a fixed TTL
+pub struct StatsCache {
+    ttl: StdDuration,
+    entries: Mutex<HashMap<StatsKey, (Instant, AdminStats)>>,
+}
+
+impl StatsCache {
+  pub fn from_env() -> Self {
+    let ttl = std::env::var("STATS_CACHE_TTL_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(STATS_CACHE_TTL_SECS);
+    StatsCache {
+      ttl: StdDuration::from_secs(ttl),
+      entries: Mutex::new(HashMap::new()),
+    }
+  }
+
+  /// A panic while the lock was held leaves at worst a stale entry, keep using the cache
+  fn entries(&self) -> MutexGuard<'_, HashMap<StatsKey, (Instant, AdminStats)>> {
+    self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+  }
+
+  fn get(&self, key: &StatsKey) -> Option<AdminStats> {
+    let entries = self.entries();
+    entries
+        .get(key)
+        .filter(|(stored_at, _)| stored_at.elapsed() < self.ttl)
+        .map(|(_, stats)| stats.clone())
+  }
+
+  fn insert(&self, key: StatsKey, stats: AdminStats) {
+    let mut entries = self.entries();
+    // Drop what expired so old ranges don't pile up
+    entries.retain(|_, (stored_at, _)| stored_at.elapsed() < self.ttl);
+    entries.insert(key, (Instant::now(), stats));
+  }
+}
+
+#[derive(Deserialize)]
+struct StatusCountRow {
+    status: i32,
+    count: i64,
+}
+
+#[derive(Deserialize)]
+struct BucketCountRow {
+    bucket: String,
+    count: i64,
+}
+
+fn start_of_day(date: NaiveDate) -> PrismaValue {
+  let start: DateTime<FixedOffset> = DateTime::<Utc>::from_utc(date.and_hms_opt(0, 0, 0).unwrap(), Utc).into();
+  PrismaValue::DateTime(start)
+}
+
+/// One grouped query per figure, no user rows are loaded
+async fn compute_stats(
+  db: &db::PrismaClient,
+  from: NaiveDate,
+  to: NaiveDate,
+  interval: StatsInterval,
+) -> AppResult<AdminStats> {
+  let status_rows: Vec<StatusCountRow> = db
+      ._query_raw(raw!("SELECT `status`, COUNT(*) AS `count` FROM `User` GROUP BY `status`"))
+      .exec()
+      .await?;
+
+  // Every status is listed, also those nobody has
+  let by_status: Vec<StatusCount> = UserStatus::ALL
+      .iter()
+      .map(|status| StatusCount {
+        status: *status,
+        count: status_rows
+            .iter()
+            .filter(|row| row.status == status.to_db())
+            .map(|row| row.count)
+            .sum(),
+      })
+      .collect();
+  let total = status_rows.iter().map(|row| row.count).sum();
+  let locked = by_status
+      .iter()
+      .filter(|status_count| LOCKED_STATUSES.contains(&status_count.status))
+      .map(|status_count| status_count.count)
+      .sum();
+
+  let bucket_sql = bucket_sql(interval);
+  let registrations_sql = format!(
+    "SELECT {} AS `bucket`, COUNT(*) AS `count` FROM `User` \
+     WHERE `createdAt` >= {{}} AND `createdAt` < {{}} GROUP BY `bucket` ORDER BY `bucket`",
+    bucket_sql,
+  );
+  // `to` is included, the range ends where the next day starts
+  let until = to.checked_add_signed(Duration::days(1)).ok_or(AppError::InvalidStatsRange)?;
+  let buckets: Vec<BucketCountRow> = db
+      ._query_raw(raw!(
+        &registrations_sql,
+        start_of_day(from),
+        start_of_day(until)
+      ))
+      .exec()
+      .await?;
+
+  let now: DateTime<FixedOffset> = Utc::now().into();
+  let active_rows: Vec<ActiveUsers> = db
+      ._query_raw(raw!(
+        "SELECT \
+           COUNT(DISTINCT CASE WHEN `createdAt` >= {} THEN `userId` END) AS `day`, \
+           COUNT(DISTINCT CASE WHEN `createdAt` >= {} THEN `userId` END) AS `week`, \
+           COUNT(DISTINCT `userId`) AS `month` \
+         FROM `LoginAttempt` WHERE `success` = TRUE AND `createdAt` >= {}",
+        PrismaValue::DateTime(now - Duration::days(1)),
+        PrismaValue::DateTime(now - Duration::days(7)),
+        PrismaValue::DateTime(now - Duration::days(30))
+      ))
+      .exec()
+      .await?;
+
+  Ok(AdminStats {
+    generated_at: Utc::now(),
+    total,
+    by_status,
+    locked,
+    registrations: Registrations {
+      from,
+      to,
+      interval,
+      buckets: buckets
+          .into_iter()
+          .map(|row| RegistrationBucket { bucket: row.bucket, count: row.count })
+          .collect(),
+    },
+    active_users: active_rows.into_iter().next().unwrap_or(ActiveUsers { day: 0, week: 0, month: 0 }),
+  })
+}
+
+#[utoipa::path(
+  get,
+  path = "/admin/stats",
+  responses(
+      (status = 200, description = "Statistics of the user base, cached for `STATS_CACHE_TTL_SECS`", body = crate::response::GetAdminStatsResponse),
+      (status = BAD_REQUEST, description = "`from` is after `to`", body = crate::error::ErrorResponse),
+      (status = UNAUTHORIZED, description = "Not Logged In", body = crate::error::ErrorResponse),
+      (status = FORBIDDEN, description = "Missing the stats.read permission", body = crate::error::ErrorResponse)
+  ),
+  params(
+    GetAdminStatsQuery,
+  )
+)]
+pub async fn get_admin_stats_api(
+  Extension(_claims): Extension<Claims>,
+  Extension(cache): Extension<Arc<StatsCache>>,
+  permissions: Permissions,
+  db: Database,
+  Query(query): Query<GetAdminStatsQuery>,
+) -> AppResult<ApiResponse<AdminStats>> {
+  permissions.require(Permission::StatsRead)?;
+
+  let to = query.to.unwrap_or_else(|| Utc::now().date_naive());
+  // Dates at the ends of the calendar would overflow
+  let from = match query.from {
+    Some(from) => from,
+    None => to
+        .checked_sub_signed(Duration::days(STATS_DEFAULT_DAYS - 1))
+        .ok_or(AppError::InvalidStatsRange)?,
+  };
+  if from > to {
+    return Err(AppError::InvalidStatsRange)
+  }
+  let key = (from, to, query.interval.unwrap_or(StatsInterval::Day));
+
+  let stats = match cache.get(&key) {
+    Some(stats) => stats,
+    None => {
+      let stats = compute_stats(&db, key.0, key.1, key.2).await?;
+      cache.insert(key, stats.clone());
+      stats
+    }
+  };
+
+  Ok(ApiResponse::ok(stats))
+}
@@ -0,0 +1,106 @@
+use axum::{
+  routing::get,
+  middleware::{self},
+  Extension,
+  Router,
+};
+use prisma_client_rust::Direction;
+use crate::db::{self, audit_event};
+use crate::error::{AppResult};
+use crate::middlewares::auth::auth_middleware;
+use crate::utils::extract::Query;
+use crate::utils::jwt::{Claims};
+use crate::models::permission::Permission;
+use crate::response::{ApiResponse, Page, PageQuery};
+use crate::utils::permission::Permissions;
+pub use rust_learn_client::types::{AuditEventView, GetAuditEventsQuery};
+type Database = Extension<std::sync::Arc<db::PrismaClient>>;
+
+/*
+
+Plan for Audit API
+
+/api/audit => GET (needs audit.read)
+
+*/
+pub fn create_route() -> Router {
+  Router::new()
+      .route("/audit", get(get_audit_events_api))
+      .layer(middleware::from_fn(auth_middleware))
+}
+
+impl From<audit_event::Data> for AuditEventView {
+  fn from(audit_obj: audit_event::Data) -> Self {
+    AuditEventView {
+      id: audit_obj.id,
+      actor_id: audit_obj.actor_id,
+      target_id: audit_obj.target_id,
+      action: audit_obj.action,
+      diff: audit_obj.diff,
+      ip: audit_obj.ip,
+      request_id: audit_obj.request_id,
+      created_at: audit_obj.created_at,
+    }
+  }
+}
+
+#[utoipa::path(
+  get,
+  path = "/audit",
+  responses(
+      (status = 200, description = "Audit events found successfully", body = crate::response::GetAuditEventsResponse),
+      (status = UNAUTHORIZED, description = "Not Logged In", body = crate::error::ErrorResponse),
+      (status = FORBIDDEN, description = "Missing the audit.read permission", body = crate::error::ErrorResponse)
+  ),
+  params(
+    GetAuditEventsQuery,
+  )
+)]
+pub async fn get_audit_events_api(
+  Extension(_claims): Extension<Claims>,
+  permissions: Permissions,
+  db: Database,
+  Query(query): Query<GetAuditEventsQuery>,
+) -> AppResult<ApiResponse<Page<AuditEventView>>> {
+  permissions.require(Permission::AuditRead)?;
+
+  let mut audit_filter = vec![];
+
+  // apply filter
+  if let Some(actor_id) = query.actor_id {
+    audit_filter.push(audit_event::actor_id::equals(Some(actor_id)))
+  }
+  if let Some(target_id) = query.target_id {
+    audit_filter.push(audit_event::target_id::equals(Some(target_id)))
+  }
+  if let Some(action) = query.action {
+    audit_filter.push(audit_event::action::equals(action))
+  }
+  if let Some(from) = query.from {
+    audit_filter.push(audit_event::created_at::gte(from))
+  }
+  if let Some(to) = query.to {
+    audit_filter.push(audit_event::created_at::lt(to))
+  }
+
+  let page_query = PageQuery::new(query.page, query.page_size)?;
+  let audit_objs = db
+      .audit_event()
+      .find_many(audit_filter.clone())
+      .order_by(audit_event::created_at::order(Direction::Desc))
+      // apply pagination
+      .take(page_query.take())
+      .skip(page_query.skip())
+      .exec()
+      .await?;
+
+  let audit_count = db
+      .audit_event()
+      .count(audit_filter)
+      .exec()
+      .await?;
+
+  let list = audit_objs.into_iter().map(AuditEventView::from).collect();
+
+  Ok(ApiResponse::page(list, audit_count, page_query))
+}
@@ -1,15 +1,26 @@
 use axum::{
-  extract::Json,
-  routing::post,
+  routing::{get, post},
   Extension,
   Router,
 };
 use axum_extra::extract::cookie::{CookieJar, Cookie};
-use serde::{Serialize, Deserialize};
-use bcrypt::{DEFAULT_COST, verify, hash};
+use serde_json::{json, Value};
+use bcrypt::{DEFAULT_COST, hash};
+use validator::ValidationErrors;
 use crate::db::{self, user};
 use crate::error::{AppError, AppResult};
-use crate::utils::jwt::sign;
+use crate::models::user_status::{UserStatus, UserStatusExt};
+use crate::models::user_view::UserSelf;
+use crate::response::ApiResponse;
+use crate::routes::account_deletion::cancel_account_deletion;
+use crate::utils::extract::Query;
+use crate::utils::audit::{self, AuditAction, AuditContext};
+use crate::utils::jwt::{sign, JwtSecret};
+use crate::utils::login_history::{self, LoginMethod, LoginOutcome};
+use crate::utils::password::{check_password, check_password_length};
+use crate::utils::username::{self, canonicalize, NameConflict, Username};
+use crate::utils::validation::{Rules, Validate, ValidatedJson};
+pub use rust_learn_client::types::{LoginRequestBody, NameAvailability, RegisterAvailabilityQuery, RegisterRequestBody};
 type Database = Extension<std::sync::Arc<db::PrismaClient>>;
 
 /*
@@ -18,26 +29,57 @@ Plan for Auth API
 
 /login => POST
 /register => POST
+/register/availability => GET
 
 */
 pub fn create_route() -> Router {
   Router::new()
       .route("/login", post(login_api))
       .route("/register", post(register_api))
+      .route("/register/availability", get(register_availability_api))
 }
 
-/// Define Login Schemas
-#[derive(Deserialize)]
-pub struct LoginRequestBody {
-    name: String,
-    password: String,
+/// The trail of a login attempt is best effort: a failed write is logged, it must
+/// neither fail a valid login nor replace the reason a login was refused. The
+/// audit entry, `(action, actor_id, diff)`, targets the user the attempt matched
+async fn record_login(
+  db: &db::PrismaClient,
+  audit_ctx: &AuditContext,
+  audit_entry: Option<(AuditAction, Option<String>, Option<Value>)>,
+  user_id: Option<String>,
+  name: &str,
+  outcome: LoginOutcome,
+) {
+  if let Some((action, actor_id, diff)) = audit_entry {
+    if let Err(e) = audit::record(db, audit_ctx, action, actor_id, user_id.clone(), diff).await {
+      tracing::error!("Recording {} for {} failed: {}", action.as_str(), name, e);
+    }
+  }
+  if let Err(e) = login_history::record(db, audit_ctx, user_id, name, LoginMethod::Password, outcome).await {
+    tracing::error!("Recording the login attempt of {} failed: {}", name, e);
+  }
 }
 
-#[derive(Serialize)]
-pub struct LoginResponse {
-    code: String,
-    message: String,
-    data: user::Data,
+/// Bounded by the `User.name` column. The password is looser than at
+/// registration, so passwords set before the rules existed still work
+impl Validate for LoginRequestBody {
+  fn validate(&self) -> Result<(), ValidationErrors> {
+    Rules::default()
+      .length("name", &self.name, 1, 50)
+      .length("password", &self.password, 1, 128)
+      .finish()
+  }
+}
+
+/// The naming rules are checked after these bounds
+impl Validate for RegisterRequestBody {
+  fn validate(&self) -> Result<(), ValidationErrors> {
+    Rules::default()
+      .length("name", &self.name, 3, 32)
+      .check("password", "length", check_password_length(&self.password))
+      .check("password_confirm", "length", check_password_length(&self.password_confirm))
+      .finish()
+  }
 }
 
 #[utoipa::path(
@@ -45,35 +87,95 @@ pub struct LoginResponse {
   path = "/login",
   request_body = LoginRequestBody,
   responses(
-      (status = 200, description = "Login successfully"),
-      (status = 401, description = "User Not Existed"),
-      (status = 401, description = "User/Password Incorrect"),
+      (status = 200, description = "Login successfully", body = crate::response::LoginResponse),
+      (status = 401, description = "User not existed or password incorrect", body = crate::error::ErrorResponse),
+      (status = 403, description = "Account is not active", body = crate::error::ErrorResponse),
+      (status = 412, description = "The account changed while its deletion was being cancelled, try again", body = crate::error::ErrorResponse),
+      (status = 422, description = "Name or password out of bounds", body = crate::response::ValidationErrorResponse),
   ),
 )]
 async fn login_api(
   db: Database,
+  Extension(jwt_secret): Extension<JwtSecret>,
+  audit_ctx: AuditContext,
   cookie_jar: CookieJar,
-  Json(input): Json<LoginRequestBody>,
-) -> Result<(CookieJar, Json<LoginResponse>), AppError> {
-  let user_obj_q: Option<user::Data> = db
+  ValidatedJson(input): ValidatedJson<LoginRequestBody>,
+) -> Result<(CookieJar, ApiResponse<UserSelf>), AppError> {
+  let mut user_obj_q: Option<user::Data> = db
       .user()
-      .find_first(vec![user::name::equals(input.name.clone())])
+      // Only the canonical form, an exact match on `name` could pick a user
+      // whose name merely looks like another one
+      .find_first(vec![user::name_canonical::equals(Some(canonicalize(&input.name)))])
       .exec()
-      .await
-      .unwrap();
+      .await?;
+
+  // Legacy users the startup backfill could not normalize have no canonical
+  // name, only their exact name finds them
+  if user_obj_q.is_none() {
+    user_obj_q = db
+        .user()
+        .find_first(vec![
+          user::name_canonical::equals(None),
+          user::name::equals(input.name.clone()),
+        ])
+        .exec()
+        .await?;
+  }
       
-  if user_obj_q.is_some() == false {
+  let user_obj = match user_obj_q {
+    Some(user_obj) => user_obj,
+    None => {
+      record_login(&db, &audit_ctx, None, None, &input.name, LoginOutcome::UnknownUser).await;
       /// Throw Error when user not found
       return Err(AppError::WrongCredentials)
+    }
+  };
+
+
+  if check_password(&input.password, &user_obj.password).is_err() {
+      record_login(
+        &db,
+        &audit_ctx,
+        Some((AuditAction::LoginFailed, None, Some(json!({ "reason": "wrong_password" })))),
+        Some(user_obj.id.clone()),
+        &input.name,
+        LoginOutcome::WrongPassword,
+      ).await;
+      return Err(AppError::WrongCredentials)
   }
 
-  let user_obj = user_obj_q.unwrap();
+  let mut status = UserStatus::from_db(user_obj.status)?;
 
+  // Logging in during the grace period cancels a requested deletion
+  if status == UserStatus::PendingDeletion {
+      cancel_account_deletion(&db, &audit_ctx, &user_obj).await?;
+      status = UserStatus::Active;
+  }
 
-  verify(&input.password, &user_obj.password).map_err(|_| AppError::WrongCredentials)?;
+  /// Only active accounts may log in
+  if status != UserStatus::Active {
+      record_login(
+        &db,
+        &audit_ctx,
+        Some((AuditAction::LoginFailed, None, Some(json!({ "reason": "status", "status": status })))),
+        Some(user_obj.id.clone()),
+        &input.name,
+        LoginOutcome::AccountNotActive(status),
+      ).await;
+      return Err(AppError::AccountNotActive(status))
+  }
+
+  record_login(
+    &db,
+    &audit_ctx,
+    Some((AuditAction::Login, Some(user_obj.id.clone()), None)),
+    Some(user_obj.id.clone()),
+    &input.name,
+    LoginOutcome::Success,
+  ).await;
 
   // set jwt cookie
-  let jwt_data = sign(user_obj.id.to_string()).unwrap();
+  let jwt_data = sign(&jwt_secret, user_obj.id.to_string())?;
 
   let set_cookie = Cookie::build("user", jwt_data)
       .path("/")
@@ -83,79 +185,112 @@ async fn login_api(
 
   let new_cookie_jar = cookie_jar.add(set_cookie);
 
-  let res_json = LoginResponse {
-    code: "200".to_string(),
-    message: "Login Success".to_string(),
-    data: user_obj
-  };
+  let res_json = ApiResponse::new("login-success", UserSelf { status, ..UserSelf::try_from(&user_obj)? });
 
   return Ok((
     new_cookie_jar,
-    Json(res_json)
+    res_json
   ))
 }
 
-/// Define Register Schemas
-#[derive(Deserialize)]
-pub struct RegisterRequestBody {
-    name: String,
-    password: String,
-    password_confirm: String,
-}
-
-#[derive(Serialize)]
-pub struct RegisterResponse {
-    code: String,
-    message: String,
-    data: String,
-}
-
 #[utoipa::path(
   post,
   path = "/register",
-  request_body = LoginRequestBody,
+  request_body = RegisterRequestBody,
   responses(
-      (status = 200, description = "Register successfully"),
-      (status = 400, description = "Record Not Existed"),
-      (status = 400, description = "Password Not Match")
+      (status = 200, description = "Register successfully, `data` is the new user id", body = crate::response::IdResponse),
+      (status = 400, description = "Passwords don't match or name breaks the naming rules", body = crate::error::ErrorResponse),
+      (status = 409, description = "Name taken, or looks too much like an existing one", body = crate::error::ErrorResponse),
+      (status = 422, description = "Name or password out of bounds", body = crate::response::ValidationErrorResponse)
   ),
 )]
 async fn register_api(
   db: Database,
-  Json(input): Json<RegisterRequestBody>,
-) -> AppResult<Json<RegisterResponse>> {
+  audit_ctx: AuditContext,
+  ValidatedJson(input): ValidatedJson<RegisterRequestBody>,
+) -> AppResult<ApiResponse<String>> {
     /// Verify Passwords are same
     if !&input.password.eq(&input.password_confirm) {
       return Err(AppError::PasswordDontMatch)
     }
 
-    /// Existed User Check
-    let existed_user_obj = db
-        .user()
-        .find_first(vec![user::name::equals(input.name.clone())])
-        .exec()
-        .await
-        .unwrap();
-    
-    /// Don't allow if there is already record with user name
-    if existed_user_obj.is_some() == true {
+    let username = Username::parse(&input.name).map_err(AppError::InvalidUsername)?;
+
+    /// Don't allow if the name or a look-alike is taken
+    let conflict = username::find_conflict(&db, &username, None).await?;
+    if conflict == Some(NameConflict::Taken) {
         return Err(AppError::RecordExisted)
+    } else if conflict == Some(NameConflict::Confusable) {
+        return Err(AppError::UsernameConfusable)
     } else {
-        let password_hash = hash(input.password, DEFAULT_COST).unwrap();
+        let password_hash = hash(input.password, DEFAULT_COST)?;
         let user_obj = db
-            .user()
-            .create(input.name, password_hash, vec![])
-            .exec()
-            .await
-            .unwrap();
+            ._transaction()
+            .run(|tx| async move {
+              let user_obj = tx
+                  .user()
+                  .create(username.display.clone(), password_hash, username.params())
+                  .exec()
+                  .await?;
+
+              audit::record(
+                &tx,
+                &audit_ctx,
+                AuditAction::Register,
+                Some(user_obj.id.clone()),
+                Some(user_obj.id.clone()),
+                Some(json!({ "after": { "name": user_obj.name, "status": UserStatus::from_db(user_obj.status)? } })),
+              ).await?;
+
+              Ok::<user::Data, AppError>(user_obj)
+            })
+            .await?;
 
         /// Response
-        let res_json = RegisterResponse {
-          code: "200".to_string(),
-          message: "OK".to_string(),
-          data: user_obj.id.to_string(),
-        };
+        let res_json = ApiResponse::ok(user_obj.id.to_string());
 
-        return Ok(Json(res_json))
+        return Ok(res_json)
     }
+}
+
+/// Number of alternatives offered for a taken name
+const NAME_SUGGESTIONS: usize = 3;
+
+#[utoipa::path(
+  get,
+  path = "/register/availability",
+  responses(
+      (status = 200, description = "Whether the name can be registered, with alternatives when it can't", body = crate::response::RegisterAvailabilityResponse),
+  ),
+  params(
+    RegisterAvailabilityQuery,
+  )
+)]
+async fn register_availability_api(
+  db: Database,
+  Query(query): Query<RegisterAvailabilityQuery>,
+) -> AppResult<ApiResponse<NameAvailability>> {
+  let (reason, suggestions) = match Username::parse(&query.name) {
+    // Nothing sensible to build alternatives from
+    Err(reason) => (Some(format!("Name {}", reason)), vec![]),
+    Ok(username) => match username::find_conflict(&db, &username, None).await? {
+      None => (None, vec![]),
+      Some(conflict) => {
+        let reason = match conflict {
+          NameConflict::Taken => "Name is taken",
+          NameConflict::Confusable => "Name looks too much like an existing one",
+        };
+        (Some(reason.to_string()), username::suggest(&db, &username, NAME_SUGGESTIONS).await?)
+      }
+    },
+  };
+
+  let res_json = ApiResponse::ok(NameAvailability {
+    name: query.name,
+    available: reason.is_none(),
+    reason,
+    suggestions,
+  });
+
+  Ok(res_json)
 }
\ No newline at end of file
@@ -0,0 +1,195 @@
+use axum::{
+  routing::{get, put},
+  middleware::{self},
+  Extension,
+  Router,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::str::FromStr;
+use utoipa::IntoParams;
+use uuid::Uuid;
+use crate::db::{self, user, user_grant};
+use crate::error::{AppError, AppResult};
+use crate::middlewares::auth::auth_middleware;
+use crate::models::permission::{Grant, GrantExt, Permission};
+use crate::response::ApiResponse;
+use crate::utils::extract::Path;
+use crate::utils::audit::{self, AuditAction, AuditContext};
+use crate::utils::jwt::{Claims};
+use crate::utils::permission::{load_permissions, Permissions};
+type Database = Extension<std::sync::Arc<db::PrismaClient>>;
+
+/*
+
+Plan for Grant API
+
+/api/users/:user_id/grants/:grant => PUT, DELETE (needs groups.manage)
+/api/users/:user_id/permissions => GET (the user themselves or users.read_private)
+
+*/
+pub fn create_route() -> Router {
+  Router::new()
+      .route("/users/:user_id/grants/:grant", put(add_user_grant_api).delete(revoke_user_grant_api))
+      .route("/users/:user_id/permissions", get(get_user_permissions_api))
+      .layer(middleware::from_fn(auth_middleware))
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct UserGrantParams {
+  user_id: Uuid,
+  /// `role:<role>` or a permission such as `audit.read`
+  grant: String,
+}
+
+#[utoipa::path(
+  put,
+  path = "/users/{user_id}/grants/{grant}",
+  responses(
+      (status = 200, description = "Grant given to the user, granting it again changes nothing", body = crate::response::IdResponse),
+      (status = BAD_REQUEST, description = "Unknown grant", body = crate::error::ErrorResponse),
+      (status = UNAUTHORIZED, description = "Not Logged In", body = crate::error::ErrorResponse),
+      (status = FORBIDDEN, description = "Missing the groups.manage permission or a permission the grant gives", body = crate::error::ErrorResponse),
+      (status = NOT_FOUND, description = "Record Not Found", body = crate::error::ErrorResponse)
+  ),
+  params(
+    UserGrantParams,
+  )
+)]
+pub async fn add_user_grant_api(
+  Extension(claims): Extension<Claims>,
+  permissions: Permissions,
+  db: Database,
+  audit_ctx: AuditContext,
+  Path(UserGrantParams{user_id, grant}): Path<UserGrantParams>,
+) -> AppResult<ApiResponse<String>> {
+  let user_id = user_id.to_string();
+  permissions.require(Permission::GroupsManage)?;
+  let grant = Grant::from_str(&grant).map_err(|_| AppError::UnknownGrant(grant))?;
+  permissions.require_all(&grant.permissions())?;
+  let grant = grant.to_string();
+
+  db.user()
+      .find_unique(user::id::equals(String::from(&user_id)))
+      .exec()
+      .await?
+      .ok_or(AppError::RecordNotFound)?;
+
+  let grant_obj = db
+      .user_grant()
+      .find_unique(user_grant::user_id_permission(String::from(&user_id), String::from(&grant)))
+      .exec()
+      .await?;
+
+  if grant_obj.is_none() {
+    let actor_id = claims.sub.to_string();
+    let target_id = String::from(&user_id);
+    let granted = String::from(&grant);
+    db._transaction()
+      .run(|tx| async move {
+        tx.user_grant()
+          .create(String::from(&granted), user::id::equals(String::from(&target_id)), vec![])
+          .exec()
+          .await?;
+
+        audit::record(
+          &tx,
+          &audit_ctx,
+          AuditAction::GrantAdd,
+          Some(actor_id),
+          Some(target_id),
+          Some(json!({ "grant": granted })),
+        ).await?;
+
+        Ok::<(), AppError>(())
+      })
+      .await?;
+  }
+
+  Ok(ApiResponse::new("grant-added", grant))
+}
+
+#[utoipa::path(
+  delete,
+  path = "/users/{user_id}/grants/{grant}",
+  responses(
+      (status = 200, description = "Grant revoked from the user, grants through the role or groups stay", body = crate::response::IdResponse),
+      (status = UNAUTHORIZED, description = "Not Logged In", body = crate::error::ErrorResponse),
+      (status = FORBIDDEN, description = "Missing the groups.manage permission", body = crate::error::ErrorResponse),
+      (status = NOT_FOUND, description = "The user does not have this grant", body = crate::error::ErrorResponse)
+  ),
+  params(
+    UserGrantParams,
+  )
+)]
+pub async fn revoke_user_grant_api(
+  Extension(claims): Extension<Claims>,
+  permissions: Permissions,
+  db: Database,
+  audit_ctx: AuditContext,
+  Path(UserGrantParams{user_id, grant}): Path<UserGrantParams>,
+) -> AppResult<ApiResponse<String>> {
+  let user_id = user_id.to_string();
+  permissions.require(Permission::GroupsManage)?;
+
+  // Grants the code no longer knows about can still be revoked
+  let actor_id = claims.sub.to_string();
+  let target_id = String::from(&user_id);
+  let revoked = String::from(&grant);
+  db._transaction()
+    .run(|tx| async move {
+      tx.user_grant()
+        .delete(user_grant::user_id_permission(String::from(&target_id), String::from(&revoked)))
+        .exec()
+        .await?;
+
+      audit::record(
+        &tx,
+        &audit_ctx,
+        AuditAction::GrantRevoke,
+        Some(actor_id),
+        Some(target_id),
+        Some(json!({ "grant": revoked })),
+      ).await?;
+
+      Ok::<(), AppError>(())
+    })
+    .await?;
+
+  Ok(ApiResponse::new("grant-revoked", grant))
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct UserPermissionsParams {
+  user_id: Uuid,
+}
+
+#[utoipa::path(
+  get,
+  path = "/users/{user_id}/permissions",
+  responses(
+      (status = 200, description = "Effective permissions of the user, from the role, direct grants and groups", body = crate::response::UserPermissionsResponse),
+      (status = UNAUTHORIZED, description = "Not Logged In", body = crate::error::ErrorResponse),
+      (status = FORBIDDEN, description = "Neither the user themselves nor holding users.read_private", body = crate::error::ErrorResponse),
+      (status = NOT_FOUND, description = "Record Not Found", body = crate::error::ErrorResponse)
+  ),
+  params(
+    UserPermissionsParams,
+  )
+)]
+pub async fn get_user_permissions_api(
+  Extension(claims): Extension<Claims>,
+  permissions: Permissions,
+  db: Database,
+  Path(UserPermissionsParams{user_id}): Path<UserPermissionsParams>,
+) -> AppResult<ApiResponse<Vec<Permission>>> {
+  let user_id = user_id.to_string();
+  let user_permissions = if claims.sub.to_string().eq(&user_id) {
+    permissions
+  } else {
+    permissions.require(Permission::UsersReadPrivate)?;
+    load_permissions(&db, user_id).await?
+  };
+
+  Ok(ApiResponse::ok(user_permissions.to_vec()))
+}
@@ -0,0 +1,596 @@
+use axum::{
+  routing::{get, put},
+  middleware::{self},
+  Extension,
+  Router,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::str::FromStr;
+use utoipa::IntoParams;
+use uuid::Uuid;
+use crate::db::{self, group, group_grant, group_member, user};
+use crate::error::{AppError, AppResult};
+use crate::middlewares::auth::auth_middleware;
+use crate::models::permission::{Grant, GrantExt, Permission};
+use crate::response::{ApiResponse, Page, PageQuery};
+use crate::utils::extract::{Json, Path, Query};
+use crate::utils::audit::{self, AuditAction, AuditContext};
+use crate::utils::jwt::{Claims};
+use crate::utils::permission::Permissions;
+pub use rust_learn_client::types::{GetGroupsQuery, GroupBody, GroupDetail, GroupGrantView, GroupMemberView, GroupView};
+type Database = Extension<std::sync::Arc<db::PrismaClient>>;
+
+/*
+
+Plan for Group API (all need groups.manage)
+
+/api/groups => GET, POST
+/api/groups/:group_id => GET, PUT, DELETE
+/api/groups/:group_id/members/:user_id => PUT, DELETE
+/api/groups/:group_id/grants/:grant => PUT, DELETE
+
+*/
+pub fn create_route() -> Router {
+  Router::new()
+      .route("/groups", get(get_groups_api).post(create_group_api))
+      .route("/groups/:group_id", get(get_group_api).put(update_group_api).delete(delete_group_api))
+      .route("/groups/:group_id/members/:user_id", put(add_group_member_api).delete(remove_group_member_api))
+      .route("/groups/:group_id/grants/:grant", put(add_group_grant_api).delete(revoke_group_grant_api))
+      .layer(middleware::from_fn(auth_middleware))
+}
+
+impl From<&group::Data> for GroupView {
+  fn from(group_obj: &group::Data) -> Self {
+    GroupView {
+      id: group_obj.id.clone(),
+      name: group_obj.name.clone(),
+      description: group_obj.description.clone(),
+      created_at: group_obj.created_at,
+    }
+  }
+}
+
+impl From<group::Data> for GroupDetail {
+  fn from(group_obj: group::Data) -> Self {
+    let members = group_obj
+        .members()
+        .map(|members| members
+          .iter()
+          .map(|member| GroupMemberView {
+            group_id: member.group_id.clone(),
+            user_id: member.user_id.clone(),
+            created_at: member.created_at,
+          })
+          .collect())
+        .unwrap_or_default();
+    let grants = group_obj
+        .grants()
+        .map(|grants| grants
+          .iter()
+          .map(|grant| GroupGrantView {
+            group_id: grant.group_id.clone(),
+            permission: grant.permission.clone(),
+            created_at: grant.created_at,
+          })
+          .collect())
+        .unwrap_or_default();
+
+    GroupDetail {
+      id: group_obj.id,
+      name: group_obj.name,
+      description: group_obj.description,
+      created_at: group_obj.created_at,
+      members,
+      grants,
+    }
+  }
+}
+
+#[utoipa::path(
+  get,
+  path = "/groups",
+  responses(
+      (status = 200, description = "Groups found successfully", body = crate::response::GetGroupsResponse),
+      (status = UNAUTHORIZED, description = "Not Logged In", body = crate::error::ErrorResponse),
+      (status = FORBIDDEN, description = "Missing the groups.manage permission", body = crate::error::ErrorResponse)
+  ),
+  params(
+    GetGroupsQuery,
+  )
+)]
+pub async fn get_groups_api(
+  permissions: Permissions,
+  db: Database,
+  Query(query): Query<GetGroupsQuery>,
+) -> AppResult<ApiResponse<Page<GroupView>>> {
+  permissions.require(Permission::GroupsManage)?;
+
+  let page_query = PageQuery::new(query.page, query.page_size)?;
+  let group_objs = db
+      .group()
+      .find_many(vec![])
+      .order_by(group::name::order(prisma_client_rust::Direction::Asc))
+      // apply pagination
+      .take(page_query.take())
+      .skip(page_query.skip())
+      .exec()
+      .await?;
+
+  let group_count = db
+      .group()
+      .count(vec![])
+      .exec()
+      .await?;
+
+  let list = group_objs.iter().map(GroupView::from).collect();
+
+  Ok(ApiResponse::page(list, group_count, page_query))
+}
+
+#[utoipa::path(
+  post,
+  path = "/groups",
+  request_body = GroupBody,
+  responses(
+      (status = 200, description = "Group created successfully", body = crate::response::GroupResponse),
+      (status = CONFLICT, description = "A group with this name exists", body = crate::error::ErrorResponse),
+      (status = UNAUTHORIZED, description = "Not Logged In", body = crate::error::ErrorResponse),
+      (status = FORBIDDEN, description = "Missing the groups.manage permission", body = crate::error::ErrorResponse)
+  )
+)]
+pub async fn create_group_api(
+  Extension(claims): Extension<Claims>,
+  permissions: Permissions,
+  db: Database,
+  audit_ctx: AuditContext,
+  Json(body): Json<GroupBody>,
+) -> AppResult<ApiResponse<GroupView>> {
+  permissions.require(Permission::GroupsManage)?;
+
+  let actor_id = claims.sub.to_string();
+  let group_obj = db._transaction()
+    .run(|tx| async move {
+      let group_obj = tx
+        .group()
+        .create(body.name, vec![group::description::set(body.description)])
+        .exec()
+        .await?;
+
+      audit::record(
+        &tx,
+        &audit_ctx,
+        AuditAction::GroupCreate,
+        Some(actor_id),
+        Some(group_obj.id.clone()),
+        Some(json!({ "after": { "name": group_obj.name, "description": group_obj.description } })),
+      ).await?;
+
+      Ok::<group::Data, AppError>(group_obj)
+    })
+    .await?;
+
+  Ok(ApiResponse::new("group-created", GroupView::from(&group_obj)))
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct GroupParams {
+  group_id: String,
+}
+
+#[utoipa::path(
+  get,
+  path = "/groups/{group_id}",
+  responses(
+      (status = 200, description = "Group found successfully, with its members and grants", body = crate::response::GroupDetailResponse),
+      (status = UNAUTHORIZED, description = "Not Logged In", body = crate::error::ErrorResponse),
+      (status = FORBIDDEN, description = "Missing the groups.manage permission", body = crate::error::ErrorResponse),
+      (status = NOT_FOUND, description = "Record Not Found", body = crate::error::ErrorResponse)
+  ),
+  params(
+    GroupParams,
+  )
+)]
+pub async fn get_group_api(
+  permissions: Permissions,
+  db: Database,
+  Path(GroupParams{group_id}): Path<GroupParams>,
+) -> AppResult<ApiResponse<GroupDetail>> {
+  permissions.require(Permission::GroupsManage)?;
+
+  let group_obj = db
+      .group()
+      .find_unique(group::id::equals(group_id))
+      .with(group::members::fetch(vec![]))
+      .with(group::grants::fetch(vec![]))
+      .exec()
+      .await?
+      .ok_or(AppError::RecordNotFound)?;
+
+  Ok(ApiResponse::ok(GroupDetail::from(group_obj)))
+}
+
+#[utoipa::path(
+  put,
+  path = "/groups/{group_id}",
+  request_body = GroupBody,
+  responses(
+      (status = 200, description = "Group updated successfully", body = crate::response::GroupResponse),
+      (status = CONFLICT, description = "A group with this name exists", body = crate::error::ErrorResponse),
+      (status = UNAUTHORIZED, description = "Not Logged In", body = crate::error::ErrorResponse),
+      (status = FORBIDDEN, description = "Missing the groups.manage permission", body = crate::error::ErrorResponse),
+      (status = NOT_FOUND, description = "Record Not Found", body = crate::error::ErrorResponse)
+  ),
+  params(
+    GroupParams,
+  )
+)]
+pub async fn update_group_api(
+  Extension(claims): Extension<Claims>,
+  permissions: Permissions,
+  db: Database,
+  audit_ctx: AuditContext,
+  Path(GroupParams{group_id}): Path<GroupParams>,
+  Json(body): Json<GroupBody>,
+) -> AppResult<ApiResponse<GroupView>> {
+  permissions.require(Permission::GroupsManage)?;
+
+  let before = db
+      .group()
+      .find_unique(group::id::equals(String::from(&group_id)))
+      .exec()
+      .await?
+      .ok_or(AppError::RecordNotFound)?;
+
+  let actor_id = claims.sub.to_string();
+  let group_obj = db._transaction()
+    .run(|tx| async move {
+      let group_obj = tx
+        .group()
+        .update(
+          group::id::equals(group_id),
+          vec![
+            group::name::set(body.name),
+            group::description::set(body.description),
+          ],
+        )
+        .exec()
+        .await?;
+
+      audit::record(
+        &tx,
+        &audit_ctx,
+        AuditAction::GroupUpdate,
+        Some(actor_id),
+        Some(group_obj.id.clone()),
+        Some(json!({
+          "before": { "name": before.name, "description": before.description },
+          "after": { "name": group_obj.name, "description": group_obj.description },
+        })),
+      ).await?;
+
+      Ok::<group::Data, AppError>(group_obj)
+    })
+    .await?;
+
+  Ok(ApiResponse::new("group-updated", GroupView::from(&group_obj)))
+}
+
+#[utoipa::path(
+  delete,
+  path = "/groups/{group_id}",
+  responses(
+      (status = 200, description = "Group deleted successfully, its memberships and grants go with it", body = crate::response::IdResponse),
+      (status = UNAUTHORIZED, description = "Not Logged In", body = crate::error::ErrorResponse),
+      (status = FORBIDDEN, description = "Missing the groups.manage permission", body = crate::error::ErrorResponse),
+      (status = NOT_FOUND, description = "Record Not Found", body = crate::error::ErrorResponse)
+  ),
+  params(
+    GroupParams,
+  )
+)]
+pub async fn delete_group_api(
+  Extension(claims): Extension<Claims>,
+  permissions: Permissions,
+  db: Database,
+  audit_ctx: AuditContext,
+  Path(GroupParams{group_id}): Path<GroupParams>,
+) -> AppResult<ApiResponse<String>> {
+  permissions.require(Permission::GroupsManage)?;
+
+  let actor_id = claims.sub.to_string();
+  let target_id = String::from(&group_id);
+  db._transaction()
+    .run(|tx| async move {
+      let group_obj = tx
+        .group()
+        .delete(group::id::equals(target_id))
+        .with(group::members::fetch(vec![]))
+        .with(group::grants::fetch(vec![]))
+        .exec()
+        .await?;
+
+      // Members and grants are removed by the cascade, keep them in the diff
+      let members: Vec<String> = group_obj
+        .members()
+        .map(|members| members.iter().map(|member| member.user_id.clone()).collect())
+        .unwrap_or_default();
+      let grants: Vec<String> = group_obj
+        .grants()
+        .map(|grants| grants.iter().map(|grant| grant.permission.clone()).collect())
+        .unwrap_or_default();
+
+      audit::record(
+        &tx,
+        &audit_ctx,
+        AuditAction::GroupDelete,
+        Some(actor_id),
+        Some(group_obj.id.clone()),
+        Some(json!({ "before": { "name": group_obj.name, "members": members, "grants": grants } })),
+      ).await?;
+
+      Ok::<(), AppError>(())
+    })
+    .await?;
+
+  Ok(ApiResponse::new("group-deleted", group_id))
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct GroupMemberParams {
+  group_id: String,
+  user_id: Uuid,
+}
+
+#[utoipa::path(
+  put,
+  path = "/groups/{group_id}/members/{user_id}",
+  responses(
+      (status = 200, description = "User is a member of the group, adding an existing member changes nothing", body = crate::response::IdResponse),
+      (status = UNAUTHORIZED, description = "Not Logged In", body = crate::error::ErrorResponse),
+      (status = FORBIDDEN, description = "Missing the groups.manage permission or a permission the group gives", body = crate::error::ErrorResponse),
+      (status = NOT_FOUND, description = "Group or user not found", body = crate::error::ErrorResponse)
+  ),
+  params(
+    GroupMemberParams,
+  )
+)]
+pub async fn add_group_member_api(
+  Extension(claims): Extension<Claims>,
+  permissions: Permissions,
+  db: Database,
+  audit_ctx: AuditContext,
+  Path(GroupMemberParams{group_id, user_id}): Path<GroupMemberParams>,
+) -> AppResult<ApiResponse<String>> {
+  let user_id = user_id.to_string();
+  permissions.require(Permission::GroupsManage)?;
+
+  let group_obj = db
+      .group()
+      .find_unique(group::id::equals(String::from(&group_id)))
+      .exec()
+      .await?
+      .ok_or(AppError::RecordNotFound)?;
+  db.user()
+      .find_unique(user::id::equals(String::from(&user_id)))
+      .exec()
+      .await?
+      .ok_or(AppError::RecordNotFound)?;
+
+  // Joining a group hands out its grants, the same rule as granting them directly
+  let group_grants = db
+      .group_grant()
+      .find_many(vec![group_grant::group_id::equals(String::from(&group_id))])
+      .exec()
+      .await?;
+  for grant_obj in group_grants {
+    // Grants the code no longer knows about give nothing
+    if let Ok(grant) = Grant::from_str(&grant_obj.permission) {
+      permissions.require_all(&grant.permissions())?;
+    }
+  }
+
+  let member_obj = db
+      .group_member()
+      .find_unique(group_member::group_id_user_id(String::from(&group_id), String::from(&user_id)))
+      .exec()
+      .await?;
+
+  if member_obj.is_none() {
+    let actor_id = claims.sub.to_string();
+    let target_id = String::from(&user_id);
+    db._transaction()
+      .run(|tx| async move {
+        tx.group_member()
+          .create(
+            group::id::equals(String::from(&group_obj.id)),
+            user::id::equals(String::from(&target_id)),
+            vec![],
+          )
+          .exec()
+          .await?;
+
+        audit::record(
+          &tx,
+          &audit_ctx,
+          AuditAction::GroupMemberAdd,
+          Some(actor_id),
+          Some(target_id),
+          Some(json!({ "group_id": group_obj.id, "group": group_obj.name })),
+        ).await?;
+
+        Ok::<(), AppError>(())
+      })
+      .await?;
+  }
+
+  Ok(ApiResponse::new("member-added", user_id))
+}
+
+#[utoipa::path(
+  delete,
+  path = "/groups/{group_id}/members/{user_id}",
+  responses(
+      (status = 200, description = "User removed from the group", body = crate::response::IdResponse),
+      (status = UNAUTHORIZED, description = "Not Logged In", body = crate::error::ErrorResponse),
+      (status = FORBIDDEN, description = "Missing the groups.manage permission", body = crate::error::ErrorResponse),
+      (status = NOT_FOUND, description = "User is not a member of the group", body = crate::error::ErrorResponse)
+  ),
+  params(
+    GroupMemberParams,
+  )
+)]
+pub async fn remove_group_member_api(
+  Extension(claims): Extension<Claims>,
+  permissions: Permissions,
+  db: Database,
+  audit_ctx: AuditContext,
+  Path(GroupMemberParams{group_id, user_id}): Path<GroupMemberParams>,
+) -> AppResult<ApiResponse<String>> {
+  let user_id = user_id.to_string();
+  permissions.require(Permission::GroupsManage)?;
+
+  let actor_id = claims.sub.to_string();
+  let target_id = String::from(&user_id);
+  db._transaction()
+    .run(|tx| async move {
+      tx.group_member()
+        .delete(group_member::group_id_user_id(String::from(&group_id), String::from(&target_id)))
+        .exec()
+        .await?;
+
+      audit::record(
+        &tx,
+        &audit_ctx,
+        AuditAction::GroupMemberRemove,
+        Some(actor_id),
+        Some(target_id),
+        Some(json!({ "group_id": group_id })),
+      ).await?;
+
+      Ok::<(), AppError>(())
+    })
+    .await?;
+
+  Ok(ApiResponse::new("member-removed", user_id))
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct GroupGrantParams {
+  group_id: String,
+  /// `role:<role>` or a permission such as `audit.read`
+  grant: String,
+}
+
+#[utoipa::path(
+  put,
+  path = "/groups/{group_id}/grants/{grant}",
+  responses(
+      (status = 200, description = "Grant given to every member of the group, granting it again changes nothing", body = crate::response::IdResponse),
+      (status = BAD_REQUEST, description = "Unknown grant", body = crate::error::ErrorResponse),
+      (status = UNAUTHORIZED, description = "Not Logged In", body = crate::error::ErrorResponse),
+      (status = FORBIDDEN, description = "Missing the groups.manage permission or a permission the grant gives", body = crate::error::ErrorResponse),
+      (status = NOT_FOUND, description = "Record Not Found", body = crate::error::ErrorResponse)
+  ),
+  params(
+    GroupGrantParams,
+  )
+)]
+pub async fn add_group_grant_api(
+  Extension(claims): Extension<Claims>,
+  permissions: Permissions,
+  db: Database,
+  audit_ctx: AuditContext,
+  Path(GroupGrantParams{group_id, grant}): Path<GroupGrantParams>,
+) -> AppResult<ApiResponse<String>> {
+  permissions.require(Permission::GroupsManage)?;
+  let grant = Grant::from_str(&grant).map_err(|_| AppError::UnknownGrant(grant))?;
+  permissions.require_all(&grant.permissions())?;
+  let grant = grant.to_string();
+
+  db.group()
+      .find_unique(group::id::equals(String::from(&group_id)))
+      .exec()
+      .await?
+      .ok_or(AppError::RecordNotFound)?;
+
+  let grant_obj = db
+      .group_grant()
+      .find_unique(group_grant::group_id_permission(String::from(&group_id), String::from(&grant)))
+      .exec()
+      .await?;
+
+  if grant_obj.is_none() {
+    let actor_id = claims.sub.to_string();
+    let target_id = String::from(&group_id);
+    db._transaction()
+      .run(|tx| async move {
+        tx.group_grant()
+          .create(String::from(&grant), group::id::equals(String::from(&target_id)), vec![])
+          .exec()
+          .await?;
+
+        audit::record(
+          &tx,
+          &audit_ctx,
+          AuditAction::GrantAdd,
+          Some(actor_id),
+          Some(target_id),
+          Some(json!({ "group_id": group_id, "grant": grant })),
+        ).await?;
+
+        Ok::<(), AppError>(())
+      })
+      .await?;
+  }
+
+  Ok(ApiResponse::new("grant-added", grant))
+}
+
+#[utoipa::path(
+  delete,
+  path = "/groups/{group_id}/grants/{grant}",
+  responses(
+      (status = 200, description = "Grant revoked from the group", body = crate::response::IdResponse),
+      (status = UNAUTHORIZED, description = "Not Logged In", body = crate::error::ErrorResponse),
+      (status = FORBIDDEN, description = "Missing the groups.manage permission", body = crate::error::ErrorResponse),
+      (status = NOT_FOUND, description = "The group does not have this grant", body = crate::error::ErrorResponse)
+  ),
+  params(
+    GroupGrantParams,
+  )
+)]
+pub async fn revoke_group_grant_api(
+  Extension(claims): Extension<Claims>,
+  permissions: Permissions,
+  db: Database,
+  audit_ctx: AuditContext,
+  Path(GroupGrantParams{group_id, grant}): Path<GroupGrantParams>,
+) -> AppResult<ApiResponse<String>> {
+  permissions.require(Permission::GroupsManage)?;
+
+  // Grants the code no longer knows about can still be revoked
+  let actor_id = claims.sub.to_string();
+  let revoked = String::from(&grant);
+  db._transaction()
+    .run(|tx| async move {
+      tx.group_grant()
+        .delete(group_grant::group_id_permission(String::from(&group_id), String::from(&revoked)))
+        .exec()
+        .await?;
+
+      audit::record(
+        &tx,
+        &audit_ctx,
+        AuditAction::GrantRevoke,
+        Some(actor_id),
+        Some(String::from(&group_id)),
+        Some(json!({ "group_id": group_id, "grant": revoked })),
+      ).await?;
+
+      Ok::<(), AppError>(())
+    })
+    .await?;
+
+  Ok(ApiResponse::new("grant-revoked", grant))
+}
@@ -0,0 +1,120 @@
+use axum::{
+  routing::get,
+  middleware::{self},
+  Extension,
+  Router,
+};
+use prisma_client_rust::Direction;
+use serde::Deserialize;
+use utoipa::IntoParams;
+use uuid::Uuid;
+use crate::db::{self, login_attempt};
+use crate::error::{AppResult};
+use crate::middlewares::auth::auth_middleware;
+use crate::models::permission::Permission;
+use crate::response::{ApiResponse, Page, PageQuery};
+use crate::utils::extract::{Path, Query};
+use crate::utils::jwt::{Claims};
+use crate::utils::permission::Permissions;
+pub use rust_learn_client::types::{GetUserLoginsQuery, LoginAttemptView};
+type Database = Extension<std::sync::Arc<db::PrismaClient>>;
+
+/*
+
+Plan for Login History API
+
+/api/users/:user_id/logins => GET (the user themselves or users.read_private)
+
+*/
+pub fn create_route() -> Router {
+  Router::new()
+      .route("/users/:user_id/logins", get(get_user_logins_api))
+      .layer(middleware::from_fn(auth_middleware))
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct GetUserLoginsParams {
+  user_id: Uuid,
+}
+
+impl From<login_attempt::Data> for LoginAttemptView {
+  fn from(login_obj: login_attempt::Data) -> Self {
+    LoginAttemptView {
+      id: login_obj.id,
+      user_id: login_obj.user_id,
+      name: login_obj.name,
+      success: login_obj.success,
+      reason: login_obj.reason,
+      method: login_obj.method,
+      ip: login_obj.ip,
+      user_agent: login_obj.user_agent,
+      created_at: login_obj.created_at,
+    }
+  }
+}
+
+#[utoipa::path(
+  get,
+  path = "/users/{user_id}/logins",
+  responses(
+      (status = 200, description = "Login attempts of the user, newest first", body = crate::response::GetUserLoginsResponse),
+      (status = UNAUTHORIZED, description = "Not Logged In", body = crate::error::ErrorResponse),
+      (status = FORBIDDEN, description = "Neither the user themselves nor holding users.read_private", body = crate::error::ErrorResponse)
+  ),
+  params(
+    GetUserLoginsParams,
+    GetUserLoginsQuery,
+  )
+)]
+pub async fn get_user_logins_api(
+  Extension(claims): Extension<Claims>,
+  permissions: Permissions,
+  db: Database,
+  Path(GetUserLoginsParams{user_id}): Path<GetUserLoginsParams>,
+  Query(query): Query<GetUserLoginsQuery>,
+) -> AppResult<ApiResponse<Page<LoginAttemptView>>> {
+  let user_id = user_id.to_string();
+  if !claims.sub.to_string().eq(&user_id) {
+    permissions.require(Permission::UsersReadPrivate)?;
+  }
+
+  let mut logins_filter = vec![login_attempt::user_id::equals(Some(user_id))];
+
+  // apply filter
+  if let Some(success) = query.success {
+    logins_filter.push(login_attempt::success::equals(success))
+  }
+  if let Some(reason) = query.reason {
+    logins_filter.push(login_attempt::reason::equals(Some(reason)))
+  }
+  if let Some(ip) = query.ip {
+    logins_filter.push(login_attempt::ip::equals(Some(ip)))
+  }
+  if let Some(from) = query.from {
+    logins_filter.push(login_attempt::created_at::gte(from))
+  }
+  if let Some(to) = query.to {
+    logins_filter.push(login_attempt::created_at::lt(to))
+  }
+
+  let page_query = PageQuery::new(query.page, query.page_size)?;
+  let login_objs = db
+      .login_attempt()
+      .find_many(logins_filter.clone())
+      .order_by(login_attempt::created_at::order(Direction::Desc))
+      // apply pagination
+      .take(page_query.take())
+      .skip(page_query.skip())
+      .exec()
+      .await?;
+
+  let login_count = db
+      .login_attempt()
+      .count(logins_filter)
+      .exec()
+      .await?;
+
+  let list = login_objs.into_iter().map(LoginAttemptView::from).collect();
+
+  Ok(ApiResponse::page(list, login_count, page_query))
+}
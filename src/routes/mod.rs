@@ -1,2 +1,33 @@
 pub mod user;
-pub mod auth;
\ No newline at end of file
+pub mod auth;
+pub mod user_import;
+pub mod user_export;
+pub mod audit;
+pub mod group;
+pub mod grant;
+pub mod user_privacy;
+pub mod login_history;
+pub mod admin_stats;
+pub mod account_deletion;
+pub mod registry;
+
+use axum::Router;
+use registry::ApiVersion;
+
+/// Every route of `version`, without its prefix, see `registry` for the list
+pub fn create_routes(version: ApiVersion) -> Router {
+  match version {
+    ApiVersion::V1 => Router::new()
+      .merge(user::create_route())
+      .merge(user_import::create_route())
+      .merge(user_export::create_route())
+      .merge(audit::create_route())
+      .merge(group::create_route())
+      .merge(grant::create_route())
+      .merge(user_privacy::create_route())
+      .merge(login_history::create_route())
+      .merge(admin_stats::create_route())
+      .merge(account_deletion::create_route())
+      .merge(auth::create_route()),
+  }
+}
@@ -0,0 +1,148 @@
+use std::fmt;
+
+/*
+
+Registry of the public API
+
+Every route a version serves is listed here, with the version-less path
+it is mounted at (axum syntax) and, once it is on its way out, when it was
+deprecated and when it goes away. `deprecation_middleware` announces the
+latter in `Deprecation`/`Sunset` headers and the OpenAPI document of the
+version marks the operation as deprecated.
+
+*/
+
+/// Versions of the API, each one mounted under its own `/api/vN` prefix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiVersion {
+    V1,
+}
+
+impl ApiVersion {
+  pub const ALL: [ApiVersion; 1] = [ApiVersion::V1];
+
+  /// What unversioned requests get
+  pub const LATEST: ApiVersion = ApiVersion::V1;
+
+  pub fn number(&self) -> u32 {
+    match self {
+      ApiVersion::V1 => 1,
+    }
+  }
+
+  pub fn from_number(number: u32) -> Option<ApiVersion> {
+    ApiVersion::ALL.into_iter().find(|version| version.number() == number)
+  }
+
+  /// Path every route of the version is mounted under
+  pub fn prefix(&self) -> &'static str {
+    match self {
+      ApiVersion::V1 => "/api/v1",
+    }
+  }
+
+  pub fn routes(&self) -> &'static [ApiRoute] {
+    match self {
+      ApiVersion::V1 => V1_ROUTES,
+    }
+  }
+}
+
+impl fmt::Display for ApiVersion {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "v{}", self.number())
+  }
+}
+
+/// When a route was deprecated and when it will be removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deprecation {
+    /// Unix time, sent as `Deprecation: @<since>` (RFC 9745)
+    pub since: i64,
+    /// HTTP date, sent as `Sunset` (RFC 8594)
+    pub sunset: &'static str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApiRoute {
+    pub method: &'static str,
+    /// Path below the version prefix, e.g. `/users/:user_id`
+    pub path: &'static str,
+    pub deprecation: Option<Deprecation>,
+}
+
+/// Bare paths without a version prefix only remain as aliases of the latest version
+pub const BARE_PATH_DEPRECATION: Deprecation = Deprecation {
+  // 2026-10-19
+  since: 1792368000,
+  sunset: "Mon, 19 Apr 2027 00:00:00 GMT",
+};
+
+/// Replaced by `PATCH /users/:user_id`
+const PER_FIELD_UPDATE_DEPRECATION: Deprecation = Deprecation {
+  // 2026-10-19
+  since: 1792368000,
+  sunset: "Mon, 19 Apr 2027 00:00:00 GMT",
+};
+
+const fn route(method: &'static str, path: &'static str) -> ApiRoute {
+  ApiRoute { method, path, deprecation: None }
+}
+
+const fn deprecated_route(method: &'static str, path: &'static str, deprecation: Deprecation) -> ApiRoute {
+  ApiRoute { method, path, deprecation: Some(deprecation) }
+}
+
+const V1_ROUTES: &[ApiRoute] = &[
+  route("POST", "/login"),
+  route("POST", "/register"),
+  route("GET", "/register/availability"),
+  route("GET", "/users"),
+  route("GET", "/users/:user_id"),
+  route("PATCH", "/users/:user_id"),
+  route("DELETE", "/users/:user_id"),
+  deprecated_route("POST", "/users/:user_id/update_password", PER_FIELD_UPDATE_DEPRECATION),
+  deprecated_route("POST", "/users/:user_id/update_status", PER_FIELD_UPDATE_DEPRECATION),
+  route("POST", "/users/bulk"),
+  route("POST", "/users/import"),
+  route("GET", "/users/export"),
+  route("GET", "/audit"),
+  route("GET", "/groups"),
+  route("POST", "/groups"),
+  route("GET", "/groups/:group_id"),
+  route("PUT", "/groups/:group_id"),
+  route("DELETE", "/groups/:group_id"),
+  route("PUT", "/groups/:group_id/members/:user_id"),
+  route("DELETE", "/groups/:group_id/members/:user_id"),
+  route("PUT", "/groups/:group_id/grants/:grant"),
+  route("DELETE", "/groups/:group_id/grants/:grant"),
+  route("PUT", "/users/:user_id/grants/:grant"),
+  route("DELETE", "/users/:user_id/grants/:grant"),
+  route("GET", "/users/:user_id/permissions"),
+  route("GET", "/users/:user_id/data-export"),
+  route("POST", "/users/:user_id/erase"),
+  route("GET", "/users/:user_id/logins"),
+  route("GET", "/admin/stats"),
+  route("POST", "/users/me/delete-request"),
+];
+
+/// Path in OpenAPI syntax, `/users/:user_id` is `/users/{user_id}`
+pub fn openapi_path(path: &str) -> String {
+  path
+    .split('/')
+    .map(|segment| match segment.strip_prefix(':') {
+      Some(name) => format!("{{{}}}", name),
+      None => segment.to_string(),
+    })
+    .collect::<Vec<String>>()
+    .join("/")
+}
+
+/// Route of `version` serving `method` on `path`, which may use either syntax
+pub fn find(version: ApiVersion, method: &str, path: &str) -> Option<&'static ApiRoute> {
+  let path = openapi_path(path);
+  version
+    .routes()
+    .iter()
+    .find(|route| route.method.eq_ignore_ascii_case(method) && openapi_path(route.path) == path)
+}
@@ -1,18 +1,39 @@
 use axum::{
-  extract::{Json, Path, Query},
-  routing::{get, post, delete},
+  body::Bytes,
+  http::{header, HeaderName, StatusCode},
+  response::{IntoResponse, Response},
+  routing::{get, post, delete, patch},
   middleware::{self},
   Extension,
   Router,
 };
 use bcrypt::{DEFAULT_COST, hash};
-use serde::{Serialize, Deserialize};
-use utoipa::{IntoParams, ToSchema};
+use serde::Deserialize;
+use utoipa::IntoParams;
 use serde_json::json;
+use std::collections::HashMap;
+use uuid::Uuid;
+use validator::ValidationErrors;
 use crate::db::{self, user};
 use crate::error::{AppError, AppResult};
 use crate::middlewares::auth::auth_middleware;
+use crate::models::role::RoleExt;
+use crate::models::user_status::{UserStatus, UserStatusExt};
+use crate::models::user_view::{find_user_view, find_users_view, parse_fields, UserView, UserViewKind};
+use crate::utils::extract::{Json, Path, Query};
+use crate::utils::audit::{self, AuditAction, AuditContext};
+use crate::utils::etag::{etag, weak_etag, IfMatch, IfNoneMatch};
 use crate::utils::jwt::{Claims};
+use crate::utils::password::check_password_length;
+use crate::utils::username::{self, NameConflict, Username};
+use crate::utils::validation::{FieldError, Rules, Validate, ValidatedJson};
+use crate::models::permission::Permission;
+use crate::response::{ApiResponse, Page, PageQuery};
+use crate::utils::permission::{load_permissions, Permissions};
+pub use rust_learn_client::types::{
+  BulkItemResult, BulkItemState, BulkOperation, BulkUsersBody, BulkUsersData, BulkUsersFilter,
+  GetUserQuery, GetUsersAPIQuery, PatchUserBody, UpdateUserPasswordBody, UpdateUserStatusBody,
+};
 type Database = Extension<std::sync::Arc<db::PrismaClient>>;
 
 /*
@@ -21,37 +42,39 @@ Plan for User API
 
 /api/users => GET, POST
 /api/users/:user_id => GET
-/api/users/:user_id/update_password => POST
-/api/users/:user_id => DELETE
+/api/users/:user_id/update_password => POST (deprecated, use PATCH)
+/api/users/:user_id/update_status => POST (deprecated, use PATCH)
+/api/users/:user_id => DELETE, PATCH
+/api/users/bulk => POST
 
 */
-#[derive(Deserialize, IntoParams)]
-pub struct GetUsersAPIQuery {
-    page: Option<i32>,
-    page_size: Option<i32>,
-    status: Option<i32>,
-}
 
-#[derive(Serialize)]
-struct UsersData {
-    list: Vec<user::Data>,
-    count: i64,
-}
+user::select!(user_version_select {
+  version
+});
+
+/// Filters shared by the users list and the users export
+pub fn users_filter(status: Option<UserStatus>) -> Vec<user::WhereParam> {
+  let mut users_filter = vec![];
 
-#[derive(Serialize)]
-pub struct GetUsersAPIResponse {
-    code: String,
-    message: String,
-    data: UsersData,
+  // apply filter
+  if let Some(status) = status {
+    users_filter.push(user::status::equals(status.to_db()))
+  }
+
+  users_filter
 }
 
 pub fn create_route() -> Router {
+  // The per-field update routes are marked deprecated in `registry`
   Router::new()
       .route("/users", get(get_users_api))
       .route("/users/:user_id", get(get_user_api))
       .route("/users/:user_id/update_password", post(update_user_password_api))
       .route("/users/:user_id/update_status", post(update_user_status_api))
       .route("/users/:user_id", delete(delete_user_api))
+      .route("/users/:user_id", patch(patch_user_api))
+      .route("/users/bulk", post(bulk_users_api))
       .layer(middleware::from_fn(auth_middleware))
 }
 
@@ -59,39 +82,34 @@ pub fn create_route() -> Router {
   get,
   path = "/users",
   responses(
-      (status = 200, description = "Users found successfully"),
-      (status = UNAUTHORIZED, description = "Not Logged In")
+      (status = 200, description = "Users found successfully, admins get `UserAdmin` items and everybody else `UserPublic`, or `UserPartial` when `fields` is set", body = crate::response::GetUsersAPIResponse),
+      (status = BAD_REQUEST, description = "Unknown or not allowed fields", body = crate::error::ErrorResponse),
+      (status = UNAUTHORIZED, description = "Not Logged In", body = crate::error::ErrorResponse)
   ),
   params(
     GetUsersAPIQuery,
   )
 )]
 pub async fn get_users_api(
-  Extension(_claims): Extension<Claims>,
+  Extension(claims): Extension<Claims>,
+  permissions: Permissions,
   db: Database,
   Query(query): Query<GetUsersAPIQuery>,
-) -> AppResult<Json<GetUsersAPIResponse>> {
-  let mut users_filter = vec![];
-
-  // apply filter
-  
-
-  let status_ind = query.status.unwrap_or(99);
-  tracing::info!("{}", status_ind);
-  if status_ind < 99 {
-    users_filter.push(user::status::equals(status_ind))
-  }
-
-  let user_objs = db
-      .user()
-      .find_many(users_filter.clone())
-      // .select()
-      // apply pagination
-      .take(i64::from(query.page_size.unwrap_or(10)))
-      .skip(i64::from(query.page_size.unwrap_or(10) * (query.page.unwrap_or(1) - 1)))
-      .exec()
-      .await
-      .unwrap();
+) -> AppResult<ApiResponse<Page<UserView>>> {
+  let users_filter = users_filter(query.status);
+
+  let view_kind = UserViewKind::for_caller(&permissions, &claims, None);
+  let fields = query.fields.as_deref().map(|fields| parse_fields(fields, view_kind)).transpose()?;
+  let page_query = PageQuery::new(query.page, query.page_size)?;
+  let user_objs = find_users_view(
+    &db,
+    users_filter.clone(),
+    // apply pagination
+    page_query.skip(),
+    page_query.take(),
+    view_kind,
+    fields.as_deref(),
+  ).await?;
 
   let user_count = db
       .user()
@@ -99,100 +117,98 @@ pub async fn get_users_api(
       .exec()
       .await?;
   
-  let res_json_data = UsersData {
-    list: user_objs,
-    count: user_count,
-  };
-
-  let res_json = GetUsersAPIResponse {
-    code: "200".to_string(),
-    message: "OK".to_string(),
-    data: res_json_data,
-  };
-
-  Ok(Json(res_json))
+  Ok(ApiResponse::page(user_objs, user_count, page_query))
 }
 
 #[derive(Deserialize, IntoParams)]
 pub struct GetUserParams {
-  user_id: String,
-}
-
-#[derive(Serialize)]
-pub struct GetUserAPIResponse {
-    code: String,
-    message: String,
-    data: user::Data,
+  user_id: Uuid,
 }
 
 #[utoipa::path(
   get,
-  path = "/users/:user_id",
+  path = "/users/{user_id}",
   responses(
-      (status = 200, description = "User found successfully"),
-      (status = UNAUTHORIZED, description = "Not Logged In")
+      (status = 200, description = "User found successfully, admins get `UserAdmin`, the user themselves `UserSelf` and everybody else `UserPublic`, or `UserPartial` when `fields` is set", body = crate::response::GetUserAPIResponse),
+      (status = NOT_MODIFIED, description = "`If-None-Match` names the current version"),
+      (status = BAD_REQUEST, description = "Unknown or not allowed fields", body = crate::error::ErrorResponse),
+      (status = UNAUTHORIZED, description = "Not Logged In", body = crate::error::ErrorResponse),
+      (status = NOT_FOUND, description = "Record Not Found", body = crate::error::ErrorResponse)
   ),
   params(
     GetUserParams,
+    GetUserQuery,
   )
 )]
 pub async fn get_user_api(
-  Extension(_claims): Extension<Claims>,
+  Extension(claims): Extension<Claims>,
+  permissions: Permissions,
   db: Database,
+  if_none_match: IfNoneMatch,
   Path(GetUserParams{user_id}): Path<GetUserParams>,
-) -> AppResult<Json<GetUserAPIResponse>> {
-  let user_obj_q = db
+  Query(query): Query<GetUserQuery>,
+) -> AppResult<Response> {
+  let user_id = user_id.to_string();
+  let version = db
       .user()
-      .find_unique(user::id::equals(user_id))
+      .find_unique(user::id::equals(String::from(&user_id)))
+      .select(user_version_select::select())
       .exec()
-      .await?;
-      // .unwrap()
-      // .unwrap();
-
+      .await?
+      .ok_or(AppError::RecordNotFound)?
+      .version;
+
+  let view_kind = UserViewKind::for_caller(&permissions, &claims, Some(&user_id));
+  let fields = query.fields.as_deref().map(|fields| parse_fields(fields, view_kind)).transpose()?;
+
+  // Same version, other view or fields, is another body
+  let variant = match &fields {
+    Some(fields) => {
+      let mut names: Vec<&str> = fields.iter().map(|field| field.as_str()).collect();
+      names.sort();
+      format!("{}:{}", view_kind.as_str(), names.join(","))
+    }
+    None => view_kind.as_str().to_string(),
+  };
+  let tag = weak_etag(version, &variant);
 
-      // .map_err(|_| AppError::RecordNotFound)?
-      // .unwrap();
-  tracing::info!("{}", json!(user_obj_q));
-  if user_obj_q.is_none() {
-    return Err(AppError::RecordNotFound)
+  // The client's copy is still current
+  if if_none_match.matches(&tag) {
+    return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, tag)]).into_response())
   }
 
+  let user_view = find_user_view(&db, user_id, view_kind, fields.as_deref())
+      .await?
+      .ok_or(AppError::RecordNotFound)?;
 
-  let res_json = GetUserAPIResponse {
-    code: "200".to_string(),
-    message: "OK".to_string(),
-    data: user_obj_q.unwrap(),
-  };
-
-  Ok(Json(res_json))
+  Ok(([(header::ETAG, tag)], ApiResponse::ok(user_view)).into_response())
 }
 
 #[derive(Deserialize, IntoParams)]
 pub struct UpdateUserPasswordParams {
-    user_id: String,
+    user_id: Uuid,
 }
 
-#[derive(Deserialize, ToSchema)]
-pub struct UpdateUserPasswordBody {
-    password: String,
-    password_confirm: String,
-}
-
-#[derive(Serialize)]
-pub struct UpdateUserPasswordResponse {
-    code: String,
-    message: String,
-    data: String,
+impl Validate for UpdateUserPasswordBody {
+  fn validate(&self) -> Result<(), ValidationErrors> {
+    Rules::default()
+      .check("password", "length", check_password_length(&self.password))
+      .check("password_confirm", "length", check_password_length(&self.password_confirm))
+      .finish()
+  }
 }
 
 #[utoipa::path(
   post,
-  path = "/users/:user_id/update_password",
+  path = "/users/{user_id}/update_password",
   request_body = UpdateUserPasswordBody,
   responses(
-      (status = 200, description = "Password updated successfully"),
-      (status = UNAUTHORIZED, description = "Not Logged In"),
-      (status = BAD_REQUEST, description = "Password Dont Match")
+      (status = 200, description = "Password updated successfully. Deprecated, use `PATCH /users/:user_id`", body = crate::response::IdResponse),
+      (status = UNAUTHORIZED, description = "Not Logged In", body = crate::error::ErrorResponse),
+      (status = FORBIDDEN, description = "Missing the users.manage permission, or a permission the user holds", body = crate::error::ErrorResponse),
+      (status = BAD_REQUEST, description = "Password Dont Match", body = crate::error::ErrorResponse),
+      (status = PRECONDITION_FAILED, description = "`If-Match` does not name the current version", body = crate::error::ErrorResponse),
+      (status = UNPROCESSABLE_ENTITY, description = "Password out of bounds", body = crate::response::ValidationErrorResponse)
   ),
   params(
     UpdateUserPasswordParams,
@@ -200,10 +216,15 @@ pub struct UpdateUserPasswordResponse {
 )]
 pub async fn update_user_password_api(
     Extension(claims): Extension<Claims>,
+    permissions: Permissions,
     db: Database,
+    audit_ctx: AuditContext,
+    if_match: IfMatch,
     Path(UpdateUserPasswordParams{user_id}): Path<UpdateUserPasswordParams>,
-    Json(input): Json<UpdateUserPasswordBody>,
-) -> AppResult<Json<UpdateUserPasswordResponse>> {
+    ValidatedJson(input): ValidatedJson<UpdateUserPasswordBody>,
+) -> AppResult<([(HeaderName, String); 1], ApiResponse<String>)> {
+    let user_id = user_id.to_string();
+    permissions.require(Permission::UsersManage)?;
     // Avoid user delete his/her self
     if claims.sub.to_string().eq(&user_id) {
       return Err(AppError::OperationConflict)
@@ -212,54 +233,55 @@ pub async fn update_user_password_api(
     if !&input.password.eq(&input.password_confirm) {
       return Err(AppError::PasswordDontMatch)
     }
-    let password_hash = hash(&input.password, DEFAULT_COST).unwrap();
-
-    let user_obj = db
-        .user()
-        .update(
-            user::id::equals(user_id),
-            vec![
+    permissions.require_over(&db, String::from(&user_id)).await?;
+    let password_hash = hash(&input.password, DEFAULT_COST)?;
+
+    let actor_id = claims.sub.to_string();
+    let (user_obj, version) = db
+        ._transaction()
+        .run(|tx| async move {
+          let (user_obj, version) = update_user_versioned(&tx, user_id, &if_match, |_| {
+            Ok(vec![
                 user::password::set(password_hash),
-            ],
-        )
-        .exec()
-        .await
-        .unwrap();
-
-    let res_json = UpdateUserPasswordResponse {
-      code: "200".to_string(),
-      message: "OK".to_string(),
-      data: user_obj.id.to_string(),
-    };
-
-    Ok(Json(res_json))
+            ])
+          }).await?;
+
+          // Never write the hash itself to the audit log
+          audit::record(
+            &tx,
+            &audit_ctx,
+            AuditAction::PasswordUpdate,
+            Some(actor_id),
+            Some(user_obj.id.clone()),
+            Some(json!({ "changed": ["password"] })),
+          ).await?;
+
+          Ok::<(user::Data, i32), AppError>((user_obj, version))
+        })
+        .await?;
+
+    let res_json = ApiResponse::ok(user_obj.id.to_string());
+
+    Ok(([(header::ETAG, etag(version))], res_json))
 }
 
 #[derive(Deserialize, IntoParams)]
 pub struct UpdateUserStatusParams {
-    user_id: String,
-}
-
-#[derive(Deserialize, ToSchema)]
-pub struct UpdateUserStatusBody {
-    status: i32,
-}
-
-#[derive(Serialize)]
-pub struct UpdateUserStatusResponse {
-    code: String,
-    message: String,
-    data: String,
+    user_id: Uuid,
 }
 
 #[utoipa::path(
   post,
-  path = "/users/:user_id/update_status",
+  path = "/users/{user_id}/update_status",
   request_body = UpdateUserStatusBody,
   responses(
-      (status = 200, description = "Password updated successfully"),
-      (status = UNAUTHORIZED, description = "Not Logged In"),
-      (status = BAD_REQUEST, description = "Password Dont Match")
+      (status = 200, description = "Status updated successfully. Deprecated, use `PATCH /users/:user_id`", body = crate::response::IdResponse),
+      (status = BAD_REQUEST, description = "Users can't change their own status", body = crate::error::ErrorResponse),
+      (status = UNAUTHORIZED, description = "Not Logged In", body = crate::error::ErrorResponse),
+      (status = FORBIDDEN, description = "Missing the users.manage permission, or a permission the user holds", body = crate::error::ErrorResponse),
+      (status = NOT_FOUND, description = "Record Not Found", body = crate::error::ErrorResponse),
+      (status = CONFLICT, description = "Status transition not allowed", body = crate::error::ErrorResponse),
+      (status = PRECONDITION_FAILED, description = "`If-Match` does not name the current version", body = crate::error::ErrorResponse)
   ),
   params(
     UpdateUserStatusParams,
@@ -267,69 +289,89 @@ pub struct UpdateUserStatusResponse {
 )]
 pub async fn update_user_status_api(
     Extension(claims): Extension<Claims>,
+    permissions: Permissions,
     db: Database,
+    audit_ctx: AuditContext,
+    if_match: IfMatch,
     Path(UpdateUserStatusParams{user_id}): Path<UpdateUserStatusParams>,
     Json(input): Json<UpdateUserStatusBody>,
-) -> AppResult<Json<UpdateUserStatusResponse>> {
-    // Avoid user delete his/her self
-    if !claims.sub.to_string().eq(&user_id) {
+) -> AppResult<([(HeaderName, String); 1], ApiResponse<String>)> {
+    let user_id = user_id.to_string();
+    permissions.require(Permission::UsersManage)?;
+    // A suspended or banned manager must not lift it themselves
+    if claims.sub.to_string().eq(&user_id) {
       return Err(AppError::OperationConflict)
     }
-
-    let user_obj = db
-        .user()
-        .update(
-            user::id::equals(user_id),
-            vec![
-                user::status::set(input.status),
-            ],
-        )
-        .exec()
-        .await
-        .unwrap();
-
-    let res_json = UpdateUserStatusResponse {
-      code: "200".to_string(),
-      message: "OK".to_string(),
-      data: user_obj.id.to_string(),
-    };
-
-    Ok(Json(res_json))
+    permissions.require_over(&db, String::from(&user_id)).await?;
+
+    let actor_id = claims.sub.to_string();
+    let (user_obj, version) = db
+        ._transaction()
+        .run(|tx| async move {
+          let (user_obj, version) = update_user_versioned(&tx, user_id, &if_match, |current_user_obj| {
+            let next_status = UserStatus::from_db(current_user_obj.status)?.transition_to(input.status)?;
+            Ok(vec![
+                user::status::set(next_status.to_db()),
+            ])
+          }).await?;
+
+          audit::record(
+            &tx,
+            &audit_ctx,
+            AuditAction::StatusUpdate,
+            Some(actor_id),
+            Some(user_obj.id.clone()),
+            Some(json!({
+              "before": { "status": UserStatus::from_db(user_obj.status)? },
+              "after": { "status": input.status },
+            })),
+          ).await?;
+
+          Ok::<(user::Data, i32), AppError>((user_obj, version))
+        })
+        .await?;
+
+    let res_json = ApiResponse::ok(user_obj.id.to_string());
+
+    Ok(([(header::ETAG, etag(version))], res_json))
 }
 
 #[derive(Deserialize, IntoParams)]
 pub struct DeleteUserParams {
-  user_id: String,
-}
-
-#[derive(Serialize)]
-pub struct DeleteUserResponse {
-    code: String,
-    message: String,
-    data: String,
+  user_id: Uuid,
 }
 
 #[utoipa::path(
   delete,
-  path = "/users/:user_id",
+  path = "/users/{user_id}",
   responses(
-      (status = 200, description = "User Delete successfully"),
-      (status = BAD_REQUEST, description = "Record Not Found"),
-      (status = UNAUTHORIZED, description = "Not Logged In"),
+      (status = 200, description = "User Delete successfully", body = crate::response::IdResponse),
+      (status = BAD_REQUEST, description = "Users can't delete themselves", body = crate::error::ErrorResponse),
+      (status = UNAUTHORIZED, description = "Not Logged In", body = crate::error::ErrorResponse),
+      (status = FORBIDDEN, description = "Missing the users.manage permission, or a permission the user holds", body = crate::error::ErrorResponse),
+      (status = NOT_FOUND, description = "Record Not Found", body = crate::error::ErrorResponse),
+      (status = PRECONDITION_FAILED, description = "`If-Match` does not name the current version", body = crate::error::ErrorResponse),
   ),
   params(
-    UpdateUserPasswordParams,
+    DeleteUserParams,
   )
 )]
 pub async fn delete_user_api(
     Extension(claims): Extension<Claims>,
     db: Database,
+    permissions: Permissions,
+    audit_ctx: AuditContext,
+    if_match: IfMatch,
     Path(DeleteUserParams{user_id}): Path<DeleteUserParams>,
-) -> AppResult<Json<DeleteUserResponse>> {
+) -> AppResult<ApiResponse<String>> {
+    permissions.require(Permission::UsersManage)?;
+
+    let user_id = user_id.to_string();
     // Avoid user delete his/her self
     if claims.sub.to_string().eq(&user_id) {
       return Err(AppError::OperationConflict)
     }
+    permissions.require_over(&db, String::from(&user_id)).await?;
 
     let user_obj_q = db
       .user()
@@ -337,20 +379,572 @@ pub async fn delete_user_api(
       .exec()
       .await?;
 
-    if user_obj_q.is_none() {
-      return Err(AppError::RecordNotFound)
+    let user_obj = user_obj_q.ok_or(AppError::RecordNotFound)?;
+    let before_status = UserStatus::from_db(user_obj.status)?;
+
+    if !if_match.matches(user_obj.version) {
+      return Err(AppError::PreconditionFailed)
+    }
+
+    let actor_id = claims.sub.to_string();
+    db._transaction()
+      .run(|tx| async move {
+        let deleted_count = tx
+          .user()
+          .delete_many(vec![
+            user::id::equals(user_obj.id.clone()),
+            user::version::equals(user_obj.version),
+          ])
+          .exec()
+          .await?;
+
+        if deleted_count == 0 {
+          return Err(AppError::PreconditionFailed)
+        }
+
+        audit::record(
+          &tx,
+          &audit_ctx,
+          AuditAction::UserDelete,
+          Some(actor_id),
+          Some(user_obj.id),
+          Some(json!({ "before": { "name": user_obj.name, "status": before_status } })),
+        ).await?;
+
+        Ok::<(), AppError>(())
+      })
+      .await?;
+
+    Ok(ApiResponse::new("user-deleted", user_id))
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct PatchUserParams {
+    user_id: Uuid,
+}
+
+/// Who may change a field through `PATCH /users/:user_id`, see `editor_kind`
+const PATCH_RULES: &[(&str, &[UserViewKind])] = &[
+  ("name", &[UserViewKind::Owner, UserViewKind::Admin]),
+  ("password", &[UserViewKind::Owner, UserViewKind::Admin]),
+  ("status", &[UserViewKind::Admin]),
+  ("role", &[UserViewKind::Admin]),
+];
+
+/// Fields nobody may change on their own account, so a suspended or banned
+/// manager can't lift it and nobody hands themselves a role
+const PATCH_NOT_ON_SELF: &[&str] = &["status", "role"];
+
+/// Editing other users takes `users.manage`, reading them privately is not enough
+fn editor_kind(permissions: &Permissions, is_self: bool) -> UserViewKind {
+  if permissions.contains(Permission::UsersManage) {
+    UserViewKind::Admin
+  } else if is_self {
+    UserViewKind::Owner
+  } else {
+    UserViewKind::Public
+  }
+}
+
+/// Check every member of the patch document against `PATCH_RULES` and parse it
+fn parse_user_patch(document: &[u8], editor_kind: UserViewKind, is_self: bool) -> AppResult<PatchUserBody> {
+  let document: serde_json::Value = serde_json::from_slice(document)
+      .map_err(|e| AppError::InvalidPatch(vec![e.to_string()]))?;
+  // Anything but an object would replace the whole user
+  let members = document
+      .as_object()
+      .ok_or_else(|| AppError::InvalidPatch(vec!["patch must be a JSON object".to_string()]))?;
+
+  let mut errors: Vec<String> = vec![];
+  for (field, value) in members.iter() {
+    match PATCH_RULES.iter().find(|(name, _)| name == field) {
+      None => errors.push(format!("{}: unknown field", field)),
+      Some((_, allowed)) if !allowed.contains(&editor_kind) => {
+        return Err(AppError::FieldNotPermitted(field.clone()))
+      }
+      // No user field can be removed
+      Some(_) if value.is_null() => errors.push(format!("{}: cannot be removed", field)),
+      Some(_) => {}
+    }
+  }
+
+  if let Some(field) = PATCH_NOT_ON_SELF.iter().find(|field| is_self && members.contains_key(**field)) {
+    return Err(AppError::FieldNotPermitted(field.to_string()))
+  }
+
+  let patch: PatchUserBody = match serde_json::from_value(document.clone()) {
+    Ok(patch) => patch,
+    Err(e) => {
+      errors.push(e.to_string());
+      PatchUserBody::default()
     }
+  };
 
-    db.user()
-      .delete(user::id::equals(String::from(&user_id)))
+  if let Some(name) = &patch.name {
+    if let Err(reason) = Username::parse(name) {
+      errors.push(format!("name: {}", reason))
+    }
+  }
+  if let Some(password) = &patch.password {
+    if let Err(reason) = check_password_length(password) {
+      errors.push(format!("password: {}", reason))
+    }
+  }
+
+  if !errors.is_empty() {
+    return Err(AppError::InvalidPatch(errors))
+  }
+
+  Ok(patch)
+}
+
+#[utoipa::path(
+  patch,
+  path = "/users/{user_id}",
+  request_body(content = PatchUserBody, content_type = "application/merge-patch+json"),
+  responses(
+      (status = 200, description = "User updated successfully", body = crate::response::PatchUserResponse),
+      (status = UNAUTHORIZED, description = "Not Logged In", body = crate::error::ErrorResponse),
+      (status = FORBIDDEN, description = "Field may not be changed by the caller, or the user holds a permission the caller lacks", body = crate::error::ErrorResponse),
+      (status = NOT_FOUND, description = "Record Not Found", body = crate::error::ErrorResponse),
+      (status = CONFLICT, description = "Status transition not allowed or name taken", body = crate::error::ErrorResponse),
+      (status = PRECONDITION_FAILED, description = "`If-Match` does not name the current version", body = crate::error::ErrorResponse),
+      (status = UNPROCESSABLE_ENTITY, description = "Invalid patch document", body = crate::error::ErrorResponse)
+  ),
+  params(
+    PatchUserParams,
+  )
+)]
+pub async fn patch_user_api(
+    Extension(claims): Extension<Claims>,
+    permissions: Permissions,
+    db: Database,
+    audit_ctx: AuditContext,
+    if_match: IfMatch,
+    Path(PatchUserParams{user_id}): Path<PatchUserParams>,
+    body: Bytes,
+) -> AppResult<([(HeaderName, String); 1], ApiResponse<UserView>)> {
+    let user_id = user_id.to_string();
+    let view_kind = UserViewKind::for_caller(&permissions, &claims, Some(&user_id));
+    let is_self = claims.sub.to_string().eq(&user_id);
+    let patch = parse_user_patch(&body, editor_kind(&permissions, is_self), is_self)?;
+    // A role is a bundle of grants, only hand out what the caller holds
+    if let Some(role) = patch.role {
+      permissions.require_all(role.permissions())?;
+    }
+    if patch.password.is_some() || patch.status.is_some() || patch.role.is_some() {
+      permissions.require_over(&db, String::from(&user_id)).await?;
+    }
+
+    // Already validated by `parse_user_patch`
+    let username = patch
+        .name
+        .as_deref()
+        .map(Username::parse)
+        .transpose()
+        .map_err(|reason| AppError::InvalidPatch(vec![format!("name: {}", reason)]))?;
+    if let Some(username) = &username {
+      match username::find_conflict(&db, username, Some(&user_id)).await? {
+        Some(NameConflict::Taken) => return Err(AppError::RecordExisted),
+        Some(NameConflict::Confusable) => return Err(AppError::UsernameConfusable),
+        None => {}
+      }
+    }
+
+    let password_hash = match &patch.password {
+      Some(password) => Some(hash(password, DEFAULT_COST)?),
+      None => None,
+    };
+
+    let actor_id = claims.sub.to_string();
+    let tx_user_id = String::from(&user_id);
+    let version = db
+        ._transaction()
+        .run(|tx| async move {
+          let (user_obj, version) = update_user_versioned(&tx, tx_user_id, &if_match, |current_user_obj| {
+            let mut params = vec![];
+            if let Some(username) = &username {
+              params.push(user::name::set(username.display.clone()));
+              params.extend(username.params());
+            }
+            if let Some(password_hash) = password_hash {
+              params.push(user::password::set(password_hash));
+            }
+            if let Some(status) = patch.status {
+              let next_status = UserStatus::from_db(current_user_obj.status)?.transition_to(status)?;
+              params.push(user::status::set(next_status.to_db()));
+            }
+            if let Some(role) = patch.role {
+              params.push(user::role::set(role.as_str().to_string()));
+            }
+            Ok(params)
+          }).await?;
+
+          // Never write the hash itself to the audit log
+          let mut before = serde_json::Map::new();
+          let mut after = serde_json::Map::new();
+          if let Some(username) = &username {
+            before.insert("name".to_string(), json!(user_obj.name));
+            after.insert("name".to_string(), json!(username.display));
+          }
+          if let Some(status) = patch.status {
+            before.insert("status".to_string(), json!(UserStatus::from_db(user_obj.status)?));
+            after.insert("status".to_string(), json!(status));
+          }
+          if let Some(role) = patch.role {
+            before.insert("role".to_string(), json!(user_obj.role));
+            after.insert("role".to_string(), json!(role));
+          }
+          let changed: Vec<&str> = PATCH_RULES
+              .iter()
+              .map(|(field, _)| *field)
+              .filter(|field| match *field {
+                "name" => patch.name.is_some(),
+                "password" => patch.password.is_some(),
+                "status" => patch.status.is_some(),
+                "role" => patch.role.is_some(),
+                _ => false,
+              })
+              .collect();
+
+          audit::record(
+            &tx,
+            &audit_ctx,
+            AuditAction::UserUpdate,
+            Some(actor_id),
+            Some(user_obj.id.clone()),
+            Some(json!({ "changed": changed, "before": before, "after": after })),
+          ).await?;
+
+          Ok::<i32, AppError>(version)
+        })
+        .await?;
+
+    let user_view = find_user_view(&db, user_id, view_kind, None)
+        .await?
+        .ok_or(AppError::RecordNotFound)?;
+
+    Ok(([(header::ETAG, etag(version))], ApiResponse::ok(user_view)))
+}
+
+/// Apply the params built from the current record when `If-Match` still names
+/// its version, bumping the version. Returns the record as it was before.
+async fn update_user_versioned(
+  db: &db::PrismaClient,
+  user_id: String,
+  if_match: &IfMatch,
+  params: impl FnOnce(&user::Data) -> AppResult<Vec<user::SetParam>>,
+) -> AppResult<(user::Data, i32)> {
+  let current_user_obj = db
+      .user()
+      .find_unique(user::id::equals(String::from(&user_id)))
+      .exec()
+      .await?
+      .ok_or(AppError::RecordNotFound)?;
+
+  if !if_match.matches(current_user_obj.version) {
+    return Err(AppError::PreconditionFailed)
+  }
+
+  let mut params = params(&current_user_obj)?;
+  params.push(user::version::increment(1));
+
+  // Guarded by the version read above, a concurrent writer makes this match nothing
+  let updated_count = db
+      .user()
+      .update_many(
+        vec![
+          user::id::equals(user_id),
+          user::version::equals(current_user_obj.version),
+        ],
+        params,
+      )
       .exec()
       .await?;
 
-    let res_json = DeleteUserResponse {
-      code: "200".to_string(),
-      message: "User Deleted".to_string(),
-      data: String::from(&user_id),
+  if updated_count == 0 {
+    return Err(AppError::PreconditionFailed)
+  }
+
+  let version = current_user_obj.version + 1;
+  Ok((current_user_obj, version))
+}
+
+/// Upper bound of users touched by a single bulk request
+const BULK_MAX_ITEMS: usize = 100;
+
+/// Error raised inside the bulk transaction, remembering which item failed
+enum BulkTxError {
+    Query(prisma_client_rust::QueryError),
+    Item(usize, AppError),
+}
+
+impl From<prisma_client_rust::QueryError> for BulkTxError {
+  fn from(error: prisma_client_rust::QueryError) -> Self {
+    BulkTxError::Query(error)
+  }
+}
+
+/// Apply one bulk operation to a single user and write its audit entry. The
+/// item was planned from `version`, a user changed since then is not touched
+async fn apply_bulk_operation(
+  db: &db::PrismaClient,
+  audit_ctx: &AuditContext,
+  actor_id: &str,
+  item: &BulkItemResult,
+  version: i32,
+  operation: &BulkOperation,
+  password_hash: Option<&String>,
+) -> AppResult<()> {
+  let user_id = item.user_id.clone();
+  let planned = vec![
+    user::id::equals(user_id.clone()),
+    user::version::equals(version),
+  ];
+  let (changed_count, action, diff) = match operation {
+    BulkOperation::UpdateStatus { status } => {
+      let changed_count = db
+        .user()
+        .update_many(planned, vec![user::status::set((*status).to_db()), user::version::increment(1)])
+        .exec()
+        .await?;
+      (
+        changed_count,
+        AuditAction::StatusUpdate,
+        json!({ "before": { "status": item.before_status }, "after": { "status": status }, "bulk": true }),
+      )
+    }
+    BulkOperation::Delete => {
+      let changed_count = db
+        .user()
+        .delete_many(planned)
+        .exec()
+        .await?;
+      (
+        changed_count,
+        AuditAction::UserDelete,
+        json!({ "before": { "status": item.before_status }, "bulk": true }),
+      )
+    }
+    BulkOperation::ResetPassword { .. } => {
+      let password_hash = password_hash.ok_or(AppError::OperationConflict)?;
+      let changed_count = db
+        .user()
+        .update_many(planned, vec![user::password::set(password_hash.clone()), user::version::increment(1)])
+        .exec()
+        .await?;
+      (
+        changed_count,
+        AuditAction::PasswordUpdate,
+        json!({ "changed": ["password"], "bulk": true }),
+      )
+    }
+  };
+
+  if changed_count == 0 {
+    return Err(AppError::PreconditionFailed)
+  }
+
+  audit::record(db, audit_ctx, action, Some(actor_id.to_string()), Some(user_id), Some(diff)).await?;
+  Ok(())
+}
+
+#[utoipa::path(
+  post,
+  path = "/users/bulk",
+  request_body = BulkUsersBody,
+  responses(
+      (status = 200, description = "Bulk operation processed, see per-item results", body = crate::response::BulkUsersResponse),
+      (status = UNAUTHORIZED, description = "Not Logged In", body = crate::error::ErrorResponse),
+      (status = FORBIDDEN, description = "Missing the users.bulk permission", body = crate::error::ErrorResponse),
+      (status = BAD_REQUEST, description = "Missing targets or too many users", body = crate::error::ErrorResponse),
+      (status = UNPROCESSABLE_ENTITY, description = "Reset password out of bounds", body = crate::response::ValidationErrorResponse)
+  ),
+)]
+pub async fn bulk_users_api(
+    Extension(claims): Extension<Claims>,
+    permissions: Permissions,
+    db: Database,
+    audit_ctx: AuditContext,
+    Json(input): Json<BulkUsersBody>,
+) -> AppResult<ApiResponse<BulkUsersData>> {
+    permissions.require(Permission::UsersBulk)?;
+    if let BulkOperation::ResetPassword { password } = &input.operation {
+      check_password_length(password).map_err(|message| AppError::ValidationFailed(vec![FieldError {
+        field: "operation.password".to_string(),
+        code: "length".to_string(),
+        message,
+      }]))?;
+    }
+
+    // Resolve the targeted user ids, either given explicitly or by filter
+    let target_ids: Vec<String> = match (&input.ids, &input.filter) {
+      (Some(ids), _) => {
+        let mut ids = ids.clone();
+        ids.sort();
+        ids.dedup();
+        ids
+      }
+      (None, Some(filter)) => {
+        db.user()
+          .find_many(users_filter(filter.status))
+          .take((BULK_MAX_ITEMS + 1) as i64)
+          .exec()
+          .await?
+          .into_iter()
+          .map(|user_obj| user_obj.id)
+          .collect()
+      }
+      (None, None) => return Err(AppError::BulkTargetMissing),
     };
 
-    Ok(Json(res_json))
+    if target_ids.len() > BULK_MAX_ITEMS {
+      return Err(AppError::BulkLimitExceeded)
+    }
+
+    let existing_users = db
+        .user()
+        .find_many(vec![user::id::in_vec(target_ids.clone())])
+        .exec()
+        .await?;
+
+    // Plan every item before touching the database, from the versions read here
+    let mut results: Vec<BulkItemResult> = vec![];
+    let mut versions: HashMap<String, i32> = HashMap::new();
+    for user_id in target_ids.iter() {
+      let existing = existing_users.iter().find(|user_obj| user_obj.id.eq(user_id));
+      if let Some(user_obj) = existing {
+        versions.insert(user_id.clone(), user_obj.version);
+      }
+      let before_status = match existing {
+        Some(user_obj) => Some(UserStatus::from_db(user_obj.status)?),
+        None => None,
+      };
+      let denied = match existing {
+        Some(user_obj) => permissions.require_all(&load_permissions(&db, user_obj.id.clone()).await?.to_vec()).err(),
+        None => None,
+      };
+      let (after_status, error) = match (&input.operation, before_status) {
+        (_, None) => (None, Some("Record not found".to_string())),
+        // Avoid user changing his/her self in bulk
+        (_, Some(_)) if claims.sub.to_string().eq(user_id) => (None, Some("Operation Conflict".to_string())),
+        // Only users holding nothing beyond the caller's permissions
+        (_, Some(_)) if denied.is_some() => (None, denied.map(|e| e.to_string())),
+        (BulkOperation::UpdateStatus { status }, Some(before)) => match before.transition_to(*status) {
+          Ok(after) => (Some(after), None),
+          Err(e) => (None, Some(e.to_string())),
+        },
+        (BulkOperation::ResetPassword { .. }, Some(before)) => (Some(before), None),
+        (BulkOperation::Delete, Some(_)) => (None, None),
+      };
+      results.push(BulkItemResult {
+        user_id: user_id.clone(),
+        state: if error.is_some() { BulkItemState::Failed } else { BulkItemState::WouldApply },
+        before_status,
+        after_status,
+        error,
+      });
+    }
+
+    let has_invalid_item = results.iter().any(|item| item.state == BulkItemState::Failed);
+
+    if input.dry_run || (!input.best_effort && has_invalid_item) {
+      // A transaction containing an invalid item would be rolled back anyway
+      if !input.dry_run {
+        for item in results.iter_mut().filter(|item| item.state == BulkItemState::WouldApply) {
+          item.state = BulkItemState::NotAttempted;
+        }
+      }
+
+      let message = if input.dry_run { "bulk-dry-run" } else { "bulk-not-committed" };
+      return Ok(ApiResponse::new(message, BulkUsersData {
+        dry_run: input.dry_run,
+        best_effort: input.best_effort,
+        committed: false,
+        results,
+      }))
+    }
+
+    let password_hash = match &input.operation {
+      BulkOperation::ResetPassword { password } => Some(hash(password, DEFAULT_COST)?),
+      _ => None,
+    };
+
+    let actor_id = claims.sub.to_string();
+    let committed = if input.best_effort {
+      for item in results.iter_mut().filter(|item| item.state == BulkItemState::WouldApply) {
+        // Each item is still atomic with its own audit entry
+        let item_result = db
+            ._transaction()
+            .run(|tx| {
+              let (audit_ctx, actor_id, item, operation) = (&audit_ctx, &actor_id, &*item, &input.operation);
+              let version = versions[&item.user_id];
+              let password_hash = password_hash.as_ref();
+              async move {
+                apply_bulk_operation(&tx, audit_ctx, actor_id, item, version, operation, password_hash).await
+              }
+            })
+            .await;
+        match item_result {
+          Ok(_) => item.state = BulkItemState::Applied,
+          Err(e) => {
+            item.state = BulkItemState::Failed;
+            item.error = Some(e.to_string());
+          }
+        }
+      }
+      results.iter().any(|item| item.state == BulkItemState::Applied)
+    } else {
+      let (tx_items, tx_versions) = (&results, &versions);
+      let (tx_audit_ctx, tx_actor_id, operation) = (&audit_ctx, &actor_id, &input.operation);
+      let tx_password_hash = password_hash.as_ref();
+
+      let tx_result = db
+          ._transaction()
+          .run(|tx| async move {
+            for (index, item) in tx_items.iter().enumerate() {
+              let version = tx_versions[&item.user_id];
+              apply_bulk_operation(&tx, tx_audit_ctx, tx_actor_id, item, version, operation, tx_password_hash)
+                .await
+                .map_err(|e| BulkTxError::Item(index, e))?;
+            }
+            Ok::<(), BulkTxError>(())
+          })
+          .await;
+
+      match tx_result {
+        Ok(_) => {
+          for item in results.iter_mut() {
+            item.state = BulkItemState::Applied;
+          }
+          true
+        }
+        Err(e) => {
+          let (failed_index, error_message) = match e {
+            BulkTxError::Item(index, e) => (Some(index), e.to_string()),
+            BulkTxError::Query(e) => (None, AppError::from(e).to_string()),
+          };
+          for (index, item) in results.iter_mut().enumerate() {
+            item.state = match failed_index {
+              Some(failed) if index == failed => BulkItemState::Failed,
+              Some(failed) if index > failed => BulkItemState::NotAttempted,
+              _ => BulkItemState::RolledBack,
+            };
+            if item.state == BulkItemState::Failed || failed_index.is_none() {
+              item.error = Some(error_message.clone());
+            }
+          }
+          false
+        }
+      }
+    };
+
+    let res_json = ApiResponse::ok(BulkUsersData {
+      dry_run: false,
+      best_effort: input.best_effort,
+      committed,
+      results,
+    });
+
+    Ok(res_json)
 }
\ No newline at end of file
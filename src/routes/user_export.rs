@@ -0,0 +1,253 @@
+use axum::{
+  body::{Bytes, StreamBody},
+  http::header,
+  response::{IntoResponse, Response},
+  routing::get,
+  middleware::{self},
+  Extension,
+  Router,
+};
+use chrono::Utc;
+use futures::stream;
+use prisma_client_rust::Direction;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::sync::Arc;
+use crate::db::{self, user};
+use crate::error::{AppError, AppResult};
+use crate::middlewares::auth::auth_middleware;
+use crate::models::user_status::{UserStatus, UserStatusExt};
+use crate::routes::user::users_filter;
+use crate::utils::extract::Query;
+use crate::utils::jwt::{Claims};
+use crate::models::permission::Permission;
+use crate::utils::permission::Permissions;
+pub use rust_learn_client::types::{ExportFormat, ExportUsersQuery};
+type Database = Extension<std::sync::Arc<db::PrismaClient>>;
+
+/*
+
+Plan for User Export API
+
+/api/users/export => GET (?format=csv|xlsx|ndjson plus the users list filters)
+
+*/
+pub fn create_route() -> Router {
+  Router::new()
+      .route("/users/export", get(export_users_api))
+      .layer(middleware::from_fn(auth_middleware))
+}
+
+/// Number of users fetched from the database per query
+const EXPORT_CHUNK_SIZE: i64 = 500;
+
+// Exported columns, the password hash is never selected
+user::select!(user_export {
+  id
+  name
+  status
+});
+
+/// Header of the CSV and XLSX exports, in the order of `ExportRow`
+const EXPORT_COLUMNS: [&str; 3] = ["id", "name", "status"];
+
+#[derive(Serialize)]
+struct ExportRow<'a> {
+    id: &'a str,
+    name: Cow<'a, str>,
+    status: UserStatus,
+}
+
+/// Spreadsheets run a cell starting with one of these as a formula, a leading
+/// `'` keeps it text. Only for CSV and XLSX, NDJSON has the name as it is
+fn escape_formula(value: &str) -> Cow<'_, str> {
+  if value.starts_with(['=', '+', '-', '@']) {
+    Cow::Owned(format!("'{}", value))
+  } else {
+    Cow::Borrowed(value)
+  }
+}
+
+/// Fetch the next chunk of users after `cursor`, ordered by id
+async fn fetch_chunk(
+  db: &db::PrismaClient,
+  filter: Vec<user::WhereParam>,
+  cursor: Option<String>,
+) -> AppResult<Vec<user_export::Data>> {
+  let mut query = db
+      .user()
+      .find_many(filter)
+      .order_by(user::id::order(Direction::Asc))
+      .take(EXPORT_CHUNK_SIZE);
+
+  if let Some(cursor) = cursor {
+    query = query.cursor(user::id::equals(cursor)).skip(1);
+  }
+
+  Ok(query.select(user_export::select()).exec().await?)
+}
+
+/// Encode one chunk of users, the CSV header is written with the first chunk,
+/// even when it is empty
+fn encode_chunk(format: ExportFormat, rows: &[user_export::Data], first: bool) -> AppResult<Vec<u8>> {
+  let rows = rows
+      .iter()
+      .map(|row| Ok(ExportRow {
+        id: &row.id,
+        name: match format {
+          ExportFormat::Ndjson => Cow::Borrowed(row.name.as_str()),
+          _ => escape_formula(&row.name),
+        },
+        status: UserStatus::from_db(row.status)?,
+      }))
+      .collect::<AppResult<Vec<ExportRow>>>()?;
+
+  match format {
+    ExportFormat::Csv => {
+      let mut writer = csv::WriterBuilder::new()
+          .has_headers(false)
+          .from_writer(vec![]);
+      if first {
+        writer.write_record(EXPORT_COLUMNS).map_err(|_| AppError::ExportFailed)?;
+      }
+      for row in rows {
+        writer.serialize(row).map_err(|_| AppError::ExportFailed)?;
+      }
+      writer.into_inner().map_err(|_| AppError::ExportFailed)
+    }
+    ExportFormat::Ndjson => {
+      let mut buffer = vec![];
+      for row in rows {
+        serde_json::to_writer(&mut buffer, &row).map_err(|_| AppError::ExportFailed)?;
+        buffer.push(b'\n');
+      }
+      Ok(buffer)
+    }
+    ExportFormat::Xlsx => Err(AppError::ExportFailed),
+  }
+}
+
+/// Stream state: `None` cursor with `first` set means nothing was fetched yet
+struct ExportState {
+    db: Arc<db::PrismaClient>,
+    filter: Vec<user::WhereParam>,
+    format: ExportFormat,
+    cursor: Option<String>,
+    first: bool,
+    done: bool,
+}
+
+/// Text formats are streamed chunk by chunk without holding the whole list
+fn stream_text_export(state: ExportState) -> StreamBody<impl futures::Stream<Item = Result<Bytes, std::io::Error>>> {
+  StreamBody::new(stream::unfold(state, |mut state| async move {
+    if state.done {
+      return None
+    }
+
+    let chunk = fetch_chunk(&state.db, state.filter.clone(), state.cursor.clone())
+        .await
+        .and_then(|rows| {
+          let encoded = encode_chunk(state.format, &rows, state.first)?;
+          Ok((rows, encoded))
+        });
+
+    match chunk {
+      Ok((rows, encoded)) => {
+        state.first = false;
+        state.done = (rows.len() as i64) < EXPORT_CHUNK_SIZE;
+        state.cursor = rows.last().map(|row| row.id.clone());
+        Some((Ok(Bytes::from(encoded)), state))
+      }
+      Err(e) => {
+        tracing::error!("Users export failed: {}", e);
+        state.done = true;
+        Some((Err(std::io::Error::new(std::io::ErrorKind::Other, e.to_string())), state))
+      }
+    }
+  }))
+}
+
+/// XLSX is a zip archive and has to be assembled before it is sent,
+/// rows are still read from the database in chunks
+async fn build_xlsx_export(db: &db::PrismaClient, filter: Vec<user::WhereParam>) -> AppResult<Vec<u8>> {
+  let mut workbook = rust_xlsxwriter::Workbook::new();
+  let worksheet = workbook.add_worksheet();
+
+  for (col, title) in EXPORT_COLUMNS.iter().enumerate() {
+    worksheet.write_string(0, col as u16, *title).map_err(|_| AppError::ExportFailed)?;
+  }
+
+  let mut row_index: u32 = 1;
+  let mut cursor = None;
+  loop {
+    let rows = fetch_chunk(db, filter.clone(), cursor).await?;
+    for row in rows.iter() {
+      worksheet.write_string(row_index, 0, &row.id).map_err(|_| AppError::ExportFailed)?;
+      worksheet.write_string(row_index, 1, &escape_formula(&row.name)).map_err(|_| AppError::ExportFailed)?;
+      let status = UserStatus::from_db(row.status)?;
+      worksheet.write_string(row_index, 2, status.as_str()).map_err(|_| AppError::ExportFailed)?;
+      row_index += 1;
+    }
+    if (rows.len() as i64) < EXPORT_CHUNK_SIZE {
+      break
+    }
+    cursor = rows.last().map(|row| row.id.clone());
+  }
+
+  workbook.save_to_buffer().map_err(|_| AppError::ExportFailed)
+}
+
+#[utoipa::path(
+  get,
+  path = "/users/export",
+  responses(
+      (status = 200, description = "Users exported as a file attachment", content(
+        ("text/csv" = String),
+        ("application/x-ndjson" = String),
+        ("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet" = Vec<u8>),
+      )),
+      (status = UNAUTHORIZED, description = "Not Logged In", body = crate::error::ErrorResponse),
+      (status = FORBIDDEN, description = "Missing the users.export permission", body = crate::error::ErrorResponse)
+  ),
+  params(
+    ExportUsersQuery,
+  )
+)]
+pub async fn export_users_api(
+  Extension(_claims): Extension<Claims>,
+  permissions: Permissions,
+  db: Database,
+  Query(query): Query<ExportUsersQuery>,
+) -> AppResult<Response> {
+  permissions.require(Permission::UsersExport)?;
+
+  let filter = users_filter(query.status);
+
+  let content_disposition = format!(
+    "attachment; filename=\"users-{}.{}\"",
+    Utc::now().format("%Y%m%d"),
+    query.format.extension(),
+  );
+  let headers = [
+    (header::CONTENT_TYPE, query.format.content_type().to_string()),
+    (header::CONTENT_DISPOSITION, content_disposition),
+  ];
+
+  match query.format {
+    ExportFormat::Xlsx => {
+      let buffer = build_xlsx_export(&db, filter).await?;
+      Ok((headers, buffer).into_response())
+    }
+    format => {
+      let state = ExportState {
+        db: db.0.clone(),
+        filter,
+        format,
+        cursor: None,
+        first: true,
+        done: false,
+      };
+      Ok((headers, stream_text_export(state)).into_response())
+    }
+  }
+}
@@ -0,0 +1,359 @@
+use axum::{
+  extract::BodyStream,
+  routing::post,
+  middleware::{self},
+  Extension,
+  Router,
+};
+use bcrypt::{DEFAULT_COST, hash};
+use futures::TryStreamExt;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashSet;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+use tokio_util::io::StreamReader;
+use crate::db::{self, user};
+use crate::error::{AppError, AppResult};
+use crate::middlewares::auth::auth_middleware;
+use crate::models::user_status::{UserStatus, UserStatusExt};
+use crate::response::ApiResponse;
+use crate::utils::audit::{self, AuditAction, AuditContext};
+use crate::utils::extract::Query;
+use crate::utils::jwt::{Claims};
+use crate::utils::password::check_password_length;
+use crate::utils::username::{self, NameConflict, Username};
+use crate::models::permission::Permission;
+use crate::utils::permission::Permissions;
+pub use rust_learn_client::types::{ImportFormat, ImportMode, ImportRowError, ImportUsersData, ImportUsersQuery};
+type Database = Extension<std::sync::Arc<db::PrismaClient>>;
+
+/*
+
+Plan for User Import API
+
+/api/users/import => POST (body is CSV or NDJSON, streamed)
+
+*/
+pub fn create_route() -> Router {
+  Router::new()
+      .route("/users/import", post(import_users_api))
+      .layer(middleware::from_fn(auth_middleware))
+}
+
+/// One row of the import file, CSV headers and NDJSON keys use the same names
+#[derive(Deserialize)]
+struct ImportRow {
+    name: String,
+    password: Option<String>,
+    /// Pre-hashed bcrypt value, used instead of `password`
+    password_hash: Option<String>,
+    status: Option<UserStatus>,
+}
+
+fn is_bcrypt_hash(value: &str) -> bool {
+  value.len() == 60
+    && ["$2a$", "$2b$", "$2x$", "$2y$"].iter().any(|prefix| value.starts_with(prefix))
+}
+
+/// Password of a validated row, plain ones are only hashed when the row is written
+enum RowPassword {
+    Plain(String),
+    Hashed(String),
+}
+
+impl RowPassword {
+  /// bcrypt takes a while on purpose, keep it off the runtime's worker threads
+  async fn into_hash(self) -> AppResult<String> {
+    match self {
+      RowPassword::Hashed(password_hash) => Ok(password_hash),
+      RowPassword::Plain(password) => tokio::task::spawn_blocking(move || hash(password, DEFAULT_COST))
+        .await
+        .map_err(|e| AppError::Internal(format!("Password hashing task failed: {}", e)))?
+        .map_err(AppError::from),
+    }
+  }
+}
+
+/// Validate a single row, returning the name and the password to store
+fn validate_row(row: &ImportRow) -> Result<(Username, RowPassword), String> {
+  let username = Username::parse(&row.name).map_err(|reason| format!("name {}", reason))?;
+  // Only the user can ask for their deletion, which also schedules it
+  if row.status == Some(UserStatus::PendingDeletion) {
+    return Err("status pending_deletion can't be imported".to_string())
+  }
+
+  let password = match (&row.password, &row.password_hash) {
+    (Some(_), Some(_)) => Err("only one of password and password_hash is allowed".to_string()),
+    (None, None) => Err("password or password_hash is required".to_string()),
+    (None, Some(password_hash)) if !is_bcrypt_hash(password_hash) => {
+      Err("password_hash is not a bcrypt hash".to_string())
+    }
+    (None, Some(password_hash)) => Ok(RowPassword::Hashed(password_hash.clone())),
+    (Some(password), None) => check_password_length(password)
+      .map(|_| RowPassword::Plain(password.clone()))
+      .map_err(|reason| format!("password {}", reason)),
+  }?;
+
+  Ok((username, password))
+}
+
+enum RowOutcome {
+    Created,
+    Updated,
+}
+
+/// Import progress, rows are processed as soon as they are read from the body
+struct Importer<'a> {
+    db: &'a db::PrismaClient,
+    /// Of the caller, an upsert can't touch a user holding more
+    permissions: &'a Permissions,
+    audit_ctx: &'a AuditContext,
+    actor_id: String,
+    mode: ImportMode,
+    /// Skeletons of the names so far, look-alikes count as duplicates
+    seen_names: HashSet<String>,
+    data: ImportUsersData,
+}
+
+impl<'a> Importer<'a> {
+  fn fail(&mut self, line: u64, name: Option<String>, message: String) {
+    self.data.failed += 1;
+    self.data.errors.push(ImportRowError { line, name, message });
+  }
+
+  /// Rows are written one by one, each on its own. A row that fails, the
+  /// database included, is reported and the rows before it stay imported
+  async fn process(&mut self, line: u64, row: Result<ImportRow, String>) {
+    self.data.total += 1;
+
+    let row = match row {
+      Ok(row) => row,
+      Err(message) => {
+        self.fail(line, None, message);
+        return
+      }
+    };
+
+    match self.import_row(&row).await {
+      Ok(Ok(RowOutcome::Created)) => self.data.created += 1,
+      Ok(Ok(RowOutcome::Updated)) => self.data.updated += 1,
+      Ok(Err(message)) => self.fail(line, Some(row.name), message),
+      Err(e) => {
+        tracing::error!("Importing line {} failed: {:?}", line, e);
+        self.fail(line, Some(row.name), e.to_string());
+      }
+    }
+  }
+
+  /// What happened to a valid row, or why it was rejected
+  async fn import_row(&mut self, row: &ImportRow) -> AppResult<Result<RowOutcome, String>> {
+    let (username, password) = match validate_row(row) {
+      Ok(validated) => validated,
+      Err(message) => return Ok(Err(message)),
+    };
+
+    // The same name twice in one file is always an error
+    if !self.seen_names.insert(username.skeleton.clone()) {
+      return Ok(Err("duplicate name in file".to_string()))
+    }
+
+    let existed_user_obj = self.db
+        .user()
+        .find_first(vec![user::name_canonical::equals(Some(username.canonical.clone()))])
+        .exec()
+        .await?;
+
+    // Upserts only ever match the same name, never a look-alike
+    if existed_user_obj.is_none()
+      && username::find_conflict(self.db, &username, None).await? == Some(NameConflict::Confusable) {
+      return Ok(Err("name looks too much like an existing one".to_string()))
+    }
+
+    match (existed_user_obj, self.mode) {
+      (Some(_), ImportMode::Create) => Ok(Err("Record existed".to_string())),
+      (Some(user_obj), ImportMode::Upsert) => {
+        if let Err(e) = self.permissions.require_over(self.db, user_obj.id.clone()).await {
+          return match e {
+            AppError::PermissionDenied => Ok(Err(e.to_string())),
+            e => Err(e),
+          }
+        }
+        // Same rules as any other status change
+        let before_status = UserStatus::from_db(user_obj.status)?;
+        let next_status = match row.status {
+          Some(status) => match before_status.transition_to(status) {
+            Ok(next_status) => Some(next_status),
+            Err(e) => return Ok(Err(e.to_string())),
+          },
+          None => None,
+        };
+        if self.data.dry_run {
+          return Ok(Ok(RowOutcome::Updated))
+        }
+
+        let mut params = vec![user::password::set(password.into_hash().await?), user::version::increment(1)];
+        if let Some(next_status) = next_status {
+          params.push(user::status::set(next_status.to_db()));
+        }
+        // The audit entries are written with the update or not at all, like in bulk
+        let (audit_ctx, actor_id) = (self.audit_ctx, self.actor_id.clone());
+        let updated_count = self.db
+          ._transaction()
+          .run(|tx| async move {
+            let updated_count = tx
+              .user()
+              .update_many(
+                vec![
+                  user::id::equals(user_obj.id.clone()),
+                  user::version::equals(user_obj.version),
+                ],
+                params,
+              )
+              .exec()
+              .await?;
+            if updated_count == 0 {
+              return Ok(updated_count)
+            }
+
+            // Never write the hash itself to the audit log
+            audit::record(
+              &tx,
+              audit_ctx,
+              AuditAction::PasswordUpdate,
+              Some(actor_id.clone()),
+              Some(user_obj.id.clone()),
+              Some(json!({ "changed": ["password"], "import": true })),
+            ).await?;
+            if let Some(next_status) = next_status.filter(|next_status| *next_status != before_status) {
+              audit::record(
+                &tx,
+                audit_ctx,
+                AuditAction::StatusUpdate,
+                Some(actor_id),
+                Some(user_obj.id),
+                Some(json!({ "before": { "status": before_status }, "after": { "status": next_status }, "import": true })),
+              ).await?;
+            }
+
+            Ok::<i64, AppError>(updated_count)
+          })
+          .await?;
+        if updated_count == 0 {
+          return Ok(Err("user changed during the import".to_string()))
+        }
+        Ok(Ok(RowOutcome::Updated))
+      }
+      (None, _) => {
+        if self.data.dry_run {
+          return Ok(Ok(RowOutcome::Created))
+        }
+
+        let mut params = username.params();
+        if let Some(status) = row.status {
+          params.push(user::status::set(status.to_db()));
+        }
+        self.db
+          .user()
+          .create(username.display, password.into_hash().await?, params)
+          .exec()
+          .await?;
+        Ok(Ok(RowOutcome::Created))
+      }
+    }
+  }
+
+  async fn import_csv<R: AsyncBufRead + Unpin + Send>(&mut self, reader: R) -> AppResult<()> {
+    let mut csv_reader = csv_async::AsyncReaderBuilder::new()
+        .trim(csv_async::Trim::All)
+        .create_reader(reader);
+    let headers = csv_reader
+        .headers()
+        .await
+        .map_err(|_| AppError::InvalidImportFile)?
+        .clone();
+
+    let mut record = csv_async::StringRecord::new();
+    loop {
+      match csv_reader.read_record(&mut record).await {
+        Ok(false) => break,
+        Ok(true) => {
+          let line = record.position().map(|pos| pos.line()).unwrap_or(0);
+          let row = record
+              .deserialize::<ImportRow>(Some(&headers))
+              .map_err(|e| e.to_string());
+          self.process(line, row).await;
+        }
+        Err(e) if e.is_io_error() => return Err(AppError::InvalidImportFile),
+        Err(e) => {
+          let line = e.position().map(|pos| pos.line()).unwrap_or(0);
+          self.data.total += 1;
+          self.fail(line, None, e.to_string());
+        }
+      }
+    }
+    Ok(())
+  }
+
+  async fn import_ndjson<R: AsyncBufRead + Unpin>(&mut self, reader: R) -> AppResult<()> {
+    let mut lines = reader.lines();
+    let mut line: u64 = 0;
+    while let Some(content) = lines.next_line().await.map_err(|_| AppError::InvalidImportFile)? {
+      line += 1;
+      if content.trim().is_empty() {
+        continue
+      }
+      let row = serde_json::from_str::<ImportRow>(&content).map_err(|e| e.to_string());
+      self.process(line, row).await;
+    }
+    Ok(())
+  }
+}
+
+#[utoipa::path(
+  post,
+  path = "/users/import",
+  request_body(content = String, description = "CSV with a header row, or one JSON object per line", content_type = "text/csv"),
+  responses(
+      (status = 200, description = "File processed, see per-row errors", body = crate::response::ImportUsersResponse),
+      (status = UNAUTHORIZED, description = "Not Logged In", body = crate::error::ErrorResponse),
+      (status = FORBIDDEN, description = "Missing the users.import permission", body = crate::error::ErrorResponse),
+      (status = BAD_REQUEST, description = "Import file could not be read", body = crate::error::ErrorResponse)
+  ),
+  params(
+    ImportUsersQuery,
+  )
+)]
+pub async fn import_users_api(
+    Extension(claims): Extension<Claims>,
+    permissions: Permissions,
+    db: Database,
+    audit_ctx: AuditContext,
+    Query(query): Query<ImportUsersQuery>,
+    body: BodyStream,
+) -> AppResult<ApiResponse<ImportUsersData>> {
+    permissions.require(Permission::UsersImport)?;
+
+    let reader = StreamReader::new(
+      body.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e)),
+    );
+
+    let mut importer = Importer {
+      db: &db,
+      permissions: &permissions,
+      audit_ctx: &audit_ctx,
+      actor_id: claims.sub.to_string(),
+      mode: query.mode.unwrap_or(ImportMode::Create),
+      seen_names: HashSet::new(),
+      data: ImportUsersData {
+        dry_run: query.dry_run.unwrap_or(false),
+        ..Default::default()
+      },
+    };
+
+    match query.format {
+      ImportFormat::Csv => importer.import_csv(reader).await?,
+      ImportFormat::Ndjson => importer.import_ndjson(reader).await?,
+    }
+
+    Ok(ApiResponse::ok(importer.data))
+}
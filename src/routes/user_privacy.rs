@@ -0,0 +1,396 @@
+use axum::{
+  http::header,
+  response::{IntoResponse, Response},
+  routing::{get, post},
+  middleware::{self},
+  Extension,
+  Router,
+};
+use bcrypt::{DEFAULT_COST, hash};
+use chrono::{DateTime, FixedOffset, Utc};
+use prisma_client_rust::{operator::or, Direction};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::{Cursor, Write};
+use utoipa::IntoParams;
+use uuid::Uuid;
+use zip::{write::FileOptions, ZipWriter};
+use crate::db::{self, audit_event, group_member, login_attempt, user, user_grant};
+use crate::error::{AppError, AppResult};
+use crate::middlewares::auth::auth_middleware;
+use crate::models::permission::Permission;
+use crate::models::user_status::{UserStatus, UserStatusExt};
+use crate::response::ApiResponse;
+use crate::routes::audit::AuditEventView;
+use crate::utils::extract::Path;
+use crate::utils::audit::{self, AuditAction, AuditContext};
+use crate::utils::jwt::{Claims};
+use crate::utils::permission::Permissions;
+pub use rust_learn_client::types::ErasureReceipt;
+type Database = Extension<std::sync::Arc<db::PrismaClient>>;
+
+/*
+
+Plan for User Privacy API (the user themselves or users.privacy)
+
+/api/users/:user_id/data-export => GET (zip archive)
+/api/users/:user_id/erase => POST
+
+*/
+pub fn create_route() -> Router {
+  Router::new()
+      .route("/users/:user_id/data-export", get(export_user_data_api))
+      .route("/users/:user_id/erase", post(erase_user_api))
+      .layer(middleware::from_fn(auth_middleware))
+}
+
+// Everything stored on the user row except the password hash
+user::select!(user_data_select {
+  id
+  name
+  status
+  role
+  version
+  erased_at
+  created_at
+});
+
+#[derive(Deserialize, IntoParams)]
+pub struct UserPrivacyParams {
+  user_id: Uuid,
+}
+
+/// Personal data may be handled by the user themselves or with `users.privacy`
+fn require_privacy_access(permissions: &Permissions, claims: &Claims, user_id: &str) -> AppResult<bool> {
+  let is_self = claims.sub.to_string().eq(user_id);
+  if !is_self {
+    permissions.require(Permission::UsersPrivacy)?;
+  }
+  Ok(is_self)
+}
+
+/// Zip the given JSON documents, in memory like the XLSX export
+fn build_archive(files: Vec<(&str, Value)>) -> AppResult<Vec<u8>> {
+  let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+  let options = FileOptions::default();
+
+  for (name, content) in files {
+    let content = serde_json::to_vec_pretty(&content).map_err(|_| AppError::ExportFailed)?;
+    archive.start_file(name, options).map_err(|_| AppError::ExportFailed)?;
+    archive.write_all(&content).map_err(|_| AppError::ExportFailed)?;
+  }
+
+  Ok(archive.finish().map_err(|_| AppError::ExportFailed)?.into_inner())
+}
+
+#[utoipa::path(
+  get,
+  path = "/users/{user_id}/data-export",
+  responses(
+      (status = 200, description = "Zip archive of everything stored about the user", body = Vec<u8>, content_type = "application/zip"),
+      (status = UNAUTHORIZED, description = "Not Logged In", body = crate::error::ErrorResponse),
+      (status = FORBIDDEN, description = "Neither the user themselves nor holding users.privacy", body = crate::error::ErrorResponse),
+      (status = NOT_FOUND, description = "Record Not Found", body = crate::error::ErrorResponse)
+  ),
+  params(
+    UserPrivacyParams,
+  )
+)]
+pub async fn export_user_data_api(
+  Extension(claims): Extension<Claims>,
+  permissions: Permissions,
+  db: Database,
+  audit_ctx: AuditContext,
+  Path(UserPrivacyParams{user_id}): Path<UserPrivacyParams>,
+) -> AppResult<Response> {
+  let user_id = user_id.to_string();
+  require_privacy_access(&permissions, &claims, &user_id)?;
+
+  let user_obj = db
+      .user()
+      .find_unique(user::id::equals(String::from(&user_id)))
+      .select(user_data_select::select())
+      .exec()
+      .await?
+      .ok_or(AppError::RecordNotFound)?;
+
+  let grant_objs = db
+      .user_grant()
+      .find_many(vec![user_grant::user_id::equals(String::from(&user_id))])
+      .exec()
+      .await?;
+
+  let member_objs = db
+      .group_member()
+      .find_many(vec![group_member::user_id::equals(String::from(&user_id))])
+      .with(group_member::group::fetch())
+      .exec()
+      .await?;
+  let groups: Vec<Value> = member_objs
+      .iter()
+      .map(|member| json!({
+        "group_id": member.group_id,
+        "name": member.group().ok().map(|group| group.name.clone()),
+        "joined_at": member.created_at,
+      }))
+      .collect();
+
+  // Entries the user did and entries about the user
+  let audit_objs = db
+      .audit_event()
+      .find_many(vec![or(vec![
+        audit_event::actor_id::equals(Some(String::from(&user_id))),
+        audit_event::target_id::equals(Some(String::from(&user_id))),
+      ])])
+      .order_by(audit_event::created_at::order(Direction::Asc))
+      .exec()
+      .await?;
+  // Somebody else's actions on the user are listed without who did them or from where
+  let audit_events: Vec<AuditEventView> = audit_objs
+      .into_iter()
+      .map(AuditEventView::from)
+      .map(|mut audit_event| {
+        if audit_event.actor_id.as_deref() != Some(user_id.as_str()) {
+          audit_event.actor_id = None;
+          audit_event.ip = None;
+          audit_event.request_id = None;
+        }
+        audit_event
+      })
+      .collect();
+
+  let login_objs = db
+      .login_attempt()
+      .find_many(vec![login_attempt::user_id::equals(Some(String::from(&user_id)))])
+      .order_by(login_attempt::created_at::order(Direction::Asc))
+      .exec()
+      .await?;
+
+  let generated_at = Utc::now();
+  let user_data = json!({
+    "id": user_obj.id,
+    "name": user_obj.name,
+    "status": UserStatus::from_db(user_obj.status)?,
+    "role": user_obj.role,
+    "version": user_obj.version,
+    "erased_at": user_obj.erased_at,
+    "created_at": user_obj.created_at,
+  });
+  let manifest = json!({
+    "user_id": user_id,
+    "generated_at": generated_at,
+    "files": ["user.json", "grants.json", "groups.json", "logins.json", "audit_events.json"],
+    "notes": [
+      "The password is stored as a bcrypt hash and is not exported.",
+      "Sessions are stateless tokens and are not stored, logins are listed in logins.json.",
+      "No files or other blobs are stored for users.",
+      "Audit entries made by somebody else leave out who made them and from where.",
+    ],
+  });
+
+  let archive = build_archive(vec![
+    ("manifest.json", manifest),
+    ("user.json", user_data),
+    ("grants.json", json!(grant_objs)),
+    ("groups.json", json!(groups)),
+    ("logins.json", json!(login_objs)),
+    ("audit_events.json", json!(audit_events)),
+  ])?;
+
+  audit::record(
+    &db,
+    &audit_ctx,
+    AuditAction::DataExport,
+    Some(claims.sub.to_string()),
+    Some(String::from(&user_id)),
+    None,
+  ).await?;
+
+  let content_disposition = format!(
+    "attachment; filename=\"user-{}-{}.zip\"",
+    user_id,
+    generated_at.format("%Y%m%d"),
+  );
+  let headers = [
+    (header::CONTENT_TYPE, "application/zip".to_string()),
+    (header::CONTENT_DISPOSITION, content_disposition),
+  ];
+
+  Ok((headers, archive).into_response())
+}
+
+/// Anonymize a user in place and record the receipt. `actor_id` is `None`
+/// when the erasure was scheduled rather than requested. `conditions` must still
+/// hold when the user is written, otherwise nothing is erased and the result is
+/// `PreconditionFailed`
+pub async fn erase_user(
+  db: &db::PrismaClient,
+  audit_ctx: AuditContext,
+  actor_id: Option<String>,
+  user_id: String,
+  conditions: Vec<user::WhereParam>,
+) -> AppResult<ErasureReceipt> {
+  let user_obj = db
+      .user()
+      .find_unique(user::id::equals(user_id))
+      .select(user_data_select::select())
+      .exec()
+      .await?
+      .ok_or(AppError::RecordNotFound)?;
+
+  if user_obj.erased_at.is_some() {
+    return Err(AppError::AlreadyErased)
+  }
+
+  // Nobody knows this password, the account can never log in again
+  let password_hash = hash(Uuid::new_v4().to_string(), DEFAULT_COST)?;
+  let erased_at: DateTime<FixedOffset> = Utc::now().into();
+
+  let receipt = db._transaction()
+    .run(|tx| async move {
+      let mut filter = vec![
+        user::id::equals(String::from(&user_obj.id)),
+        // Two erasures racing, only one of them gets to write the receipt
+        user::erased_at::equals(None),
+      ];
+      filter.extend(conditions);
+
+      // The row and its id stay, so audit entries keep pointing at it
+      let updated_count = tx
+        .user()
+        .update_many(
+          filter,
+          vec![
+            user::name::set(format!("erased-{}", user_obj.id)),
+            // Frees the name and its look-alikes for new users
+            user::name_canonical::set(None),
+            user::name_skeleton::set(None),
+            user::password::set(password_hash),
+            user::status::set(UserStatus::Deactivated.to_db()),
+            user::role::set(String::from("user")),
+            user::erased_at::set(Some(erased_at)),
+            user::deletion_scheduled_at::set(None),
+            // Signs out every session with the rest of the erasure, not after it
+            user::tokens_revoked_at::set(Some(erased_at)),
+            user::version::increment(1),
+          ],
+        )
+        .exec()
+        .await?;
+
+      if updated_count == 0 {
+        return Err(AppError::PreconditionFailed)
+      }
+
+      let grants_removed = tx
+        .user_grant()
+        .delete_many(vec![user_grant::user_id::equals(String::from(&user_obj.id))])
+        .exec()
+        .await?;
+
+      let memberships_removed = tx
+        .group_member()
+        .delete_many(vec![group_member::user_id::equals(String::from(&user_obj.id))])
+        .exec()
+        .await?;
+
+      // Diffs about the user hold old names, IPs tie entries to a person
+      let mut audit_events_scrubbed = tx
+        .audit_event()
+        .update_many(
+          vec![audit_event::target_id::equals(Some(String::from(&user_obj.id)))],
+          vec![audit_event::diff::set(None), audit_event::ip::set(None)],
+        )
+        .exec()
+        .await?;
+      audit_events_scrubbed += tx
+        .audit_event()
+        .update_many(
+          vec![
+            audit_event::actor_id::equals(Some(String::from(&user_obj.id))),
+            audit_event::target_id::not(Some(String::from(&user_obj.id))),
+          ],
+          vec![audit_event::ip::set(None)],
+        )
+        .exec()
+        .await?;
+
+      // Login history stays for security, without what identifies the person
+      let logins_scrubbed = tx
+        .login_attempt()
+        .update_many(
+          vec![login_attempt::user_id::equals(Some(String::from(&user_obj.id)))],
+          vec![
+            login_attempt::name::set(format!("erased-{}", user_obj.id)),
+            login_attempt::ip::set(None),
+            login_attempt::user_agent::set(None),
+          ],
+        )
+        .exec()
+        .await?;
+
+      let diff = json!({
+        "erased_at": erased_at,
+        "grants_removed": grants_removed,
+        "memberships_removed": memberships_removed,
+        "audit_events_scrubbed": audit_events_scrubbed,
+        "logins_scrubbed": logins_scrubbed,
+      });
+      let audit_obj = audit::record(
+        &tx,
+        &audit_ctx,
+        AuditAction::UserErase,
+        actor_id,
+        Some(String::from(&user_obj.id)),
+        Some(diff),
+      ).await?;
+
+      Ok::<ErasureReceipt, AppError>(ErasureReceipt {
+        user_id: user_obj.id,
+        erased_at,
+        audit_event_id: audit_obj.id,
+        grants_removed,
+        memberships_removed,
+        audit_events_scrubbed,
+        logins_scrubbed,
+      })
+    })
+    .await?;
+
+  Ok(receipt)
+}
+
+#[utoipa::path(
+  post,
+  path = "/users/{user_id}/erase",
+  responses(
+      (status = 200, description = "Personal data erased, the receipt is kept in the audit log", body = crate::response::EraseUserResponse),
+      (status = BAD_REQUEST, description = "Users delete their own account through `/users/me/delete-request`", body = crate::error::ErrorResponse),
+      (status = UNAUTHORIZED, description = "Not Logged In", body = crate::error::ErrorResponse),
+      (status = FORBIDDEN, description = "Missing the users.privacy permission", body = crate::error::ErrorResponse),
+      (status = NOT_FOUND, description = "Record Not Found", body = crate::error::ErrorResponse),
+      (status = CONFLICT, description = "User data was already erased", body = crate::error::ErrorResponse),
+      (status = PRECONDITION_FAILED, description = "User was erased by another request meanwhile", body = crate::error::ErrorResponse)
+  ),
+  params(
+    UserPrivacyParams,
+  )
+)]
+pub async fn erase_user_api(
+  Extension(claims): Extension<Claims>,
+  permissions: Permissions,
+  db: Database,
+  audit_ctx: AuditContext,
+  Path(UserPrivacyParams{user_id}): Path<UserPrivacyParams>,
+) -> AppResult<ApiResponse<ErasureReceipt>> {
+  let user_id = user_id.to_string();
+  permissions.require(Permission::UsersPrivacy)?;
+  // Self-service goes through the deletion request, with its password check and grace period
+  if claims.sub.to_string().eq(&user_id) {
+    return Err(AppError::OperationConflict)
+  }
+
+  let receipt = erase_user(&db, audit_ctx, Some(claims.sub.to_string()), user_id, vec![]).await?;
+
+  Ok(ApiResponse::new("user-data-erased", receipt))
+}
@@ -0,0 +1,121 @@
+use axum::{
+  async_trait,
+  extract::FromRequestParts,
+  http::{header, request::Parts},
+};
+use prisma_client_rust::QueryError;
+use serde_json::Value;
+use std::convert::Infallible;
+use crate::db::{self, audit_event};
+use crate::utils::client_ip::{client_ip, trusted_proxies};
+
+/// Security relevant actions written to the audit log
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuditAction {
+    Login,
+    LoginFailed,
+    Register,
+    PasswordUpdate,
+    StatusUpdate,
+    UserDelete,
+    UserUpdate,
+    GroupCreate,
+    GroupUpdate,
+    GroupDelete,
+    GroupMemberAdd,
+    GroupMemberRemove,
+    GrantAdd,
+    GrantRevoke,
+    DataExport,
+    UserErase,
+    DeletionRequest,
+    DeletionCancel,
+}
+
+impl AuditAction {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      AuditAction::Login => "auth.login",
+      AuditAction::LoginFailed => "auth.login_failed",
+      AuditAction::Register => "auth.register",
+      AuditAction::PasswordUpdate => "user.password_update",
+      AuditAction::StatusUpdate => "user.status_update",
+      AuditAction::UserDelete => "user.delete",
+      AuditAction::UserUpdate => "user.update",
+      AuditAction::GroupCreate => "group.create",
+      AuditAction::GroupUpdate => "group.update",
+      AuditAction::GroupDelete => "group.delete",
+      AuditAction::GroupMemberAdd => "group.member_add",
+      AuditAction::GroupMemberRemove => "group.member_remove",
+      AuditAction::GrantAdd => "permission.grant",
+      AuditAction::GrantRevoke => "permission.revoke",
+      AuditAction::DataExport => "user.data_export",
+      AuditAction::UserErase => "user.erase",
+      AuditAction::DeletionRequest => "user.deletion_request",
+      AuditAction::DeletionCancel => "user.deletion_cancel",
+    }
+  }
+}
+
+/// Where a request came from, extracted once per request for audit entries
+#[derive(Debug, Clone, Default)]
+pub struct AuditContext {
+    pub ip: Option<String>,
+    pub request_id: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuditContext
+where
+  S: Send + Sync,
+{
+  type Rejection = Infallible;
+
+  async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+    // Same address the rate limiter counts the request against
+    let ip = client_ip(&parts.headers, &parts.extensions, trusted_proxies());
+
+    let request_id = parts
+        .headers
+        .get("x-request-id")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+
+    let user_agent = parts
+        .headers
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+
+    Ok(AuditContext {
+      ip,
+      request_id,
+      user_agent,
+    })
+  }
+}
+
+/// Write one audit entry, pass the transaction client to keep it atomic with the change
+pub async fn record(
+  db: &db::PrismaClient,
+  ctx: &AuditContext,
+  action: AuditAction,
+  actor_id: Option<String>,
+  target_id: Option<String>,
+  diff: Option<Value>,
+) -> Result<audit_event::Data, QueryError> {
+  db.audit_event()
+    .create(
+      action.as_str().to_string(),
+      vec![
+        audit_event::actor_id::set(actor_id),
+        audit_event::target_id::set(target_id),
+        audit_event::diff::set(diff),
+        audit_event::ip::set(ctx.ip.clone()),
+        audit_event::request_id::set(ctx.request_id.clone()),
+      ],
+    )
+    .exec()
+    .await
+}
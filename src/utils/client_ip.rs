@@ -0,0 +1,57 @@
+use axum::{
+  extract::ConnectInfo,
+  http::{Extensions, HeaderMap},
+};
+use std::net::SocketAddr;
+
+/// Number of proxies in front of the app that append to `X-Forwarded-For`,
+/// from `TRUSTED_PROXIES`, none when unset or not a number. The older
+/// `TRUST_FORWARDED_FOR=true` means one proxy
+pub fn trusted_proxies() -> usize {
+  match std::env::var("TRUSTED_PROXIES") {
+    Ok(value) => value.trim().parse().unwrap_or(0),
+    Err(_) => usize::from(std::env::var("TRUST_FORWARDED_FOR").as_deref() == Ok("true")),
+  }
+}
+
+/// Address of the client. Each trusted proxy appends the address it got the
+/// request from, so the entry `trusted_proxies` from the right is the last one
+/// a proxy wrote, anything left of it came from the client and proves nothing.
+/// The peer address when there are no trusted proxies or not enough entries
+pub fn client_ip(headers: &HeaderMap, extensions: &Extensions, trusted_proxies: usize) -> Option<String> {
+  let forwarded_ip = headers
+    .get("x-forwarded-for")
+    .filter(|_| trusted_proxies > 0)
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.rsplit(',').nth(trusted_proxies - 1))
+    .map(|value| value.trim().to_string())
+    .filter(|value| !value.is_empty());
+  let peer_ip = extensions
+    .get::<ConnectInfo<SocketAddr>>()
+    .map(|ConnectInfo(addr)| addr.ip().to_string());
+  forwarded_ip.or(peer_ip)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn forwarded_for(value: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("x-forwarded-for", value.parse().unwrap());
+    headers
+  }
+
+  #[test]
+  fn only_entries_written_by_trusted_proxies_count() {
+    let mut extensions = Extensions::new();
+    extensions.insert(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 443))));
+    let headers = forwarded_for("6.6.6.6, 203.0.113.7, 10.0.0.2");
+
+    assert_eq!(client_ip(&headers, &extensions, 0).as_deref(), Some("10.0.0.1"));
+    assert_eq!(client_ip(&headers, &extensions, 1).as_deref(), Some("10.0.0.2"));
+    assert_eq!(client_ip(&headers, &extensions, 2).as_deref(), Some("203.0.113.7"));
+    // More proxies than entries, the client could have written all of them
+    assert_eq!(client_ip(&forwarded_for("6.6.6.6"), &extensions, 2).as_deref(), Some("10.0.0.1"));
+  }
+}
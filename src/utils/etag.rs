@@ -0,0 +1,138 @@
+use axum::{
+  async_trait,
+  extract::FromRequestParts,
+  http::{header, request::Parts, HeaderMap, HeaderValue},
+};
+use std::convert::Infallible;
+
+/// Entity tag of a record with the given `version` column
+pub fn etag(version: i32) -> String {
+  format!("\"{}\"", version)
+}
+
+/// Weak entity tag of one representation of a record, for GET. Callers with
+/// another view or another field set get another body from the same version
+pub fn weak_etag(version: i32, variant: &str) -> String {
+  format!("W/\"{}-{}\"", version, variant)
+}
+
+/// Version a tag from `etag` or `weak_etag` was made from
+fn tag_version(tag: &str) -> Option<i32> {
+  tag.trim_matches('"').split('-').next()?.parse().ok()
+}
+
+/// Parsed `If-Match` / `If-None-Match` header value
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntityTags {
+    Any,
+    Tags(Vec<String>),
+}
+
+impl EntityTags {
+  fn parse(value: &str) -> EntityTags {
+    if value.trim() == "*" {
+      return EntityTags::Any
+    }
+    EntityTags::Tags(
+      value
+        .split(',')
+        .map(|tag| tag.trim())
+        // Versions are compared as values, a weak tag carries the same version
+        .map(|tag| tag.trim_start_matches("W/").to_string())
+        .filter(|tag| !tag.is_empty())
+        .collect(),
+    )
+  }
+
+  fn from_headers(headers: &HeaderMap, name: header::HeaderName) -> Option<EntityTags> {
+    let values: Vec<&str> = headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value: &HeaderValue| value.to_str().ok())
+        .collect();
+    if values.is_empty() {
+      None
+    } else {
+      Some(EntityTags::parse(&values.join(",")))
+    }
+  }
+
+  /// Whether a tag names `version`, whichever representation it was sent with
+  pub fn matches(&self, version: i32) -> bool {
+    match self {
+      EntityTags::Any => true,
+      EntityTags::Tags(tags) => tags.iter().any(|tag| tag_version(tag) == Some(version)),
+    }
+  }
+
+  /// Whether `tag` itself was sent, weak or not
+  pub fn matches_tag(&self, tag: &str) -> bool {
+    match self {
+      EntityTags::Any => true,
+      EntityTags::Tags(tags) => tags.iter().any(|sent| sent == tag.trim_start_matches("W/")),
+    }
+  }
+}
+
+/// `If-Match` request header, `None` when the client did not send one
+pub struct IfMatch(pub Option<EntityTags>);
+
+impl IfMatch {
+  /// Without the header every version is accepted
+  pub fn matches(&self, version: i32) -> bool {
+    self.0.as_ref().map_or(true, |tags| tags.matches(version))
+  }
+}
+
+/// `If-None-Match` request header, `None` when the client did not send one
+pub struct IfNoneMatch(pub Option<EntityTags>);
+
+impl IfNoneMatch {
+  /// True when the client's cached copy is still current, `tag` names the
+  /// representation the client would get now
+  pub fn matches(&self, tag: &str) -> bool {
+    self.0.as_ref().map_or(false, |tags| tags.matches_tag(tag))
+  }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for IfMatch
+where
+  S: Send + Sync,
+{
+  type Rejection = Infallible;
+
+  async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+    Ok(IfMatch(EntityTags::from_headers(&parts.headers, header::IF_MATCH)))
+  }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for IfNoneMatch
+where
+  S: Send + Sync,
+{
+  type Rejection = Infallible;
+
+  async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+    Ok(IfNoneMatch(EntityTags::from_headers(&parts.headers, header::IF_NONE_MATCH)))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn if_match_compares_versions_and_if_none_match_representations() {
+    let admin_tag = weak_etag(3, "admin");
+    let sent = EntityTags::parse(&admin_tag);
+
+    assert!(sent.matches(3));
+    assert!(!sent.matches(4));
+    assert!(EntityTags::parse(&etag(3)).matches(3));
+    assert!(sent.matches_tag(&admin_tag));
+    assert!(!sent.matches_tag(&weak_etag(3, "public")));
+    assert!(!sent.matches_tag(&weak_etag(3, "admin:id,name")));
+  }
+}
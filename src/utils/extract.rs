@@ -0,0 +1,55 @@
+use axum::extract::{
+  rejection::{JsonRejection, PathRejection, QueryRejection},
+  FromRequest,
+  FromRequestParts,
+};
+use crate::error::AppError;
+
+/// `axum::Json` answering malformed bodies with the JSON error envelope
+#[derive(FromRequest, Debug, Clone, Copy, Default)]
+#[from_request(via(axum::Json), rejection(AppError))]
+pub struct Json<T>(pub T);
+
+/// `axum::extract::Path` answering malformed segments with the JSON error envelope
+#[derive(FromRequestParts, Debug, Clone, Copy, Default)]
+#[from_request(via(axum::extract::Path), rejection(AppError))]
+pub struct Path<T>(pub T);
+
+/// `axum::extract::Query` answering malformed query strings with the JSON error envelope
+#[derive(FromRequestParts, Debug, Clone, Copy, Default)]
+#[from_request(via(axum::extract::Query), rejection(AppError))]
+pub struct Query<T>(pub T);
+
+impl From<JsonRejection> for AppError {
+  fn from(rejection: JsonRejection) -> Self {
+    match rejection {
+      JsonRejection::JsonSyntaxError(e) => AppError::MalformedJson(e.body_text()),
+      JsonRejection::JsonDataError(e) => AppError::InvalidJsonData(e.body_text()),
+      JsonRejection::MissingJsonContentType(_) => AppError::MissingJsonContentType,
+      // Reading the body failed, e.g. it is over the size limit
+      e => AppError::RequestRejected { status: e.status(), message: e.body_text() },
+    }
+  }
+}
+
+impl From<PathRejection> for AppError {
+  fn from(rejection: PathRejection) -> Self {
+    match rejection {
+      PathRejection::FailedToDeserializePathParams(e) => AppError::InvalidPath(e.body_text()),
+      // The route and the handler disagree, a bug rather than a bad request
+      e => {
+        tracing::error!("Path extraction failed: {}", e.body_text());
+        AppError::RequestRejected { status: e.status(), message: e.body_text() }
+      }
+    }
+  }
+}
+
+impl From<QueryRejection> for AppError {
+  fn from(rejection: QueryRejection) -> Self {
+    match rejection {
+      QueryRejection::FailedToDeserializeQueryString(e) => AppError::InvalidQuery(e.body_text()),
+      e => AppError::RequestRejected { status: e.status(), message: e.body_text() },
+    }
+  }
+}
@@ -0,0 +1,95 @@
+use fluent_templates::{fluent_bundle::FluentValue, static_loader, Loader};
+use std::collections::HashMap;
+use unic_langid::{langid, LanguageIdentifier};
+
+static_loader! {
+  static LOCALES = {
+    locales: "./locales",
+    fallback_language: "en-US",
+    // Messages are sent as JSON, not embedded in bidirectional text
+    customise: |bundle| bundle.set_use_isolating(false),
+  };
+}
+
+pub const DEFAULT_LOCALE: LanguageIdentifier = langid!("en-US");
+
+/// Locales with a catalog in `locales/`
+pub const SUPPORTED_LOCALES: [LanguageIdentifier; 2] = [langid!("en-US"), langid!("zh-CN")];
+
+tokio::task_local! {
+  /// Locale of the request being handled, set by `locale_middleware`
+  static LOCALE: LanguageIdentifier;
+}
+
+/// Parse the catalogs now, so a broken file fails at startup instead of on the first error
+pub fn load() {
+  let locales: Vec<String> = LOCALES.locales().map(|locale| locale.to_string()).collect();
+  tracing::info!("Loaded message catalogs: {}", locales.join(", "));
+}
+
+/// Supported locale for a language tag, matched on the language alone (`zh-TW` gets `zh-CN`)
+fn supported(tag: &str) -> Option<LanguageIdentifier> {
+  let requested: LanguageIdentifier = tag.trim().parse().ok()?;
+  SUPPORTED_LOCALES
+    .iter()
+    .find(|locale| locale.language == requested.language)
+    .cloned()
+}
+
+/// Pick the locale of a request, the `lang` preference wins over `Accept-Language`
+pub fn negotiate(preference: Option<&str>, accept_language: Option<&str>) -> LanguageIdentifier {
+  if let Some(locale) = preference.and_then(supported) {
+    return locale
+  }
+
+  let mut ranges: Vec<(&str, f32)> = accept_language
+    .unwrap_or_default()
+    .split(',')
+    .filter_map(|range| {
+      let mut params = range.split(';').map(|param| param.trim());
+      let tag = params.next().filter(|tag| !tag.is_empty())?;
+      let quality = params
+        .find_map(|param| param.strip_prefix("q="))
+        .and_then(|quality| quality.parse().ok())
+        .unwrap_or(1.0);
+      Some((tag, quality))
+    })
+    .filter(|(_, quality)| *quality > 0.0)
+    .collect();
+  // Stable, so equal qualities keep the client's order
+  ranges.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+  ranges
+    .into_iter()
+    .find_map(|(tag, _)| supported(tag))
+    .unwrap_or(DEFAULT_LOCALE)
+}
+
+/// Locale of the current request, English outside of one
+pub fn current_locale() -> LanguageIdentifier {
+  LOCALE.try_with(|locale| locale.clone()).unwrap_or(DEFAULT_LOCALE)
+}
+
+/// Run `future` with `locale` as the current locale
+pub async fn with_locale<F: std::future::Future>(locale: LanguageIdentifier, future: F) -> F::Output {
+  LOCALE.scope(locale, future).await
+}
+
+/// Message in the given locale only, `None` when the catalog lacks it
+pub fn lookup(locale: &LanguageIdentifier, key: &str, args: &[(&str, FluentValue<'static>)]) -> Option<String> {
+  let args: HashMap<String, FluentValue> = args
+    .iter()
+    .map(|(name, value)| (name.to_string(), value.clone()))
+    .collect();
+  LOCALES.lookup_single_language(locale, key, Some(&args))
+}
+
+/// Message in the current locale, falling back to English and then to the key itself
+pub fn translate(key: &str, args: &[(&str, FluentValue<'static>)]) -> String {
+  lookup(&current_locale(), key, args)
+    .or_else(|| lookup(&DEFAULT_LOCALE, key, args))
+    .unwrap_or_else(|| {
+      tracing::warn!("Missing message {}", key);
+      key.to_string()
+    })
+}
@@ -8,6 +8,7 @@ use jsonwebtoken::{
     Validation,
 };
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 use crate::error::AppError;
 use uuid::Uuid;
 
@@ -15,6 +16,10 @@ use uuid::Uuid;
 pub struct Claims {
     pub sub: Uuid,
     pub iat: i64,
+    /// `iat` in milliseconds, so a token signed in the same second as a
+    /// revocation but after it stays valid. Missing from older tokens
+    #[serde(default)]
+    pub iat_ms: Option<i64>,
     pub exp: i64,
 }
 
@@ -26,25 +31,55 @@ impl Claims {
         Self {
             sub: sub,
             iat: iat.timestamp(),
+            iat_ms: Some(iat.timestamp_millis()),
             exp: exp.timestamp(),
         }
     }
+
+    /// When the token was issued in milliseconds, older tokens only know the second
+    pub fn issued_at_ms(&self) -> i64 {
+        self.iat_ms.unwrap_or(self.iat * 1000)
+    }
+}
+
+/// Key the tokens are signed with, handed to `create_router` so tests can bring their own
+#[derive(Clone)]
+pub struct JwtSecret(Option<Arc<str>>);
+
+impl JwtSecret {
+    pub fn new(secret: &str) -> Self {
+        Self(Some(Arc::from(secret)))
+    }
+
+    /// From `JWT_SECRET`, without it no token can be signed or verified
+    pub fn from_env() -> Self {
+        Self(std::env::var("JWT_SECRET").ok().map(Arc::from))
+    }
+
+    fn key(&self) -> Result<&[u8], AppError> {
+        self.0
+            .as_deref()
+            .map(str::as_bytes)
+            .ok_or_else(|| AppError::Internal("JWT_SECRET is not set".to_string()))
+    }
 }
 
-pub fn sign(user_id: String) -> Result<String, String> {
+pub fn sign(secret: &JwtSecret, user_id: String) -> Result<String, AppError> {
+    let sub = Uuid::parse_str(&user_id)
+        .map_err(|err| AppError::Internal(format!("User id {} is not a UUID: {}", user_id, err)))?;
     let token = encode(
         &Header::default(),
-        &Claims::new(Uuid::parse_str(&user_id).unwrap()),
-        &EncodingKey::from_secret(std::env::var("JWT_SECRET").unwrap().as_ref()),
+        &Claims::new(sub),
+        &EncodingKey::from_secret(secret.key()?),
     )
-    .map_err(|err| err.to_string())?;
+    .map_err(|err| AppError::Internal(format!("Signing the token failed: {}", err)))?;
     Ok(token)
 }
 
-pub fn verify(token: &str) -> Result<Claims, AppError> {
+pub fn verify(secret: &JwtSecret, token: &str) -> Result<Claims, AppError> {
     let token_data = decode::<Claims>(
         &token,
-        &DecodingKey::from_secret(std::env::var("JWT_SECRET").unwrap().as_ref()),
+        &DecodingKey::from_secret(secret.key()?),
         &Validation::default()
     )
     .map_err(|_| AppError::JWTTokenInvalid)?;
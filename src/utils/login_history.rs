@@ -0,0 +1,72 @@
+use prisma_client_rust::QueryError;
+use crate::db::{self, login_attempt};
+use crate::models::user_status::UserStatus;
+use crate::utils::audit::AuditContext;
+
+/// How a login was authenticated, stored as `LoginAttempt.method`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoginMethod {
+    Password,
+}
+
+impl LoginMethod {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      LoginMethod::Password => "password",
+    }
+  }
+}
+
+/// Result of a login attempt, failures are stored as `LoginAttempt.reason`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoginOutcome {
+    Success,
+    UnknownUser,
+    WrongPassword,
+    AccountNotActive(UserStatus),
+}
+
+impl LoginOutcome {
+  pub fn reason(&self) -> Option<String> {
+    match self {
+      LoginOutcome::Success => None,
+      LoginOutcome::UnknownUser => Some("unknown_user".to_string()),
+      LoginOutcome::WrongPassword => Some("wrong_password".to_string()),
+      LoginOutcome::AccountNotActive(status) => Some(format!("account_{}", status)),
+    }
+  }
+}
+
+/// Longest user agent kept, the column is a `VarChar(255)`
+const USER_AGENT_MAX_CHARS: usize = 255;
+
+/// Write one row of login history
+pub async fn record(
+  db: &db::PrismaClient,
+  ctx: &AuditContext,
+  user_id: Option<String>,
+  name: &str,
+  method: LoginMethod,
+  outcome: LoginOutcome,
+) -> Result<login_attempt::Data, QueryError> {
+  let user_agent = ctx
+      .user_agent
+      .as_ref()
+      .map(|user_agent| user_agent.chars().take(USER_AGENT_MAX_CHARS).collect::<String>());
+
+  db.login_attempt()
+    .create(
+      // Names are limited like `User.name`, anything longer can't exist
+      name.chars().take(50).collect::<String>(),
+      outcome == LoginOutcome::Success,
+      method.as_str().to_string(),
+      vec![
+        login_attempt::user_id::set(user_id),
+        login_attempt::reason::set(outcome.reason()),
+        login_attempt::ip::set(ctx.ip.clone()),
+        login_attempt::user_agent::set(user_agent),
+      ],
+    )
+    .exec()
+    .await
+}
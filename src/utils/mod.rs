@@ -1 +1,12 @@
-pub mod jwt;
\ No newline at end of file
+pub mod jwt;
+pub mod password;
+pub mod audit;
+pub mod client_ip;
+pub mod etag;
+pub mod permission;
+pub mod login_history;
+pub mod username;
+pub mod validation;
+pub mod extract;
+pub mod i18n;
+pub mod rate_limit;
\ No newline at end of file
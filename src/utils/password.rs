@@ -0,0 +1,61 @@
+use bcrypt::verify;
+use crate::error::{AppError, AppResult};
+
+/// Bounds of a new password, the minimum in characters and the maximum in
+/// bytes, bcrypt ignores everything after 72 bytes
+pub const PASSWORD_MIN_LENGTH: usize = 8;
+pub const PASSWORD_MAX_BYTES: usize = 72;
+
+/// Rule for every new password: validated bodies, patches, bulk resets and imports
+pub fn check_password_length(password: &str) -> Result<(), String> {
+  if password.chars().count() < PASSWORD_MIN_LENGTH || password.len() > PASSWORD_MAX_BYTES {
+    return Err(format!("must be {} characters to {} bytes", PASSWORD_MIN_LENGTH, PASSWORD_MAX_BYTES))
+  }
+  Ok(())
+}
+
+/// Check a password against its stored bcrypt hash. `verify` answers a wrong
+/// password with `Ok(false)` and only errors on a malformed hash, both are
+/// wrong credentials
+pub fn check_password(password: &str, password_hash: &str) -> AppResult<()> {
+  match verify(password, password_hash) {
+    Ok(true) => Ok(()),
+    Ok(false) | Err(_) => Err(AppError::WrongCredentials),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn only_the_right_password_passes() {
+    // Lowest cost bcrypt allows, the check doesn't depend on it
+    let password_hash = bcrypt::hash("right-password", 4).unwrap();
+
+    assert!(check_password("right-password", &password_hash).is_ok());
+    assert!(matches!(check_password("wrong-password", &password_hash), Err(AppError::WrongCredentials)));
+    assert!(matches!(check_password("", &password_hash), Err(AppError::WrongCredentials)));
+    assert!(matches!(check_password("right-password", "not-a-hash"), Err(AppError::WrongCredentials)));
+  }
+
+  #[test]
+  fn new_passwords_are_8_characters_to_72_bytes() {
+    assert!(check_password_length("").is_err());
+    assert!(check_password_length("1234567").is_err());
+    assert!(check_password_length("12345678").is_ok());
+    assert!(check_password_length(&"a".repeat(72)).is_ok());
+    assert!(check_password_length(&"a".repeat(73)).is_err());
+  }
+
+  #[test]
+  fn the_maximum_counts_bytes() {
+    // 2 bytes each
+    assert!(check_password_length(&"é".repeat(8)).is_ok());
+    assert!(check_password_length(&"é".repeat(36)).is_ok());
+    assert!(check_password_length(&"é".repeat(37)).is_err());
+    // 3 bytes each, 24 characters already fill bcrypt's input
+    assert!(check_password_length(&"€".repeat(24)).is_ok());
+    assert!(check_password_length(&"€".repeat(30)).is_err());
+  }
+}
@@ -0,0 +1,161 @@
+use axum::{
+  async_trait,
+  extract::FromRequestParts,
+  http::request::Parts,
+};
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::Arc;
+use crate::db::{self, group, group_grant, group_member, user, user_grant};
+use crate::error::{AppError, AppResult};
+use crate::models::permission::{Grant, GrantExt, Permission};
+use crate::models::role::{Role, RoleExt};
+use crate::utils::jwt::Claims;
+
+/// Effective permissions of the logged user: role, direct grants and group grants
+#[derive(Debug, Clone, Default)]
+pub struct Permissions(HashSet<Permission>);
+
+impl Permissions {
+  pub fn contains(&self, permission: Permission) -> bool {
+    self.0.contains(&permission)
+  }
+
+  pub fn require(&self, permission: Permission) -> AppResult<()> {
+    if self.contains(permission) {
+      Ok(())
+    } else {
+      tracing::info!("Missing permission {}", permission);
+      Err(AppError::PermissionDenied)
+    }
+  }
+
+  /// Whatever is handed out, through a grant, a group or a role, must already be
+  /// held by the caller, otherwise `groups.manage` would be enough to become admin
+  pub fn require_all(&self, permissions: &[Permission]) -> AppResult<()> {
+    permissions.iter().try_for_each(|permission| self.require(*permission))
+  }
+
+  /// Resetting the password, changing the role or status of a user, or deleting
+  /// them, needs every permission they hold, so `users.manage` can't take over an admin
+  pub async fn require_over(&self, db: &db::PrismaClient, user_id: String) -> AppResult<()> {
+    let target = load_permissions(db, user_id).await?;
+    self.require_all(&target.to_vec())
+  }
+
+  pub fn to_vec(&self) -> Vec<Permission> {
+    let mut permissions: Vec<Permission> = self.0.iter().copied().collect();
+    permissions.sort_by_key(|permission| permission.as_str());
+    permissions
+  }
+}
+
+/// Compute the effective permissions of a user from the database
+pub async fn load_permissions(db: &db::PrismaClient, user_id: String) -> AppResult<Permissions> {
+  user::select!(user_role_select { role });
+
+  let user_obj = db
+      .user()
+      .find_unique(user::id::equals(String::from(&user_id)))
+      .select(user_role_select::select())
+      .exec()
+      .await?
+      .ok_or(AppError::RecordNotFound)?;
+
+  let user_grants = db
+      .user_grant()
+      .find_many(vec![user_grant::user_id::equals(String::from(&user_id))])
+      .exec()
+      .await?;
+
+  let group_grants = db
+      .group_grant()
+      .find_many(vec![group_grant::group::is(vec![
+        group::members::some(vec![group_member::user_id::equals(user_id)]),
+      ])])
+      .exec()
+      .await?;
+
+  let mut permissions: HashSet<Permission> = Role::from_db(user_obj.role.as_str())
+      .permissions()
+      .iter()
+      .copied()
+      .collect();
+
+  let grants = user_grants
+      .iter()
+      .map(|grant| grant.permission.as_str())
+      .chain(group_grants.iter().map(|grant| grant.permission.as_str()));
+  for grant in grants {
+    // Grants the code no longer knows about are ignored
+    match Grant::from_str(grant) {
+      Ok(grant) => permissions.extend(grant.permissions()),
+      Err(_) => tracing::warn!("Unknown grant {}", grant),
+    }
+  }
+
+  Ok(Permissions(permissions))
+}
+
+/// Computed on first use and cached in the request extensions, so middlewares
+/// and handlers of one request share a single lookup
+#[async_trait]
+impl<S> FromRequestParts<S> for Permissions
+where
+  S: Send + Sync,
+{
+  type Rejection = AppError;
+
+  async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+    if let Some(permissions) = parts.extensions.get::<Permissions>() {
+      return Ok(permissions.clone())
+    }
+
+    // Both are set before any handler runs: `auth_middleware` and the app's Extension layer
+    let claims = parts
+        .extensions
+        .get::<Claims>()
+        .cloned()
+        .ok_or(AppError::JWTTokenInvalid)?;
+    let db = parts
+        .extensions
+        .get::<Arc<db::PrismaClient>>()
+        .cloned()
+        .expect("PrismaClient extension is missing");
+
+    // The token outlived its user
+    let permissions = load_permissions(&db, claims.sub.to_string())
+        .await
+        .map_err(|e| match e {
+          AppError::RecordNotFound => AppError::JWTTokenInvalid,
+          e => e,
+        })?;
+    parts.extensions.insert(permissions.clone());
+
+    Ok(permissions)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn only_held_permissions_can_be_handed_out() {
+    let permissions = Permissions(HashSet::from([Permission::GroupsManage, Permission::UsersBulk]));
+
+    assert!(permissions.require_all(&[]).is_ok());
+    assert!(permissions.require_all(&Grant::Permission(Permission::UsersBulk).permissions()).is_ok());
+    assert!(matches!(
+      permissions.require_all(&Grant::Permission(Permission::AuditRead).permissions()),
+      Err(AppError::PermissionDenied)
+    ));
+    assert!(matches!(
+      permissions.require_all(&Grant::Role(Role::Admin).permissions()),
+      Err(AppError::PermissionDenied)
+    ));
+
+    let admin = Permissions(Role::Admin.permissions().iter().copied().collect());
+    assert!(admin.require_all(&Grant::Role(Role::Admin).permissions()).is_ok());
+  }
+}
@@ -0,0 +1,566 @@
+use axum::async_trait;
+use redis::aio::ConnectionManager;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use tokio::sync::OnceCell;
+use crate::error::{AppError, AppResult};
+use crate::utils::client_ip;
+
+/*
+
+Rate limiting with GCRA (generic cell rate algorithm)
+
+A quota of `limit` requests per `period` lets one request through every
+`period / limit`, with bursts of up to `limit`. All a bucket keeps is its
+theoretical arrival time (TAT): when it would be empty again if requests kept
+coming at the allowed rate. A request is let through unless that is more than
+`period` away, and then moves the TAT one interval further.
+
+Quotas come from `RATE_LIMITS`, e.g.
+
+  POST /login=10/60:ip; POST /register=5/3600:ip; *=600/60:user
+
+that is `<METHOD> <path>=<limit>/<seconds>[:<key>]` per route, paths as in the
+route registry, and `*` for the routes without their own quota.
+
+API keys counted by `api_key` quotas come from `RATE_LIMIT_API_KEYS`, e.g.
+
+  partner=3f1c...; billing=9a7e...
+
+that is `<name>=<key>` per key. Buckets are named after the key's name, and a
+request with any other key is counted by its address.
+
+*/
+
+/// Quotas used when `RATE_LIMITS` is not set
+const DEFAULT_RATE_LIMITS: &str = "POST /login=10/60:ip; POST /register=5/3600:ip; *=600/60:user";
+
+/// Buckets the in-memory store holds at most
+const MEMORY_STORE_CAPACITY: usize = 10_000;
+
+/// Number of requests allowed per period
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    pub limit: u32,
+    pub period_secs: u64,
+}
+
+impl Quota {
+  pub fn period_ms(&self) -> i64 {
+    self.period_secs as i64 * 1000
+  }
+
+  /// Time between two requests at the sustained rate
+  pub fn interval_ms(&self) -> i64 {
+    self.period_ms() / i64::from(self.limit)
+  }
+}
+
+/// What a bucket is kept for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyKind {
+    /// The client address
+    Ip,
+    /// The logged in user (`Claims.sub`), the address for anonymous requests
+    User,
+    /// The name of the `X-Api-Key` header's key, the address for requests
+    /// without one or with a key that is not configured
+    ApiKey,
+}
+
+impl KeyKind {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      KeyKind::Ip => "ip",
+      KeyKind::User => "user",
+      KeyKind::ApiKey => "api_key",
+    }
+  }
+}
+
+/// Quota of one route, or of every other route when `route` is `None`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteQuota {
+    /// Method and registry path, e.g. `("POST", "/login")`
+    pub route: Option<(String, String)>,
+    pub quota: Quota,
+    pub key: KeyKind,
+}
+
+impl RouteQuota {
+  /// Prefix of the buckets of this quota, routes with their own quota have their own buckets
+  pub fn bucket_prefix(&self) -> String {
+    match &self.route {
+      Some((method, path)) => format!("{} {}", method, path),
+      None => "*".to_string(),
+    }
+  }
+}
+
+/// Parse `RATE_LIMITS`, see the top of this module for the syntax
+pub fn parse_quotas(value: &str) -> Result<Vec<RouteQuota>, String> {
+  value
+    .split(';')
+    .map(|entry| entry.trim())
+    .filter(|entry| !entry.is_empty())
+    .map(|entry| {
+      let (route, rule) = entry
+        .rsplit_once('=')
+        .ok_or_else(|| format!("{}: expected <route>=<limit>/<seconds>", entry))?;
+      let route = match route.trim() {
+        "*" => None,
+        route => {
+          let (method, path) = route
+            .split_once(' ')
+            .ok_or_else(|| format!("{}: expected <METHOD> <path> or *", entry))?;
+          Some((method.trim().to_uppercase(), path.trim().to_string()))
+        }
+      };
+
+      let (rate, key) = match rule.split_once(':') {
+        Some((rate, key)) => (rate, key.trim()),
+        None => (rule, "ip"),
+      };
+      let key = match key {
+        "ip" => KeyKind::Ip,
+        "user" => KeyKind::User,
+        "api_key" => KeyKind::ApiKey,
+        key => return Err(format!("{}: unknown key {}, expected ip, user or api_key", entry, key)),
+      };
+      let (limit, period_secs) = rate
+        .split_once('/')
+        .and_then(|(limit, period)| Some((limit.trim().parse::<u32>().ok()?, period.trim().parse::<u64>().ok()?)))
+        .filter(|(limit, period_secs)| *limit > 0 && *period_secs * 1000 >= u64::from(*limit))
+        .ok_or_else(|| format!("{}: expected a positive limit per a period of seconds", entry))?;
+
+      Ok(RouteQuota { route, quota: Quota { limit, period_secs }, key })
+    })
+    .collect()
+}
+
+/// Parse `RATE_LIMIT_API_KEYS` into a map from key to name, see the top of this module
+pub fn parse_api_keys(value: &str) -> Result<HashMap<String, String>, String> {
+  let mut api_keys = HashMap::new();
+  let entries = value.split(';').map(|entry| entry.trim()).filter(|entry| !entry.is_empty());
+  for (index, entry) in entries.enumerate() {
+    // Errors name the entry by position, they must not echo a key
+    let (name, key) = entry
+      .split_once('=')
+      .map(|(name, key)| (name.trim(), key.trim()))
+      .filter(|(name, key)| !name.is_empty() && !key.is_empty())
+      .ok_or_else(|| format!("entry {}: expected <name>=<key>", index + 1))?;
+    if api_keys.insert(key.to_string(), name.to_string()).is_some() {
+      return Err(format!("entry {}: key is listed twice", index + 1))
+    }
+  }
+  Ok(api_keys)
+}
+
+/// Outcome of one request against its bucket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// Until the bucket is full again
+    pub reset_ms: i64,
+    /// Until the next request would be let through, 0 when this one was
+    pub retry_after_ms: i64,
+}
+
+impl Decision {
+  /// `tat` is the bucket's TAT after the request, or unchanged when it was refused
+  pub fn new(quota: Quota, now: i64, allowed: bool, tat: i64) -> Self {
+    let backlog = (tat - now).max(0);
+    let remaining = ((quota.period_ms() - backlog) / quota.interval_ms()).max(0);
+    let retry_after_ms = if allowed {
+      0
+    } else {
+      (tat + quota.interval_ms() - quota.period_ms() - now).max(0)
+    };
+    Decision {
+      allowed,
+      limit: quota.limit,
+      remaining: remaining as u32,
+      reset_ms: backlog,
+      retry_after_ms,
+    }
+  }
+}
+
+/// GCRA step, returns the decision and the TAT to store when the request is let through
+pub fn gcra(quota: Quota, now: i64, stored_tat: Option<i64>) -> (Decision, Option<i64>) {
+  let tat = stored_tat.unwrap_or(now).max(now);
+  let new_tat = tat + quota.interval_ms();
+  if now < new_tat - quota.period_ms() {
+    return (Decision::new(quota, now, false, tat), None)
+  }
+  (Decision::new(quota, now, true, new_tat), Some(new_tat))
+}
+
+/// Where buckets are kept, shared by every instance of the app when it is not in memory
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+  /// Count one request against `key` at `now` (Unix time in ms), atomically
+  async fn acquire(&self, key: &str, quota: Quota, now: i64) -> AppResult<Decision>;
+}
+
+/// Buckets of this process only, at most `capacity` of them
+pub struct MemoryStore {
+    tats: Mutex<HashMap<String, i64>>,
+    capacity: usize,
+}
+
+impl Default for MemoryStore {
+  fn default() -> Self {
+    MemoryStore::with_capacity(MEMORY_STORE_CAPACITY)
+  }
+}
+
+impl MemoryStore {
+  pub fn with_capacity(capacity: usize) -> Self {
+    MemoryStore {
+      tats: Mutex::new(HashMap::new()),
+      capacity: capacity.max(1),
+    }
+  }
+
+  /// A panic while the lock was held leaves at worst one stale bucket
+  fn tats(&self) -> MutexGuard<'_, HashMap<String, i64>> {
+    self.tats.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+  }
+
+  /// Make room for a new bucket once the store is full. Expired buckets go
+  /// first, then the ones closest to being full again, until a tenth of the
+  /// capacity is free, so this runs once per that many new keys and not on
+  /// every request. An evicted bucket starts over as full
+  fn make_room(&self, tats: &mut HashMap<String, i64>, now: i64) {
+    // A TAT in the past is the same as no bucket at all
+    tats.retain(|_, tat| *tat > now);
+
+    let keep = self.capacity * 9 / 10;
+    if tats.len() <= keep {
+      return
+    }
+    let excess = tats.len() - keep;
+    let mut by_tat: Vec<(i64, String)> = tats.iter().map(|(key, tat)| (*tat, key.clone())).collect();
+    by_tat.select_nth_unstable(excess - 1);
+    for (_, key) in &by_tat[..excess] {
+      tats.remove(key);
+    }
+  }
+}
+
+#[async_trait]
+impl RateLimitStore for MemoryStore {
+  async fn acquire(&self, key: &str, quota: Quota, now: i64) -> AppResult<Decision> {
+    let mut tats = self.tats();
+    if tats.len() >= self.capacity && !tats.contains_key(key) {
+      self.make_room(&mut tats, now);
+    }
+
+    let (decision, new_tat) = gcra(quota, now, tats.get(key).copied());
+    if let Some(new_tat) = new_tat {
+      tats.insert(key.to_string(), new_tat);
+    }
+    Ok(decision)
+  }
+}
+
+/// Same steps as `gcra`, run by the server so that instances can't race
+const GCRA_SCRIPT: &str = r"
+local now = tonumber(ARGV[1])
+local interval = tonumber(ARGV[2])
+local period = tonumber(ARGV[3])
+local tat = tonumber(redis.call('GET', KEYS[1]) or now)
+if tat < now then tat = now end
+local new_tat = tat + interval
+if now < new_tat - period then return {0, tat} end
+redis.call('SET', KEYS[1], new_tat, 'PX', new_tat - now)
+return {1, new_tat}
+";
+
+/// What `RedisStore` needs from the server, so the store can be tested without one
+#[async_trait]
+pub trait RedisBackend: Send + Sync {
+  /// Run `GCRA_SCRIPT` on `key` with `now`, the interval and the period in ms,
+  /// returns 1 or 0 for whether the request went through, and the TAT
+  async fn run_gcra(&self, key: &str, now: i64, interval_ms: i64, period_ms: i64) -> AppResult<(i64, i64)>;
+}
+
+/// Redis, or anything speaking its protocol and running Lua scripts
+/// (Valkey, KeyDB, Dragonfly, ...)
+pub struct RedisConnection {
+    client: redis::Client,
+    /// Opened on first use, and reconnected by the manager when it drops
+    connection: OnceCell<ConnectionManager>,
+    script: redis::Script,
+}
+
+impl RedisConnection {
+  pub fn open(url: &str) -> AppResult<Self> {
+    let client = redis::Client::open(url)
+        .map_err(|e| AppError::Internal(format!("Invalid rate limit store URL: {}", e)))?;
+    Ok(RedisConnection {
+      client,
+      connection: OnceCell::new(),
+      script: redis::Script::new(GCRA_SCRIPT),
+    })
+  }
+}
+
+#[async_trait]
+impl RedisBackend for RedisConnection {
+  async fn run_gcra(&self, key: &str, now: i64, interval_ms: i64, period_ms: i64) -> AppResult<(i64, i64)> {
+    let mut connection = self.connection
+        .get_or_try_init(|| self.client.get_tokio_connection_manager())
+        .await
+        .map_err(|e| AppError::Internal(format!("Rate limit store unreachable: {}", e)))?
+        .clone();
+
+    self.script
+        .key(key)
+        .arg(now)
+        .arg(interval_ms)
+        .arg(period_ms)
+        .invoke_async(&mut connection)
+        .await
+        .map_err(|e| AppError::Internal(format!("Rate limit store failed: {}", e)))
+  }
+}
+
+/// Buckets in Redis, they expire once they are full again
+pub struct RedisStore<B: RedisBackend = RedisConnection> {
+    backend: B,
+}
+
+impl RedisStore {
+  pub fn open(url: &str) -> AppResult<Self> {
+    Ok(RedisStore::new(RedisConnection::open(url)?))
+  }
+}
+
+impl<B: RedisBackend> RedisStore<B> {
+  pub fn new(backend: B) -> Self {
+    RedisStore { backend }
+  }
+}
+
+#[async_trait]
+impl<B: RedisBackend> RateLimitStore for RedisStore<B> {
+  async fn acquire(&self, key: &str, quota: Quota, now: i64) -> AppResult<Decision> {
+    let (allowed, tat) = self.backend
+        .run_gcra(&format!("rate_limit:{}", key), now, quota.interval_ms(), quota.period_ms())
+        .await?;
+
+    Ok(Decision::new(quota, now, allowed == 1, tat))
+  }
+}
+
+/// Quotas and the store their buckets live in
+pub struct RateLimiter {
+    quotas: Vec<RouteQuota>,
+    store: Box<dyn RateLimitStore>,
+    /// Proxies whose `X-Forwarded-For` entries are trusted, see `client_ip::trusted_proxies`
+    pub trusted_proxies: usize,
+    /// Known API keys and their names, other keys don't get a bucket of their own
+    pub api_keys: HashMap<String, String>,
+}
+
+impl RateLimiter {
+  pub fn new(quotas: Vec<RouteQuota>, store: Box<dyn RateLimitStore>) -> Self {
+    RateLimiter { quotas, store, trusted_proxies: 0, api_keys: HashMap::new() }
+  }
+
+  /// `RATE_LIMITS` (`off` turns limiting off), `RATE_LIMIT_STORE` (`memory`, the
+  /// default, or a `redis://` URL), `RATE_LIMIT_API_KEYS` and `TRUSTED_PROXIES`.
+  /// Panics on a broken configuration, it is read once at startup
+  pub fn from_env() -> Self {
+    let quotas = match std::env::var("RATE_LIMITS") {
+      Ok(value) if value.trim() == "off" => vec![],
+      Ok(value) => parse_quotas(&value).expect("RATE_LIMITS is invalid"),
+      Err(_) => parse_quotas(DEFAULT_RATE_LIMITS).unwrap(),
+    };
+
+    let store: Box<dyn RateLimitStore> = match std::env::var("RATE_LIMIT_STORE") {
+      Ok(url) if url != "memory" => Box::new(RedisStore::open(&url).expect("RATE_LIMIT_STORE is invalid")),
+      _ => Box::new(MemoryStore::default()),
+    };
+
+    RateLimiter {
+      trusted_proxies: client_ip::trusted_proxies(),
+      api_keys: match std::env::var("RATE_LIMIT_API_KEYS") {
+        Ok(value) => parse_api_keys(&value).expect("RATE_LIMIT_API_KEYS is invalid"),
+        Err(_) => HashMap::new(),
+      },
+      ..RateLimiter::new(quotas, store)
+    }
+  }
+
+  /// Quota of a route, its own or the one of every other route
+  pub fn quota_for(&self, method: &str, path: &str) -> Option<&RouteQuota> {
+    self.quotas
+      .iter()
+      .find(|quota| matches!(&quota.route, Some((m, p)) if m.eq_ignore_ascii_case(method) && p == path))
+      .or_else(|| self.quotas.iter().find(|quota| quota.route.is_none()))
+  }
+
+  /// Name of a configured API key, `None` for anything else
+  pub fn api_key_name(&self, api_key: &str) -> Option<&str> {
+    self.api_keys.get(api_key).map(|name| name.as_str())
+  }
+
+  pub async fn acquire(&self, key: &str, quota: Quota, now: i64) -> AppResult<Decision> {
+    self.store.acquire(key, quota, now).await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const QUOTA: Quota = Quota { limit: 3, period_secs: 3 };
+
+  /// Three requests per three seconds, from the same key
+  async fn check_store(store: &dyn RateLimitStore, key: &str) {
+    let now = 1_700_000_000_000;
+    for remaining in [2, 1, 0] {
+      let decision = store.acquire(key, QUOTA, now).await.unwrap();
+      assert!(decision.allowed);
+      assert_eq!(decision.remaining, remaining);
+    }
+
+    let refused = store.acquire(key, QUOTA, now).await.unwrap();
+    assert!(!refused.allowed);
+    assert_eq!(refused.remaining, 0);
+    assert_eq!(refused.retry_after_ms, 1000);
+    assert_eq!(refused.reset_ms, 3000);
+
+    // Other keys have their own bucket
+    assert!(store.acquire(&format!("{}-other", key), QUOTA, now).await.unwrap().allowed);
+
+    // One interval later exactly one more request goes through
+    assert!(store.acquire(key, QUOTA, now + 1000).await.unwrap().allowed);
+    assert!(!store.acquire(key, QUOTA, now + 1000).await.unwrap().allowed);
+
+    // A full period later the whole burst is back
+    let decision = store.acquire(key, QUOTA, now + 5000).await.unwrap();
+    assert!(decision.allowed);
+    assert_eq!(decision.remaining, 2);
+  }
+
+  #[tokio::test]
+  async fn memory_store_follows_the_quota() {
+    check_store(&MemoryStore::default(), "ip:127.0.0.1").await;
+  }
+
+  #[tokio::test]
+  async fn memory_store_stays_within_its_capacity() {
+    let store = MemoryStore::with_capacity(10);
+    let now = 1_700_000_000_000;
+    for i in 0..10 {
+      store.acquire(&format!("ip:10.0.0.{}", i), QUOTA, now + i).await.unwrap();
+    }
+    assert_eq!(store.tats().len(), 10);
+
+    // Nothing has expired, the bucket closest to full makes room
+    store.acquire("ip:10.0.0.10", QUOTA, now + 10).await.unwrap();
+    assert_eq!(store.tats().len(), 10);
+    assert!(!store.tats().contains_key("ip:10.0.0.0"));
+    assert!(store.tats().contains_key("ip:10.0.0.1"));
+
+    // Known keys never evict anything
+    store.acquire("ip:10.0.0.1", QUOTA, now + 11).await.unwrap();
+    assert_eq!(store.tats().len(), 10);
+
+    // Expired buckets go first
+    store.acquire("ip:10.0.0.11", QUOTA, now + 60_000).await.unwrap();
+    assert_eq!(store.tats().len(), 1);
+  }
+
+  /// Runs `GCRA_SCRIPT` the way the server does, values expire with their `PX`
+  #[derive(Default)]
+  struct ScriptedRedis {
+      values: Mutex<HashMap<String, (i64, i64)>>,
+  }
+
+  #[async_trait]
+  impl RedisBackend for ScriptedRedis {
+    async fn run_gcra(&self, key: &str, now: i64, interval_ms: i64, period_ms: i64) -> AppResult<(i64, i64)> {
+      let mut values = self.values.lock().unwrap();
+      let stored = values.get(key).filter(|(_, expires_at)| *expires_at > now).map(|(tat, _)| *tat);
+      let tat = stored.unwrap_or(now).max(now);
+      let new_tat = tat + interval_ms;
+      if now < new_tat - period_ms {
+        return Ok((0, tat))
+      }
+      // `PX new_tat - now`, the value expires at the TAT
+      values.insert(key.to_string(), (new_tat, new_tat));
+      Ok((1, new_tat))
+    }
+  }
+
+  #[tokio::test]
+  async fn redis_store_follows_the_quota() {
+    let store = RedisStore::new(ScriptedRedis::default());
+    check_store(&store, "ip:127.0.0.1").await;
+
+    // Buckets live under their own prefix
+    assert!(store.backend.values.lock().unwrap().keys().all(|key| key.starts_with("rate_limit:ip:127.0.0.1")));
+  }
+
+  /// Same steps against a Redis-compatible server in `TEST_REDIS_URL`, e.g.
+  /// `redis://127.0.0.1:6379`, run it with `--ignored`
+  #[tokio::test]
+  #[ignore = "needs a Redis-compatible server in TEST_REDIS_URL"]
+  async fn redis_server_follows_the_quota() {
+    let url = std::env::var("TEST_REDIS_URL").expect("TEST_REDIS_URL is not set");
+    let store = RedisStore::open(&url).unwrap();
+    let key = format!("test:{}", uuid::Uuid::new_v4());
+    check_store(&store, &key).await;
+  }
+
+  #[test]
+  fn quotas_are_parsed() {
+    let quotas = parse_quotas(DEFAULT_RATE_LIMITS).unwrap();
+    assert_eq!(quotas[0], RouteQuota {
+      route: Some(("POST".to_string(), "/login".to_string())),
+      quota: Quota { limit: 10, period_secs: 60 },
+      key: KeyKind::Ip,
+    });
+    assert_eq!(quotas[2].route, None);
+    assert_eq!(quotas[2].key, KeyKind::User);
+
+    assert_eq!(parse_quotas("get /users=5/1").unwrap()[0].key, KeyKind::Ip);
+    assert!(parse_quotas("POST /login=0/60").is_err());
+    assert!(parse_quotas("POST /login=10").is_err());
+    assert!(parse_quotas("/login=10/60").is_err());
+    assert!(parse_quotas("*=10/60:session").is_err());
+  }
+
+  #[test]
+  fn api_keys_are_parsed() {
+    let api_keys = parse_api_keys("partner=key-1; billing = key-2 ;").unwrap();
+    assert_eq!(api_keys.len(), 2);
+    assert_eq!(api_keys["key-1"], "partner");
+    assert_eq!(api_keys["key-2"], "billing");
+
+    assert!(parse_api_keys("").unwrap().is_empty());
+    assert!(parse_api_keys("partner").is_err());
+    assert!(parse_api_keys("partner=").is_err());
+    assert!(parse_api_keys("partner=key-1; billing=key-1").is_err());
+    // The key itself never shows up in the error
+    assert!(!parse_api_keys("secret-key").unwrap_err().contains("secret"));
+    assert!(!parse_api_keys("partner=secret-key; billing=secret-key").unwrap_err().contains("secret"));
+  }
+
+  #[test]
+  fn routes_without_a_quota_share_the_default() {
+    let limiter = RateLimiter::new(parse_quotas(DEFAULT_RATE_LIMITS).unwrap(), Box::new(MemoryStore::default()));
+    assert_eq!(limiter.quota_for("post", "/login").unwrap().quota.limit, 10);
+    assert_eq!(limiter.quota_for("GET", "/users/:user_id").unwrap().bucket_prefix(), "*");
+
+    let limiter = RateLimiter::new(parse_quotas("POST /login=10/60").unwrap(), Box::new(MemoryStore::default()));
+    assert!(limiter.quota_for("GET", "/users").is_none());
+  }
+}
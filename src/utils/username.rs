@@ -0,0 +1,271 @@
+use prisma_client_rust::{operator::or, QueryError};
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::{skeleton, MixedScript};
+use crate::db::{self, user};
+
+pub const NAME_MIN_LENGTH: usize = 3;
+pub const NAME_MAX_LENGTH: usize = 32;
+
+/// Names nobody may register, compared by skeleton so look-alikes are caught too
+const RESERVED_NAMES: &[&str] = &[
+  "admin",
+  "administrator",
+  "root",
+  "system",
+  "support",
+  "security",
+  "api",
+  "login",
+  "register",
+  "me",
+  "null",
+  "undefined",
+];
+
+/// Prefix of the names given to erased users
+const RESERVED_PREFIX: &str = "erased-";
+
+/// NFKC plus case folding, two names with the same canonical form are the same name
+pub fn canonicalize(name: &str) -> String {
+  let folded = caseless::default_case_fold_str(&name.trim().nfkc().collect::<String>());
+  folded.nfkc().collect()
+}
+
+/// Confusable skeleton (UTS #39) of a canonical name
+pub fn confusable_skeleton(canonical: &str) -> String {
+  skeleton(canonical).collect()
+}
+
+/// A validated user name with its forms stored on `User`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Username {
+    /// What the user typed, NFKC normalized, stored as `User.name`
+    pub display: String,
+    pub canonical: String,
+    pub skeleton: String,
+}
+
+impl Username {
+  /// Validate a requested name, the error is the reason shown to the client
+  pub fn parse(name: &str) -> Result<Username, String> {
+    let display: String = name.trim().nfkc().collect();
+    let canonical = canonicalize(&display);
+
+    let length = canonical.chars().count();
+    if !(NAME_MIN_LENGTH..=NAME_MAX_LENGTH).contains(&length) {
+      return Err(format!("must be {} to {} characters", NAME_MIN_LENGTH, NAME_MAX_LENGTH))
+    }
+    if !canonical.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.') {
+      return Err("may only contain letters, digits, '_', '-' and '.'".to_string())
+    }
+    if !canonical.chars().next().map_or(false, |c| c.is_alphanumeric()) {
+      return Err("must start with a letter or a digit".to_string())
+    }
+    if !canonical.as_str().is_single_script() {
+      return Err("must not mix scripts".to_string())
+    }
+
+    let skeleton = confusable_skeleton(&canonical);
+    let reserved = canonical.starts_with(RESERVED_PREFIX)
+        || RESERVED_NAMES.iter().any(|reserved| confusable_skeleton(reserved) == skeleton);
+    if reserved {
+      return Err("is reserved".to_string())
+    }
+
+    Ok(Username { display, canonical, skeleton })
+  }
+
+  /// Columns to set next to `User.name`
+  pub fn params(&self) -> Vec<user::SetParam> {
+    vec![
+      user::name_canonical::set(Some(self.canonical.clone())),
+      user::name_skeleton::set(Some(self.skeleton.clone())),
+    ]
+  }
+
+  /// Whether a name stored with these columns keeps this one from being used
+  pub fn conflict_with(&self, canonical: Option<&str>, skeleton: Option<&str>) -> Option<NameConflict> {
+    if canonical == Some(self.canonical.as_str()) {
+      Some(NameConflict::Taken)
+    } else if skeleton == Some(self.skeleton.as_str()) {
+      Some(NameConflict::Confusable)
+    } else {
+      None
+    }
+  }
+}
+
+/// Why a valid name can't be used
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NameConflict {
+    /// Same canonical name
+    Taken,
+    /// Different name that looks the same
+    Confusable,
+}
+
+/// Look up a user holding the name or a look-alike, `exclude_user_id` is the one renaming
+pub async fn find_conflict(
+  db: &db::PrismaClient,
+  username: &Username,
+  exclude_user_id: Option<&str>,
+) -> Result<Option<NameConflict>, QueryError> {
+  user::select!(user_name_select { name_canonical name_skeleton });
+
+  let mut filter = vec![or(vec![
+    user::name_canonical::equals(Some(username.canonical.clone())),
+    user::name_skeleton::equals(Some(username.skeleton.clone())),
+  ])];
+  if let Some(user_id) = exclude_user_id {
+    filter.push(user::id::not(user_id.to_string()))
+  }
+
+  let conflict = db
+      .user()
+      .find_first(filter)
+      .select(user_name_select::select())
+      .exec()
+      .await?
+      .and_then(|user_obj| {
+        username.conflict_with(user_obj.name_canonical.as_deref(), user_obj.name_skeleton.as_deref())
+      });
+
+  Ok(conflict)
+}
+
+/// Free names close to the requested one, checked in a single query
+pub async fn suggest(
+  db: &db::PrismaClient,
+  username: &Username,
+  limit: usize,
+) -> Result<Vec<String>, QueryError> {
+  user::select!(user_skeleton_select { name_skeleton });
+
+  let base: String = username.display.chars().take(NAME_MAX_LENGTH - 3).collect();
+  let candidates: Vec<Username> = ["1", "2", "3", "_", "01", "_1", "123", ".dev"]
+      .iter()
+      .filter_map(|suffix| Username::parse(&format!("{}{}", base, suffix)).ok())
+      .collect();
+  if candidates.is_empty() {
+    return Ok(vec![])
+  }
+
+  // Skeletons cover canonical names, equal canonical names have equal skeletons
+  let taken: Vec<String> = db
+      .user()
+      .find_many(vec![user::name_skeleton::in_vec(
+        candidates.iter().map(|candidate| candidate.skeleton.clone()).collect(),
+      )])
+      .select(user_skeleton_select::select())
+      .exec()
+      .await?
+      .into_iter()
+      .filter_map(|user_obj| user_obj.name_skeleton)
+      .collect();
+
+  let mut picked: Vec<Username> = vec![];
+  for candidate in candidates {
+    // Two suffixes can end up with the same skeleton
+    let duplicate = picked.iter().any(|username| username.skeleton == candidate.skeleton);
+    if !taken.contains(&candidate.skeleton) && !duplicate {
+      picked.push(candidate);
+    }
+  }
+
+  Ok(picked.into_iter().take(limit).map(|username| username.display).collect())
+}
+
+/// Fill the normalized columns of users created before they existed.
+/// Legacy names are kept as they are, even if they break today's rules
+pub async fn backfill_canonical_names(db: &db::PrismaClient) -> Result<(), QueryError> {
+  user::select!(user_backfill_select { id name });
+
+  let user_objs = db
+      .user()
+      .find_many(vec![
+        user::name_canonical::equals(None),
+        user::erased_at::equals(None),
+      ])
+      .select(user_backfill_select::select())
+      .exec()
+      .await?;
+
+  for user_obj in user_objs {
+    let canonical = canonicalize(&user_obj.name);
+    let skeleton = confusable_skeleton(&canonical);
+    let updated = db
+        .user()
+        .update(
+          user::id::equals(user_obj.id.clone()),
+          vec![
+            user::name_canonical::set(Some(canonical)),
+            user::name_skeleton::set(Some(skeleton)),
+          ],
+        )
+        .exec()
+        .await;
+
+    // Two legacy names that only differ by case stay unnormalized, login
+    // falls back to their exact name until an admin renames one of them
+    if let Err(e) = updated {
+      tracing::warn!("Could not normalize the name of user {}: {}", user_obj.id, e);
+    }
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn names_differing_by_case_are_the_same_name() {
+    let upper = Username::parse("Alice").unwrap();
+    let lower = Username::parse("alice").unwrap();
+
+    assert_eq!(upper.display, "Alice");
+    assert_eq!(upper.canonical, "alice");
+    assert_eq!(upper.canonical, lower.canonical);
+    assert_eq!(
+      lower.conflict_with(Some(upper.canonical.as_str()), Some(upper.skeleton.as_str())),
+      Some(NameConflict::Taken)
+    );
+  }
+
+  #[test]
+  fn look_alikes_conflict_without_being_the_same_name() {
+    // Cyrillic "а" in an otherwise Latin name
+    assert_eq!(Username::parse("\u{430}lice"), Err("must not mix scripts".to_string()));
+
+    // All Cyrillic, looks exactly like the Latin "ace"
+    let latin = Username::parse("ace").unwrap();
+    let cyrillic = Username::parse("\u{430}\u{441}\u{435}").unwrap();
+    assert_ne!(latin.canonical, cyrillic.canonical);
+    assert_eq!(latin.skeleton, cyrillic.skeleton);
+    assert_eq!(
+      cyrillic.conflict_with(Some(latin.canonical.as_str()), Some(latin.skeleton.as_str())),
+      Some(NameConflict::Confusable)
+    );
+
+    let other = Username::parse("bob").unwrap();
+    assert_eq!(cyrillic.conflict_with(Some(other.canonical.as_str()), Some(other.skeleton.as_str())), None);
+  }
+
+  #[test]
+  fn reserved_names_are_rejected() {
+    assert_eq!(Username::parse("Admin"), Err("is reserved".to_string()));
+    assert_eq!(Username::parse("ROOT"), Err("is reserved".to_string()));
+    assert_eq!(Username::parse("erased-1234"), Err("is reserved".to_string()));
+    assert!(Username::parse("administrators").is_ok());
+  }
+
+  #[test]
+  fn malformed_names_are_rejected() {
+    assert!(Username::parse("al").is_err());
+    assert!(Username::parse(&"a".repeat(NAME_MAX_LENGTH + 1)).is_err());
+    assert!(Username::parse("al ice").is_err());
+    assert!(Username::parse("_alice").is_err());
+    assert_eq!(Username::parse("  alice  ").unwrap().display, "alice");
+  }
+}
@@ -0,0 +1,117 @@
+use axum::{
+  async_trait,
+  body::HttpBody,
+  extract::FromRequest,
+  http::Request,
+  BoxError,
+};
+use serde::de::DeserializeOwned;
+use validator::{ValidationError, ValidationErrors, ValidationErrorsKind};
+use crate::error::AppError;
+use crate::utils::extract::Json;
+pub use rust_learn_client::types::FieldError;
+
+/// Rules of a request body. The bodies are shared with the client crate, which
+/// only knows their shape, so their rules are written out on the server
+pub trait Validate {
+  fn validate(&self) -> Result<(), ValidationErrors>;
+}
+
+/// Collects the rules a body broke, keyed by field like `validator` does
+#[derive(Default)]
+pub struct Rules(ValidationErrors);
+
+impl Rules {
+  /// Length in characters, bounds included
+  pub fn length(self, field: &'static str, value: &str, min: usize, max: usize) -> Self {
+    let length = value.chars().count();
+    let result = if (min..=max).contains(&length) {
+      Ok(())
+    } else {
+      Err(format!("must be {} to {} characters", min, max))
+    };
+    self.check(field, "length", result)
+  }
+
+  /// Outcome of a check shared with other code paths, the error is the message
+  pub fn check(mut self, field: &'static str, code: &'static str, result: Result<(), String>) -> Self {
+    if let Err(message) = result {
+      let mut error = ValidationError::new(code);
+      error.message = Some(message.into());
+      self.0.add(field, error);
+    }
+    self
+  }
+
+  pub fn finish(self) -> Result<(), ValidationErrors> {
+    if self.0.is_empty() {
+      Ok(())
+    } else {
+      Err(self.0)
+    }
+  }
+}
+
+/// Flatten the errors of a struct and of the structs nested in it
+pub fn field_errors(errors: &ValidationErrors) -> Vec<FieldError> {
+  let mut collected = vec![];
+  collect_field_errors(errors, "", &mut collected);
+  // HashMap order, sort so responses are stable
+  collected.sort_by(|a, b| a.field.cmp(&b.field).then_with(|| a.code.cmp(&b.code)));
+  collected
+}
+
+fn collect_field_errors(errors: &ValidationErrors, prefix: &str, collected: &mut Vec<FieldError>) {
+  for (field, kind) in errors.errors() {
+    let path = if prefix.is_empty() { field.to_string() } else { format!("{}.{}", prefix, field) };
+    match kind {
+      ValidationErrorsKind::Field(field_errors) => {
+        for error in field_errors {
+          collected.push(FieldError {
+            field: path.clone(),
+            code: error.code.to_string(),
+            message: error
+              .message
+              .as_ref()
+              .map(|message| message.to_string())
+              .unwrap_or_else(|| format!("is invalid ({})", error.code)),
+          })
+        }
+      }
+      ValidationErrorsKind::Struct(nested) => collect_field_errors(nested, &path, collected),
+      ValidationErrorsKind::List(items) => {
+        for (index, nested) in items {
+          collect_field_errors(nested, &format!("{}[{}]", path, index), collected)
+        }
+      }
+    }
+  }
+}
+
+impl From<ValidationErrors> for AppError {
+  fn from(errors: ValidationErrors) -> Self {
+    AppError::ValidationFailed(field_errors(&errors))
+  }
+}
+
+/// `Json<T>` that also runs the `Validate` rules of `T`, failures are answered with 422
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidatedJson<T>(pub T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for ValidatedJson<T>
+where
+  T: DeserializeOwned + Validate,
+  S: Send + Sync,
+  B: HttpBody + Send + 'static,
+  B::Data: Send,
+  B::Error: Into<BoxError>,
+{
+  type Rejection = AppError;
+
+  async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+    let Json(value) = Json::<T>::from_request(req, state).await?;
+    value.validate()?;
+    Ok(ValidatedJson(value))
+  }
+}